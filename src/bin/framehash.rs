@@ -0,0 +1,66 @@
+//! Headless per-frame checksums for decoding regression tests.
+//!
+//! Decodes every frame of the video stream of a file through the player's decoder and conversion path
+//! and prints a framemd5-style list of checksums of the displayed pixels, so two runs can be diffed.
+//!
+//! ```text
+//! framehash [--hash md5|crc32] [--output <file>] <file>
+//! ```
+use ffmpeg_next as ffmpeg;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use testffmpeg::checksum::{write_frame_checksums, HashKind};
+
+const USAGE: &str = "usage: framehash [--hash md5|crc32] [--output <file>] <file>";
+
+fn exit_with_usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut kind = HashKind::Md5;
+    let mut output_path = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--hash" => match args.next().as_deref().and_then(HashKind::from_name) {
+                Some(hash) => kind = hash,
+                None => exit_with_usage(),
+            },
+            "--output" => output_path = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let [path] = paths.as_slice() else {
+        exit_with_usage();
+    };
+    ffmpeg::util::log::set_level(ffmpeg::util::log::Level::Error);
+
+    let mut out: Box<dyn Write> = match output_path.as_ref() {
+        Some(output_path) => match File::create(output_path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("couldn't create {output_path}: {e}");
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+    let written = write_frame_checksums(path, kind, &mut out).and_then(|frames| {
+        out.flush()?;
+        Ok(frames)
+    });
+    match written {
+        Ok(frames) => eprintln!("{frames} frames"),
+        Err(e) => {
+            eprintln!("couldn't checksum {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Headless media validation.
+//!
+//! Opens each file the same way the player does and prints its streams, duration, chapters and
+//! metadata, either as JSON or as a human readable table. Exits with a non-zero code if any file
+//! can't be played.
+//!
+//! ```text
+//! probe [--json] [--timeout-ms <ms>] <file>...
+//! ```
+use ffmpeg_next as ffmpeg;
+use serde::Serialize;
+use std::time::Duration;
+use testffmpeg::media_info::{format_duration_ms, MediaInfo};
+use testffmpeg::player::{FFMpegPlayer, OpenLimits};
+
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+
+const USAGE: &str = "usage: probe [--json] [--timeout-ms <ms>] <file>...";
+
+#[derive(Serialize)]
+struct ProbeReport {
+    path: String,
+    playable: bool,
+    /// Why the file can't be played, if it can't.
+    error: Option<String>,
+    info: Option<MediaInfo>,
+}
+
+/// Construct a player for `path` the same way the app does, allowing each phase of opening it at most `timeout`.
+fn check_playable(path: &str, timeout: Duration) -> Result<(), String> {
+    let limits = OpenLimits {
+        open_timeout: timeout,
+        first_frame_timeout: timeout,
+        ..OpenLimits::default()
+    };
+    let ctx = egui::Context::default();
+    FFMpegPlayer::new_with_limits(&ctx, &path.to_string(), limits)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn probe(path: &str, timeout: Duration) -> ProbeReport {
+    let info = match MediaInfo::probe(path) {
+        Ok(info) => info,
+        Err(e) => {
+            return ProbeReport {
+                path: path.to_string(),
+                playable: false,
+                error: Some(e.to_string()),
+                info: None,
+            };
+        }
+    };
+    let error = check_playable(path, timeout).err();
+    ProbeReport {
+        path: path.to_string(),
+        playable: error.is_none(),
+        error,
+        info: Some(info),
+    }
+}
+
+fn print_table(report: &ProbeReport) {
+    match report.error.as_ref() {
+        Some(e) => println!("{}: NOT PLAYABLE ({e})", report.path),
+        None => println!("{}: playable", report.path),
+    }
+    let Some(info) = report.info.as_ref() else {
+        return;
+    };
+    for (label, value) in info.rows() {
+        println!("  {label:<12} {value}");
+    }
+    if !info.streams.is_empty() {
+        println!("  streams");
+        for stream in &info.streams {
+            println!("    {}", stream.title());
+            for (label, value) in stream.rows() {
+                println!("      {label:<20} {value}");
+            }
+        }
+    }
+    if !info.chapters.is_empty() {
+        println!("  chapters");
+        for chapter in &info.chapters {
+            println!(
+                "    {:<4} {} - {}  {}",
+                chapter.id,
+                format_duration_ms(Some(chapter.start_ms)),
+                format_duration_ms(Some(chapter.end_ms)),
+                chapter.title.as_deref().unwrap_or("")
+            );
+        }
+    }
+    if !info.tags.is_empty() {
+        println!("  tags");
+        for (key, value) in &info.tags {
+            println!("    {key}: {value}");
+        }
+    }
+}
+
+fn main() {
+    let mut json = false;
+    let mut timeout = Duration::from_millis(DEFAULT_TIMEOUT_MS);
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--timeout-ms" => match args.next().and_then(|ms| ms.parse().ok()) {
+                Some(ms) => timeout = Duration::from_millis(ms),
+                None => {
+                    eprintln!("{USAGE}");
+                    std::process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("{USAGE}");
+        std::process::exit(2);
+    }
+    ffmpeg::util::log::set_level(ffmpeg::util::log::Level::Error);
+
+    let reports: Vec<ProbeReport> = paths.iter().map(|path| probe(path, timeout)).collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+    } else {
+        for report in &reports {
+            print_table(report);
+        }
+    }
+    let all_playable = reports.iter().all(|r| r.playable);
+    std::process::exit(if all_playable { 0 } else { 1 });
+}
@@ -0,0 +1,86 @@
+//! Headless objective quality measurement.
+//!
+//! Decodes a reference and a distorted file the same way the player does, pairs their frames by
+//! presentation time and prints the aggregate PSNR (Y/U/V) and SSIM. The per-frame metrics can be
+//! written as CSV and the whole report as JSON, `-` writing to stdout instead of a file.
+//!
+//! ```text
+//! quality [--csv <file>] [--json <file>] <reference> <distorted>
+//! ```
+use ffmpeg_next as ffmpeg;
+use testffmpeg::quality::compare_files;
+
+const USAGE: &str = "usage: quality [--csv <file>] [--json <file>] <reference> <distorted>";
+
+fn exit_with_usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
+
+/// Write `contents` to `path`, or to stdout if it is `-`.
+fn write_output(path: &str, contents: &str) -> std::io::Result<()> {
+    if path == "-" {
+        print!("{contents}");
+        Ok(())
+    } else {
+        std::fs::write(path, contents)
+    }
+}
+
+fn main() {
+    let mut csv_path = None;
+    let mut json_path = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--csv" => csv_path = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "--json" => json_path = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let [reference, distorted] = paths.as_slice() else {
+        exit_with_usage();
+    };
+    ffmpeg::util::log::set_level(ffmpeg::util::log::Level::Error);
+
+    let report = match compare_files(reference, distorted) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("couldn't compare {reference} and {distorted}: {e}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(path) = csv_path.as_ref() {
+        if let Err(e) = write_output(path, &report.to_csv()) {
+            eprintln!("couldn't write {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = json_path.as_ref() {
+        let written = report
+            .to_json()
+            .and_then(|json| Ok(write_output(path, &json)?));
+        if let Err(e) = written {
+            eprintln!("couldn't write {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+    // keep stdout machine readable when a report goes there
+    if csv_path.as_deref() == Some("-") || json_path.as_deref() == Some("-") {
+        eprintln!("{}", report.summary());
+    } else {
+        println!("{}", report.summary());
+    }
+    if report.unmatched_reference_frames > 0 || report.unmatched_distorted_frames > 0 {
+        eprintln!(
+            "{} reference and {} distorted frames had no counterpart",
+            report.unmatched_reference_frames, report.unmatched_distorted_frames
+        );
+    }
+    std::process::exit(if report.aggregate.is_some() { 0 } else { 1 });
+}
@@ -0,0 +1,146 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::frame::Video;
+use egui::ColorImage;
+use anyhow::Result;
+use md5::{Digest, Md5};
+use std::io::Write;
+use crate::player::{is_ffmpeg_eof_error, VideoStreamer};
+
+/// The hash computed over each frame by [`write_frame_checksums`].
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HashKind {
+    Md5,
+    Crc32,
+}
+
+impl HashKind {
+    pub const ALL: [HashKind; 2] = [HashKind::Md5, HashKind::Crc32];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashKind::Md5 => "MD5",
+            HashKind::Crc32 => "CRC32",
+        }
+    }
+
+    /// The hash named `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+
+    fn hash(&self, data: &[u8]) -> String {
+        match self {
+            HashKind::Md5 => Md5::digest(data).iter().map(|byte| format!("{byte:02x}")).collect(),
+            HashKind::Crc32 => format!("0x{:08x}", crc32fast::hash(data)),
+        }
+    }
+}
+
+/// The checksum of one displayed frame.
+#[derive(Clone, Debug)]
+pub struct FrameChecksum {
+    /// The index of the video stream in the input.
+    pub stream_index: usize,
+    /// In the time base of the stream, `None` if the frame has no timestamp.
+    pub pts: Option<i64>,
+    /// In the time base of the stream, 0 if unknown.
+    pub duration: i64,
+    /// The number of hashed bytes.
+    pub size: usize,
+    pub hash: String,
+}
+
+impl FrameChecksum {
+    /// The line for this frame in a framemd5-style list.
+    pub fn line(&self) -> String {
+        let pts = self.pts.map(|pts| pts.to_string()).unwrap_or_else(|| String::from("N/A"));
+        format!("{},{:>12},{:>9},{:>9}, {}", self.stream_index, pts, self.duration, self.size, self.hash)
+    }
+}
+
+/// The packed RGB24 bytes of a displayed frame, which is what gets hashed.
+fn packed_rgb(image: &ColorImage) -> Vec<u8> {
+    image
+        .pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [r, g, b, _] = pixel.to_array();
+            [r, g, b]
+        })
+        .collect()
+}
+
+fn frame_checksum(stream_index: usize, image: &ColorImage, source: &Video, kind: HashKind) -> FrameChecksum {
+    let rgb = packed_rgb(image);
+    FrameChecksum {
+        stream_index,
+        pts: source.pts(),
+        duration: unsafe { (*source.as_ptr()).duration },
+        size: rgb.len(),
+        hash: kind.hash(&rgb),
+    }
+}
+
+/// Decode every frame of the video stream of `input_path` with the player's decoder and conversion path, as
+/// it would be displayed without picture adjustments, and write a list of their checksums to `out`: a
+/// commented header, then `stream, pts, duration, size, hash` per frame. This only resembles ffmpeg's
+/// framemd5 output: the frames are hashed as displayed (packed RGB24) and listed in presentation order,
+/// so there is no codec or dts column. Returns the number of frames.
+pub fn write_frame_checksums(input_path: &str, kind: HashKind, out: &mut impl Write) -> Result<usize> {
+    let mut streamer = VideoStreamer::open(input_path)?;
+    let stream_index = streamer.video_stream_index();
+    let time_base = streamer.time_base();
+    let decoder = streamer.video_decoder();
+    writeln!(out, "#format: frame checksums")?;
+    writeln!(out, "#version: 2")?;
+    writeln!(out, "#hash: {}", kind.name())?;
+    writeln!(out, "#tb {stream_index}: {}/{}", time_base.numerator(), time_base.denominator())?;
+    writeln!(out, "#media_type {stream_index}: video")?;
+    writeln!(out, "#dimensions {stream_index}: {}x{}", decoder.width(), decoder.height())?;
+    writeln!(out, "#pixel_format {stream_index}: rgb24")?;
+    writeln!(out, "#stream#,        pts, duration,     size, hash")?;
+    let mut frames = 0;
+    loop {
+        let (image, source) = match streamer.recieve_next_displayed_frame() {
+            Ok(frame) => frame,
+            Err(e) if is_ffmpeg_eof_error(&e) => break,
+            Err(e) => return Err(e),
+        };
+        writeln!(out, "{}", frame_checksum(stream_index, &image, &source, kind).line())?;
+        frames += 1;
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(HashKind::Md5.hash(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(HashKind::Md5.hash(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(HashKind::Crc32.hash(b"123456789"), "0xcbf43926");
+    }
+
+    #[test]
+    fn hash_names() {
+        assert_eq!(HashKind::from_name("md5"), Some(HashKind::Md5));
+        assert_eq!(HashKind::from_name("CRC32"), Some(HashKind::Crc32));
+        assert_eq!(HashKind::from_name("sha1"), None);
+    }
+
+    #[test]
+    fn line_format() {
+        let mut checksum = FrameChecksum {
+            stream_index: 0,
+            pts: Some(1001),
+            duration: 1001,
+            size: 6220800,
+            hash: String::from("d41d8cd98f00b204e9800998ecf8427e"),
+        };
+        assert_eq!(checksum.line(), "0,        1001,     1001,  6220800, d41d8cd98f00b204e9800998ecf8427e");
+        checksum.pts = None;
+        assert_eq!(checksum.line(), "0,         N/A,     1001,  6220800, d41d8cd98f00b204e9800998ecf8427e");
+    }
+}
@@ -0,0 +1,412 @@
+use egui::load::SizedTexture;
+use egui::{
+    Align2, Color32, ColorImage, CursorIcon, FontId, Image, Pos2, Rect, Response, Sense, Stroke, TextureHandle,
+    TextureOptions, Ui, Vec2,
+};
+use std::collections::BTreeMap;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::player::{FFMpegPlayer, PlayerState};
+use crate::quality::{FrameQuality, IDENTICAL_PSNR_DB};
+
+/// How much differences are amplified in [`CompareMode::Difference`].
+const DIFFERENCE_GAIN: u16 = 4;
+/// How long each video is shown in [`CompareMode::Flicker`].
+const FLICKER_INTERVAL: Duration = Duration::from_millis(500);
+/// The PSNR range the quality graph spans, in dB.
+const GRAPH_PSNR_RANGE: (f64, f64) = (20., 60.);
+
+/// How the two videos of a [`Comparison`] are shown.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CompareMode {
+    /// Next to each other.
+    SideBySide,
+    /// In the same place, the reference left of a draggable split and the distorted video right of it.
+    Wipe,
+    /// The amplified absolute difference between the two.
+    Difference,
+    /// In the same place, switching between the two.
+    Flicker,
+}
+
+impl CompareMode {
+    pub const ALL: [CompareMode; 4] = [
+        CompareMode::SideBySide,
+        CompareMode::Wipe,
+        CompareMode::Difference,
+        CompareMode::Flicker,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompareMode::SideBySide => "side by side",
+            CompareMode::Wipe => "wipe",
+            CompareMode::Difference => "difference",
+            CompareMode::Flicker => "flicker",
+        }
+    }
+}
+
+/// A pair of displayed frames, and the reference position they were shown at.
+type FramePair = (i64, Arc<ColorImage>, Arc<ColorImage>);
+
+/// Two players locked to the clock of the first, e.g. an original and an encode of it, shown together.
+/// Playback, seeking and stepping apply to both.
+pub struct Comparison {
+    pub reference: FFMpegPlayer,
+    pub distorted: FFMpegPlayer,
+    pub mode: CompareMode,
+    /// Where the wipe splits the frames, from 0 (all distorted) to 1 (all reference).
+    pub split: f32,
+    /// Compute the PSNR and SSIM between the displayed frames.
+    pub measure_quality: bool,
+    quality: BTreeMap<i64, FrameQuality>,
+    request_sender: Sender<FramePair>,
+    result_reciever: Receiver<(i64, FrameQuality)>,
+    pending: bool,
+    measured: Option<(Arc<ColorImage>, Arc<ColorImage>)>,
+    difference_texture: Option<TextureHandle>,
+    difference_of: Option<(Arc<ColorImage>, Arc<ColorImage>)>,
+    flicker_started: Instant,
+}
+
+impl Comparison {
+    /// Compare `distorted` against `reference`, showing `distorted`'s frames by `reference`'s clock.
+    pub fn new(reference: FFMpegPlayer, mut distorted: FFMpegPlayer) -> Self {
+        distorted.follow_clock(reference.clock());
+        let (request_sender, request_reciever) = std::sync::mpsc::channel::<FramePair>();
+        let (result_sender, result_reciever) = std::sync::mpsc::channel();
+        // exits once the comparison, and with it the sender, is dropped
+        std::thread::spawn(move || {
+            while let Ok(mut request) = request_reciever.recv() {
+                // only the newest pair matters
+                while let Ok(newer) = request_reciever.try_recv() {
+                    request = newer;
+                }
+                let (position_ms, reference, distorted) = request;
+                let distorted = resized(&distorted, reference.size);
+                let quality = FrameQuality::of_images(&reference, &distorted);
+                if result_sender.send((position_ms, quality)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            reference,
+            distorted,
+            mode: CompareMode::SideBySide,
+            split: 0.5,
+            measure_quality: false,
+            quality: BTreeMap::new(),
+            request_sender,
+            result_reciever,
+            pending: false,
+            measured: None,
+            difference_texture: None,
+            difference_of: None,
+            flicker_started: Instant::now(),
+        }
+    }
+
+    /// Start both from the beginning.
+    pub fn start(&mut self) {
+        self.reference.start();
+        self.distorted.start();
+    }
+
+    /// Pause both if playing, otherwise play both.
+    pub fn toggle_playback(&mut self) {
+        match self.reference.player_state.get() {
+            PlayerState::Playing => {
+                self.reference.pause();
+                self.distorted.pause();
+            }
+            PlayerState::Paused => {
+                self.reference.resume();
+                self.distorted.resume();
+            }
+            PlayerState::Stopped | PlayerState::EndOfFile => self.start(),
+            _ => (),
+        }
+    }
+
+    /// Seek both to `seek_frac` of the reference's duration. The distorted file goes to the same time rather
+    /// than the same fraction, since the durations of the two can differ.
+    pub fn seek(&mut self, seek_frac: f32) {
+        self.reference.seek(seek_frac);
+        let target_ms = seek_frac as f64 * self.reference.duration_ms as f64;
+        let distorted_frac = (target_ms / self.distorted.duration_ms.max(1) as f64).clamp(0., 1.);
+        self.distorted.seek(distorted_frac as f32);
+    }
+
+    /// Show the next frame of both and stay paused.
+    pub fn step_frame(&mut self) {
+        self.reference.step_frame();
+        self.distorted.step_frame();
+    }
+
+    /// The PSNR and SSIM measured so far, by position in milliseconds.
+    pub fn quality(&self) -> &BTreeMap<i64, FrameQuality> {
+        &self.quality
+    }
+
+    /// The average PSNR (with identical frames counted as [`IDENTICAL_PSNR_DB`]) and SSIM measured so far.
+    pub fn mean_quality(&self) -> Option<FrameQuality> {
+        if self.quality.is_empty() {
+            return None;
+        }
+        let count = self.quality.len() as f64;
+        Some(FrameQuality {
+            psnr: self.quality.values().map(|q| q.psnr.min(IDENTICAL_PSNR_DB)).sum::<f64>() / count,
+            ssim: self.quality.values().map(|q| q.ssim).sum::<f64>() / count,
+        })
+    }
+
+    /// Forget the measured quality, e.g. after changing the videos' filters.
+    pub fn clear_quality(&mut self) {
+        self.quality.clear();
+        self.measured = None;
+    }
+
+    /// Draw both videos in `size` as set by [`Comparison::mode`], and process state changes. Clicking pauses
+    /// or plays both.
+    pub fn ui(&mut self, ui: &mut Ui, size: Vec2) -> Response {
+        let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+        let response = match self.mode {
+            CompareMode::SideBySide => self.show_side_by_side(ui, rect),
+            CompareMode::Wipe => self.show_wipe(ui, rect),
+            CompareMode::Difference => self.show_difference(ui, rect),
+            CompareMode::Flicker => self.show_flicker(ui, rect),
+        };
+        if response.clicked() {
+            self.toggle_playback();
+        }
+        self.measure();
+        self.reference.process_state();
+        self.distorted.process_state();
+        response
+    }
+
+    fn show_side_by_side(&mut self, ui: &mut Ui, rect: Rect) -> Response {
+        let (left, right) = rect.split_left_right_at_fraction(0.5);
+        let reference_response = self.reference.render_frame_at(ui, left);
+        let distorted_response = self.distorted.render_frame_at(ui, right);
+        // zooming and panning either zooms and pans both
+        if distorted_response.hovered() {
+            self.reference.viewport = self.distorted.viewport.clone();
+        } else {
+            self.distorted.viewport = self.reference.viewport.clone();
+        }
+        label(ui, left, Align2::LEFT_TOP, "reference");
+        label(ui, right, Align2::LEFT_TOP, "distorted");
+        reference_response | distorted_response
+    }
+
+    fn show_wipe(&mut self, ui: &mut Ui, rect: Rect) -> Response {
+        let response = self.reference.render_frame_at(ui, rect);
+        self.distorted.viewport = self.reference.viewport.clone();
+        let split_x = rect.left() + rect.width() * self.split;
+        let right = Rect::from_min_max(Pos2::new(split_x, rect.top()), rect.max);
+        let layout = self.distorted.frame_layout(rect).clipped(right);
+        if layout.visible.is_positive() {
+            self.distorted.generate_frame_image(&layout).paint_at(ui, layout.visible);
+        }
+        let handle_rect = Rect::from_center_size(Pos2::new(split_x, rect.center().y), Vec2::new(12., rect.height()));
+        let handle = ui
+            .interact(handle_rect, response.id.with("wipe_handle"), Sense::drag())
+            .on_hover_cursor(CursorIcon::ResizeHorizontal);
+        if let Some(pointer) = handle.interact_pointer_pos().filter(|_| handle.dragged()) {
+            self.split = ((pointer.x - rect.left()) / rect.width()).clamp(0., 1.);
+        }
+        ui.painter().line_segment(
+            [Pos2::new(split_x, rect.top()), Pos2::new(split_x, rect.bottom())],
+            Stroke::new(if handle.hovered() || handle.dragged() { 2. } else { 1. }, Color32::WHITE),
+        );
+        label(ui, rect, Align2::LEFT_TOP, "reference");
+        label(ui, rect, Align2::RIGHT_TOP, "distorted");
+        response
+    }
+
+    fn show_difference(&mut self, ui: &mut Ui, rect: Rect) -> Response {
+        // drawn for its interaction and as a fallback until both videos have a frame
+        let response = self.reference.render_frame_at(ui, rect);
+        if let (Some(reference), Some(distorted)) = (self.reference.displayed_frame(), self.distorted.displayed_frame()) {
+            let is_current = self
+                .difference_of
+                .as_ref()
+                .is_some_and(|(a, b)| Arc::ptr_eq(a, &reference) && Arc::ptr_eq(b, &distorted));
+            if !is_current {
+                let image = difference(&reference, &resized(&distorted, reference.size));
+                match self.difference_texture.as_mut() {
+                    Some(texture) => texture.set(image, TextureOptions::default()),
+                    None => {
+                        self.difference_texture =
+                            Some(ui.ctx().load_texture("difference", image, TextureOptions::default()))
+                    }
+                }
+                self.difference_of = Some((reference, distorted));
+            }
+        }
+        if let Some(texture) = self.difference_texture.as_ref() {
+            let layout = self.reference.frame_layout(rect);
+            if layout.visible.is_positive() {
+                Image::new(SizedTexture::new(texture.id(), layout.visible.size()))
+                    .uv(layout.uv)
+                    .paint_at(ui, layout.visible);
+            }
+        }
+        label(ui, rect, Align2::LEFT_TOP, &format!("difference ×{DIFFERENCE_GAIN}"));
+        response
+    }
+
+    fn show_flicker(&mut self, ui: &mut Ui, rect: Rect) -> Response {
+        let response = self.reference.render_frame_at(ui, rect);
+        self.distorted.viewport = self.reference.viewport.clone();
+        let elapsed = self.flicker_started.elapsed().as_millis();
+        let interval = FLICKER_INTERVAL.as_millis();
+        let show_distorted = (elapsed / interval) % 2 == 1;
+        if show_distorted {
+            let layout = self.distorted.frame_layout(rect);
+            if layout.visible.is_positive() {
+                self.distorted.generate_frame_image(&layout).paint_at(ui, layout.visible);
+            }
+        }
+        label(ui, rect, Align2::LEFT_TOP, if show_distorted { "distorted" } else { "reference" });
+        ui.ctx().request_repaint_after(Duration::from_millis((interval - elapsed % interval) as u64));
+        response
+    }
+
+    /// Pick up finished measurements, and measure the displayed frames if they changed.
+    fn measure(&mut self) {
+        while let Ok((position_ms, quality)) = self.result_reciever.try_recv() {
+            self.pending = false;
+            self.quality.insert(position_ms, quality);
+        }
+        if !self.measure_quality || self.pending {
+            return;
+        }
+        let (Some(reference), Some(distorted)) = (self.reference.displayed_frame(), self.distorted.displayed_frame()) else {
+            return;
+        };
+        let is_measured = self
+            .measured
+            .as_ref()
+            .is_some_and(|(a, b)| Arc::ptr_eq(a, &reference) && Arc::ptr_eq(b, &distorted));
+        if is_measured {
+            return;
+        }
+        let position_ms = self.reference.elapsed_ms();
+        if self.request_sender.send((position_ms, reference.clone(), distorted.clone())).is_ok() {
+            self.pending = true;
+            self.measured = Some((reference, distorted));
+        }
+    }
+
+    /// Graph the PSNR and SSIM measured so far over the reference's duration, `width` wide.
+    pub fn show_quality_graph(&self, ui: &mut Ui, width: f32) {
+        let (response, painter) = ui.allocate_painter(Vec2::new(width, 160.), Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 0., Color32::from_gray(16));
+        let (psnr_rect, ssim_rect) = rect.split_top_bottom_at_fraction(0.5);
+        let duration_ms = self.reference.duration_ms.max(1) as f32;
+        let x_of = |position_ms: i64| rect.left() + rect.width() * (position_ms as f32 / duration_ms).clamp(0., 1.);
+        let graticule = Stroke::new(1., Color32::from_white_alpha(40));
+        painter.line_segment([psnr_rect.left_bottom(), psnr_rect.right_bottom()], graticule);
+
+        let (min_psnr, max_psnr) = GRAPH_PSNR_RANGE;
+        let psnr_points: Vec<Pos2> = self
+            .quality
+            .iter()
+            .map(|(position_ms, quality)| {
+                let t = ((quality.psnr.min(IDENTICAL_PSNR_DB) - min_psnr) / (max_psnr - min_psnr)).clamp(0., 1.);
+                Pos2::new(x_of(*position_ms), psnr_rect.bottom() - psnr_rect.height() * t as f32)
+            })
+            .collect();
+        // SSIM of encodes worth comparing sits close to 1, so the graph spans from the lowest measured value
+        let min_ssim = self.quality.values().map(|q| q.ssim).fold(0.9, f64::min);
+        let ssim_points: Vec<Pos2> = self
+            .quality
+            .iter()
+            .map(|(position_ms, quality)| {
+                let t = ((quality.ssim - min_ssim) / (1. - min_ssim).max(f64::EPSILON)).clamp(0., 1.);
+                Pos2::new(x_of(*position_ms), ssim_rect.bottom() - ssim_rect.height() * t as f32)
+            })
+            .collect();
+        painter.line(psnr_points, Stroke::new(1., Color32::LIGHT_GREEN));
+        painter.line(ssim_points, Stroke::new(1., Color32::LIGHT_BLUE));
+
+        let position_x = x_of(self.reference.elapsed_ms());
+        painter.line_segment(
+            [Pos2::new(position_x, rect.top()), Pos2::new(position_x, rect.bottom())],
+            Stroke::new(1., Color32::from_white_alpha(120)),
+        );
+        let font = FontId::monospace(11.);
+        painter.text(
+            psnr_rect.left_top() + Vec2::splat(4.),
+            Align2::LEFT_TOP,
+            format!("PSNR {min_psnr:.0}–{max_psnr:.0} dB"),
+            font.clone(),
+            Color32::LIGHT_GREEN,
+        );
+        painter.text(
+            ssim_rect.left_top() + Vec2::splat(4.),
+            Align2::LEFT_TOP,
+            format!("SSIM {min_ssim:.3}–1"),
+            font,
+            Color32::LIGHT_BLUE,
+        );
+    }
+}
+
+/// Draw `text` in the `align` corner of `rect`.
+fn label(ui: &Ui, rect: Rect, align: Align2, text: &str) {
+    let pos = align.pos_in_rect(&rect.shrink(8.));
+    let painter = ui.painter();
+    let galley = painter.layout_no_wrap(text.to_string(), FontId::proportional(14.), Color32::WHITE);
+    let text_rect = align.anchor_size(pos, galley.size());
+    painter.rect_filled(text_rect.expand(3.), 3., Color32::from_black_alpha(160));
+    painter.galley(text_rect.min, galley, Color32::WHITE);
+}
+
+/// `image` scaled to `size` by nearest neighbour, or a copy if it already has that size.
+fn resized(image: &ColorImage, size: [usize; 2]) -> ColorImage {
+    if image.size == size {
+        return image.clone();
+    }
+    let [width, height] = size;
+    let [source_width, source_height] = image.size;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let source_y = (y * source_height / height.max(1)).min(source_height.saturating_sub(1));
+        for x in 0..width {
+            let source_x = (x * source_width / width.max(1)).min(source_width.saturating_sub(1));
+            pixels.push(image.pixels[source_y * source_width + source_x]);
+        }
+    }
+    ColorImage {
+        size,
+        source_size: Vec2::new(width as f32, height as f32),
+        pixels,
+    }
+}
+
+/// The amplified absolute difference of each channel of two images of the same size.
+fn difference(a: &ColorImage, b: &ColorImage) -> ColorImage {
+    let pixels = a
+        .pixels
+        .iter()
+        .zip(&b.pixels)
+        .map(|(a, b)| {
+            let [ar, ag, ab, _] = a.to_array();
+            let [br, bg, bb, _] = b.to_array();
+            let amplify = |x: u8, y: u8| (x.abs_diff(y) as u16 * DIFFERENCE_GAIN).min(255) as u8;
+            Color32::from_rgb(amplify(ar, br), amplify(ag, bg), amplify(ab, bb))
+        })
+        .collect();
+    ColorImage {
+        size: a.size,
+        source_size: a.source_size,
+        pixels,
+    }
+}
@@ -0,0 +1,89 @@
+use ffmpeg_next::frame::Video;
+use bytemuck::NoUninit;
+
+/// How interlaced video is turned into progressive frames for display.
+#[derive(PartialEq, Clone, Copy, Debug, NoUninit)]
+#[repr(u8)]
+pub enum DeinterlaceMode {
+    /// Show frames as they are decoded, combing and all.
+    Off,
+    /// Deinterlace only frames flagged as interlaced, at the frame rate.
+    Auto,
+    /// Show every field as a frame of its own (interpolating the missing lines), at twice the frame rate.
+    Bob,
+    /// Motion adaptive deinterlacing at the frame rate.
+    Yadif,
+    /// Motion adaptive deinterlacing with better edges, at twice the frame rate.
+    Bwdif,
+}
+
+impl DeinterlaceMode {
+    pub const ALL: [DeinterlaceMode; 5] = [
+        DeinterlaceMode::Off,
+        DeinterlaceMode::Auto,
+        DeinterlaceMode::Bob,
+        DeinterlaceMode::Yadif,
+        DeinterlaceMode::Bwdif,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeinterlaceMode::Off => "off",
+            DeinterlaceMode::Auto => "auto",
+            DeinterlaceMode::Bob => "bob",
+            DeinterlaceMode::Yadif => "yadif",
+            DeinterlaceMode::Bwdif => "bwdif",
+        }
+    }
+
+    /// The libavfilter graph implementing the mode. The field order comes from each frame's flags.
+    pub fn filter_spec(&self) -> Option<&'static str> {
+        match self {
+            DeinterlaceMode::Off => None,
+            DeinterlaceMode::Auto => Some("yadif=mode=send_frame:parity=auto:deint=interlaced"),
+            DeinterlaceMode::Bob => Some("yadif=mode=send_field_nospatial:parity=auto:deint=all"),
+            DeinterlaceMode::Yadif => Some("yadif=mode=send_frame:parity=auto:deint=all"),
+            DeinterlaceMode::Bwdif => Some("bwdif=mode=send_field:parity=auto:deint=all"),
+        }
+    }
+
+    /// How many frames are shown per decoded frame.
+    pub fn frames_per_frame(&self) -> u8 {
+        match self {
+            DeinterlaceMode::Bob | DeinterlaceMode::Bwdif => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// How the lines of a frame were captured, according to its flags.
+#[derive(PartialEq, Clone, Copy, Debug, NoUninit)]
+#[repr(u8)]
+pub enum ScanType {
+    /// No frame has been decoded yet.
+    Unknown,
+    Progressive,
+    /// Interlaced, with the top field first.
+    InterlacedTff,
+    /// Interlaced, with the bottom field first.
+    InterlacedBff,
+}
+
+impl ScanType {
+    pub fn of(frame: &Video) -> Self {
+        match (frame.is_interlaced(), frame.is_top_first()) {
+            (false, _) => ScanType::Progressive,
+            (true, true) => ScanType::InterlacedTff,
+            (true, false) => ScanType::InterlacedBff,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScanType::Unknown => "-",
+            ScanType::Progressive => "progressive",
+            ScanType::InterlacedTff => "interlaced (top field first)",
+            ScanType::InterlacedBff => "interlaced (bottom field first)",
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::software::resampling;
+use ffmpeg::{ffi, format, ChannelLayout};
+use bytemuck::NoUninit;
+
+/// How surround audio is folded down when the output device has fewer channels than the audio.
+#[derive(PartialEq, Clone, Copy, Debug, NoUninit)]
+#[repr(u8)]
+pub enum DownmixMatrix {
+    /// ITU-R BS.775: the center and surrounds at -3 dB, the LFE dropped.
+    Itu,
+    /// Surrounds mixed out of phase so that a Dolby Pro Logic II decoder can recover them.
+    ProLogicII,
+    /// The center (where dialog usually is) at full level over quieter fronts and surrounds.
+    CenterBoost,
+}
+
+impl DownmixMatrix {
+    pub const ALL: [DownmixMatrix; 3] = [DownmixMatrix::Itu, DownmixMatrix::ProLogicII, DownmixMatrix::CenterBoost];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DownmixMatrix::Itu => "ITU",
+            DownmixMatrix::ProLogicII => "Pro Logic II",
+            DownmixMatrix::CenterBoost => "center boost",
+        }
+    }
+
+    /// The weights of `channel` in the left and right output.
+    fn stereo_weights(&self, channel: u64) -> (f64, f64) {
+        const MINUS_3DB: f64 = std::f64::consts::FRAC_1_SQRT_2;
+        let is = |mask: u64| channel & mask != 0;
+        let left_surround = ffi::AV_CH_SIDE_LEFT | ffi::AV_CH_BACK_LEFT;
+        let right_surround = ffi::AV_CH_SIDE_RIGHT | ffi::AV_CH_BACK_RIGHT;
+        match self {
+            DownmixMatrix::Itu => match () {
+                _ if is(ffi::AV_CH_FRONT_LEFT) => (1., 0.),
+                _ if is(ffi::AV_CH_FRONT_RIGHT) => (0., 1.),
+                _ if is(ffi::AV_CH_FRONT_CENTER | ffi::AV_CH_BACK_CENTER) => (MINUS_3DB, MINUS_3DB),
+                _ if is(left_surround) => (MINUS_3DB, 0.),
+                _ if is(right_surround) => (0., MINUS_3DB),
+                _ => (0., 0.),
+            },
+            DownmixMatrix::ProLogicII => match () {
+                _ if is(ffi::AV_CH_FRONT_LEFT) => (1., 0.),
+                _ if is(ffi::AV_CH_FRONT_RIGHT) => (0., 1.),
+                _ if is(ffi::AV_CH_FRONT_CENTER) => (MINUS_3DB, MINUS_3DB),
+                _ if is(ffi::AV_CH_BACK_CENTER) => (-MINUS_3DB, MINUS_3DB),
+                _ if is(left_surround) => (-0.8718, 0.4899),
+                _ if is(right_surround) => (-0.4899, 0.8718),
+                _ => (0., 0.),
+            },
+            DownmixMatrix::CenterBoost => match () {
+                _ if is(ffi::AV_CH_FRONT_LEFT) => (0.6, 0.),
+                _ if is(ffi::AV_CH_FRONT_RIGHT) => (0., 0.6),
+                _ if is(ffi::AV_CH_FRONT_CENTER) => (1., 1.),
+                _ if is(ffi::AV_CH_BACK_CENTER) => (0.35, 0.35),
+                _ if is(left_surround) => (0.5, 0.),
+                _ if is(right_surround) => (0., 0.5),
+                _ => (0., 0.),
+            },
+        }
+    }
+
+    /// The remix matrix from `source` to `output_channels` (1 or 2) channels, in the layout
+    /// `swr_set_matrix` expects: the weight of input `i` in output `o` at `i + o * source channels`.
+    /// `None` if `source` doesn't describe which channel is which.
+    fn coefficients(&self, source: ChannelLayout, output_channels: usize) -> Option<Vec<f64>> {
+        let mask = source.bits();
+        if mask.count_ones() != source.channels() as u32 || !(1..=2).contains(&output_channels) {
+            return None;
+        }
+        let channels: Vec<u64> = (0..64).map(|bit| 1u64 << bit).filter(|c| mask & c != 0).collect();
+        let (left, right): (Vec<f64>, Vec<f64>) = channels.iter().map(|c| self.stereo_weights(*c)).unzip();
+        // keep a full scale signal on every channel from clipping
+        let normalize = |row: Vec<f64>| {
+            let sum: f64 = row.iter().map(|w| w.abs()).sum();
+            row.into_iter().map(move |w| w / sum.max(1.))
+        };
+        Some(match output_channels {
+            1 => normalize(left.iter().zip(&right).map(|(l, r)| l + r).collect()).collect(),
+            _ => normalize(left).chain(normalize(right)).collect(),
+        })
+    }
+}
+
+/// The channel count SDL supports closest to (but not above) `channels`.
+pub(crate) fn supported_output_channels(channels: u8) -> u8 {
+    match channels {
+        0 | 2 | 3 => 2,
+        1 => 1,
+        4 | 5 => 4,
+        6 | 7 => 6,
+        _ => 8,
+    }
+}
+
+/// Does `source` have to be downmixed to play on `output_channels` channels?
+pub(crate) fn needs_downmix(source: ChannelLayout, output_channels: u16) -> bool {
+    source.channels() as u16 > output_channels
+}
+
+/// A resampler converting audio in `source` to packed `f32` in the default layout of `output_channels`
+/// at `output_rate`. Surround audio is folded down with `matrix` if it has to be downmixed to stereo or
+/// mono; other conversions use swresample's defaults.
+pub(crate) fn audio_resampler(
+    source_format: format::Sample,
+    source: ChannelLayout,
+    source_rate: u32,
+    output_channels: u16,
+    output_rate: u32,
+    matrix: DownmixMatrix,
+) -> Result<resampling::Context, ffmpeg::Error> {
+    let mut resampler = resampling::Context::get(
+        source_format,
+        source,
+        source_rate,
+        format::Sample::F32(format::sample::Type::Packed),
+        ChannelLayout::default(output_channels as i32),
+        output_rate,
+    )?;
+    if !needs_downmix(source, output_channels) {
+        return Ok(resampler);
+    }
+    if let Some(coefficients) = matrix.coefficients(source, output_channels as usize) {
+        // the matrix can only be changed while the context is uninitialized. if it is rejected, swresample
+        // falls back to its default one
+        unsafe {
+            let context = resampler.as_mut_ptr();
+            ffi::swr_close(context);
+            ffi::swr_set_matrix(context, coefficients.as_ptr(), source.channels() as i32);
+            let init = ffi::swr_init(context);
+            if init < 0 {
+                return Err(ffmpeg::Error::from(init));
+            }
+        }
+    }
+    Ok(resampler)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{actual:?}");
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn itu_stereo_from_5_1() {
+        // FL FR FC LFE SL SR, each row scaled by 1 / (1 + 2 * -3 dB) so it can't clip
+        let coefficients = DownmixMatrix::Itu.coefficients(ChannelLayout::_5POINT1, 2).unwrap();
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        let scale = 1. / (1. + 2. * h);
+        let left = [1., 0., h, 0., h, 0.].map(|w| w * scale);
+        let right = [0., 1., h, 0., 0., h].map(|w| w * scale);
+        assert_close(&coefficients, &[left, right].concat());
+    }
+
+    #[test]
+    fn mono_sums_both_sides() {
+        let coefficients = DownmixMatrix::Itu.coefficients(ChannelLayout::_5POINT1, 1).unwrap();
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        let row = [1., 1., 2. * h, 0., h, h];
+        let sum: f64 = row.iter().sum();
+        assert_close(&coefficients, &row.map(|w| w / sum));
+    }
+
+    #[test]
+    fn stereo_to_stereo_is_unchanged() {
+        for matrix in [DownmixMatrix::Itu, DownmixMatrix::ProLogicII] {
+            let coefficients = matrix.coefficients(ChannelLayout::STEREO, 2).unwrap();
+            assert_close(&coefficients, &[1., 0., 0., 1.]);
+        }
+    }
+
+    #[test]
+    fn pro_logic_surrounds_are_out_of_phase() {
+        let coefficients = DownmixMatrix::ProLogicII.coefficients(ChannelLayout::_5POINT1, 2).unwrap();
+        let (left, right) = coefficients.split_at(6);
+        assert!(left[4] < 0. && right[4] > 0.);
+        assert!(left[5] < 0. && right[5] > 0.);
+        assert_eq!(left[3], 0.);
+    }
+
+    #[test]
+    fn no_row_can_clip() {
+        for matrix in DownmixMatrix::ALL {
+            for layout in [ChannelLayout::_5POINT1, ChannelLayout::_7POINT1, ChannelLayout::_6POINT1] {
+                let channels = layout.channels() as usize;
+                for outputs in 1..=2 {
+                    let coefficients = matrix.coefficients(layout, outputs).unwrap();
+                    for row in coefficients.chunks(channels) {
+                        let sum: f64 = row.iter().map(|w| w.abs()).sum();
+                        assert!(sum <= 1. + 1e-9, "{matrix:?} {layout:?}: {row:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_stereo_and_mono_outputs() {
+        assert!(DownmixMatrix::Itu.coefficients(ChannelLayout::_5POINT1, 0).is_none());
+        assert!(DownmixMatrix::Itu.coefficients(ChannelLayout::_5POINT1, 4).is_none());
+    }
+}
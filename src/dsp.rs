@@ -0,0 +1,418 @@
+use std::f64::consts::PI;
+
+/// A stage of audio processing, applied to decoded audio before it is played.
+///
+/// Implement this to add your own stages with [`DspChain::custom`].
+pub trait AudioProcessor: Send {
+    /// Process interleaved samples in place.
+    fn process(&mut self, samples: &mut [f32], channels: usize, sample_rate: u32);
+    /// Forget any state carried over from previous samples, e.g. after seeking.
+    fn reset(&mut self) {}
+    /// Does this stage change the audio with its current settings?
+    fn is_active(&self) -> bool {
+        true
+    }
+}
+
+/// A second order IIR filter, in direct form I.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// A filter from coefficients normalized so that `a[0]` is 1.
+    pub(crate) fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self { b, a, x: [0.; 2], y: [0.; 2] }
+    }
+
+    /// Normalize coefficients of the Audio EQ Cookbook form.
+    fn from_cookbook(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self::new([b[0] / a[0], b[1] / a[0], b[2] / a[0]], [1., a[1] / a[0], a[2] / a[0]])
+    }
+
+    /// Boost or cut around `frequency`.
+    pub(crate) fn peaking(sample_rate: f64, frequency: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.);
+        let w0 = 2. * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2. * q);
+        Self::from_cookbook(
+            [1. + alpha * a, -2. * w0.cos(), 1. - alpha * a],
+            [1. + alpha / a, -2. * w0.cos(), 1. - alpha / a],
+        )
+    }
+
+    /// Boost or cut everything below `frequency`.
+    pub(crate) fn low_shelf(sample_rate: f64, frequency: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.);
+        let w0 = 2. * PI * frequency / sample_rate;
+        let cos = w0.cos();
+        // shelf slope of 1
+        let alpha = w0.sin() / 2. * 2f64.sqrt();
+        let sqrt_a_alpha = 2. * a.sqrt() * alpha;
+        Self::from_cookbook(
+            [
+                a * ((a + 1.) - (a - 1.) * cos + sqrt_a_alpha),
+                2. * a * ((a - 1.) - (a + 1.) * cos),
+                a * ((a + 1.) - (a - 1.) * cos - sqrt_a_alpha),
+            ],
+            [
+                (a + 1.) + (a - 1.) * cos + sqrt_a_alpha,
+                -2. * ((a - 1.) + (a + 1.) * cos),
+                (a + 1.) + (a - 1.) * cos - sqrt_a_alpha,
+            ],
+        )
+    }
+
+    pub(crate) fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+
+    fn reset(&mut self) {
+        self.x = [0.; 2];
+        self.y = [0.; 2];
+    }
+}
+
+/// The center frequencies of the [`Equalizer`] bands, one octave apart.
+pub const EQUALIZER_BANDS_HZ: [f64; 10] = [31., 62., 125., 250., 500., 1000., 2000., 4000., 8000., 16000.];
+
+/// A 10 band graphic equalizer.
+#[derive(Clone, Debug, Default)]
+pub struct Equalizer {
+    pub enabled: bool,
+    /// The boost (or cut, if negative) of each band of [`EQUALIZER_BANDS_HZ`], in dB.
+    pub gains_db: [f32; 10],
+    // the settings the filters were designed for
+    designed_for: Option<([f32; 10], usize, u32)>,
+    // one set of band filters per channel
+    filters: Vec<Vec<Biquad>>,
+}
+
+impl AudioProcessor for Equalizer {
+    fn process(&mut self, samples: &mut [f32], channels: usize, sample_rate: u32) {
+        if !self.is_active() {
+            return;
+        }
+        if self.designed_for != Some((self.gains_db, channels, sample_rate)) {
+            let bands: Vec<Biquad> = EQUALIZER_BANDS_HZ
+                .iter()
+                .zip(self.gains_db)
+                // bands at or above nyquist can't be filtered, and flat ones don't need to be
+                .filter(|(frequency, gain_db)| **frequency < sample_rate as f64 / 2. && *gain_db != 0.)
+                .map(|(frequency, gain_db)| Biquad::peaking(sample_rate as f64, *frequency, 2f64.sqrt(), gain_db as f64))
+                .collect();
+            self.filters = vec![bands; channels];
+            self.designed_for = Some((self.gains_db, channels, sample_rate));
+        }
+        for frame in samples.chunks_exact_mut(channels) {
+            for (sample, filters) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                *sample = filters.iter_mut().fold(*sample as f64, |x, filter| filter.process(x)) as f32;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.filters.iter_mut().flatten().for_each(Biquad::reset);
+    }
+
+    fn is_active(&self) -> bool {
+        self.enabled && self.gains_db.iter().any(|gain_db| *gain_db != 0.)
+    }
+}
+
+/// Boosts low frequencies with a shelf filter.
+#[derive(Clone, Debug)]
+pub struct BassBoost {
+    pub enabled: bool,
+    pub gain_db: f32,
+    /// Frequencies below this are boosted.
+    pub frequency_hz: f32,
+    designed_for: Option<(f32, f32, usize, u32)>,
+    filters: Vec<Biquad>,
+}
+
+impl Default for BassBoost {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gain_db: 6.,
+            frequency_hz: 100.,
+            designed_for: None,
+            filters: Vec::new(),
+        }
+    }
+}
+
+impl AudioProcessor for BassBoost {
+    fn process(&mut self, samples: &mut [f32], channels: usize, sample_rate: u32) {
+        if !self.is_active() {
+            return;
+        }
+        let settings = (self.gain_db, self.frequency_hz, channels, sample_rate);
+        if self.designed_for != Some(settings) {
+            let filter = Biquad::low_shelf(sample_rate as f64, self.frequency_hz as f64, self.gain_db as f64);
+            self.filters = vec![filter; channels];
+            self.designed_for = Some(settings);
+        }
+        for frame in samples.chunks_exact_mut(channels) {
+            for (sample, filter) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                *sample = filter.process(*sample as f64) as f32;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.filters.iter_mut().for_each(Biquad::reset);
+    }
+
+    fn is_active(&self) -> bool {
+        self.enabled && self.gain_db != 0.
+    }
+}
+
+/// Narrows the dynamic range by turning down loud passages ("night mode"), then makes everything louder.
+#[derive(Clone, Debug)]
+pub struct Compressor {
+    pub enabled: bool,
+    /// Levels above this are compressed, in dBFS.
+    pub threshold_db: f32,
+    /// How many dB the input has to rise above the threshold for the output to rise by 1 dB.
+    pub ratio: f32,
+    /// The gain applied after compression, in dB.
+    pub makeup_db: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    // the detected level, in dBFS
+    envelope_db: f32,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_db: -30.,
+            ratio: 4.,
+            makeup_db: 10.,
+            attack_ms: 10.,
+            release_ms: 200.,
+            envelope_db: -120.,
+        }
+    }
+}
+
+impl AudioProcessor for Compressor {
+    fn process(&mut self, samples: &mut [f32], channels: usize, sample_rate: u32) {
+        if !self.is_active() {
+            return;
+        }
+        let coefficient = |ms: f32| (-1. / (ms.max(0.1) / 1000. * sample_rate as f32)).exp();
+        let (attack, release) = (coefficient(self.attack_ms), coefficient(self.release_ms));
+        for frame in samples.chunks_exact_mut(channels) {
+            // linked, so that the stereo image doesn't shift
+            let peak = frame.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+            let level_db = 20. * peak.max(1e-6).log10();
+            let smoothing = if level_db > self.envelope_db { attack } else { release };
+            self.envelope_db = level_db + smoothing * (self.envelope_db - level_db);
+            let over_db = (self.envelope_db - self.threshold_db).max(0.);
+            let gain_db = self.makeup_db - over_db * (1. - 1. / self.ratio.max(1.));
+            let gain = 10f32.powf(gain_db / 20.);
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope_db = -120.;
+    }
+
+    fn is_active(&self) -> bool {
+        self.enabled
+    }
+}
+
+/// Widens or narrows the stereo image by scaling the difference between the channels.
+#[derive(Clone, Debug)]
+pub struct StereoWidener {
+    pub enabled: bool,
+    /// 0 is mono, 1 leaves the audio unchanged, above 1 is wider.
+    pub width: f32,
+}
+
+impl Default for StereoWidener {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            width: 1.5,
+        }
+    }
+}
+
+impl AudioProcessor for StereoWidener {
+    fn process(&mut self, samples: &mut [f32], channels: usize, _sample_rate: u32) {
+        if !self.is_active() || channels != 2 {
+            return;
+        }
+        for frame in samples.chunks_exact_mut(2) {
+            let mid = (frame[0] + frame[1]) / 2.;
+            let side = (frame[0] - frame[1]) / 2. * self.width;
+            frame[0] = mid + side;
+            frame[1] = mid - side;
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.enabled && self.width != 1.
+    }
+}
+
+/// Plays the average of all channels on every channel.
+#[derive(Clone, Debug, Default)]
+pub struct MonoDownmix {
+    pub enabled: bool,
+}
+
+impl AudioProcessor for MonoDownmix {
+    fn process(&mut self, samples: &mut [f32], channels: usize, _sample_rate: u32) {
+        if !self.is_active() || channels < 2 {
+            return;
+        }
+        for frame in samples.chunks_exact_mut(channels) {
+            let mono = frame.iter().sum::<f32>() / channels as f32;
+            frame.fill(mono);
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.enabled
+    }
+}
+
+/// The audio processing applied between decoding and playback: the built-in stages in the order of their
+/// fields, followed by any custom stages. Changes apply to the playing stream right away.
+#[derive(Default)]
+pub struct DspChain {
+    pub equalizer: Equalizer,
+    pub bass_boost: BassBoost,
+    pub compressor: Compressor,
+    pub stereo_widener: StereoWidener,
+    pub mono: MonoDownmix,
+    pub custom: Vec<Box<dyn AudioProcessor>>,
+}
+
+impl DspChain {
+    fn stages_mut(&mut self) -> impl Iterator<Item = &mut dyn AudioProcessor> {
+        [
+            &mut self.equalizer as &mut dyn AudioProcessor,
+            &mut self.bass_boost,
+            &mut self.compressor,
+            &mut self.stereo_widener,
+            &mut self.mono,
+        ]
+        .into_iter()
+        .chain(self.custom.iter_mut().map(|stage| stage.as_mut() as &mut dyn AudioProcessor))
+    }
+}
+
+impl AudioProcessor for DspChain {
+    fn process(&mut self, samples: &mut [f32], channels: usize, sample_rate: u32) {
+        for stage in self.stages_mut() {
+            stage.process(samples, channels, sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        for stage in self.stages_mut() {
+            stage.reset();
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.equalizer.is_active()
+            || self.bass_boost.is_active()
+            || self.compressor.is_active()
+            || self.stereo_widener.is_active()
+            || self.mono.is_active()
+            || self.custom.iter().any(|stage| stage.is_active())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    /// One second of a mono sine at `frequency` with a peak of 0.5.
+    fn sine(frequency: f64) -> Vec<f32> {
+        (0..RATE as usize)
+            .map(|i| (0.5 * (2. * PI * frequency * i as f64 / RATE as f64).sin()) as f32)
+            .collect()
+    }
+
+    /// The gain `processor` applies to a sine at `frequency` once it has settled, in dB.
+    fn gain_at(processor: &mut impl AudioProcessor, frequency: f64) -> f64 {
+        let mut samples = sine(frequency);
+        processor.process(&mut samples, 1, RATE);
+        let settled = &samples[samples.len() / 2..];
+        let peak = settled.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        20. * (peak as f64 / 0.5).log10()
+    }
+
+    #[test]
+    fn flat_equalizer_passes_through() {
+        let mut equalizer = Equalizer { enabled: true, ..Default::default() };
+        let mut samples = sine(1000.);
+        equalizer.process(&mut samples, 1, RATE);
+        assert_eq!(samples, sine(1000.));
+    }
+
+    #[test]
+    fn equalizer_band_gain_at_center() {
+        let mut gains_db = [0.; 10];
+        gains_db[5] = 6.;
+        let mut equalizer = Equalizer { enabled: true, gains_db, ..Default::default() };
+        let gain_db = gain_at(&mut equalizer, EQUALIZER_BANDS_HZ[5]);
+        assert!((gain_db - 6.).abs() < 0.1, "{gain_db}");
+    }
+
+    #[test]
+    fn bass_boost_shelf() {
+        let mut bass_boost = BassBoost { enabled: true, gain_db: 6., frequency_hz: 100., ..Default::default() };
+        // half the gain at the corner frequency, all of it well below, none well above
+        let gain_db = gain_at(&mut bass_boost, 100.);
+        assert!((gain_db - 3.).abs() < 0.2, "{gain_db}");
+        bass_boost.reset();
+        let gain_db = gain_at(&mut bass_boost, 10.);
+        assert!((gain_db - 6.).abs() < 0.2, "{gain_db}");
+        bass_boost.reset();
+        let gain_db = gain_at(&mut bass_boost, 5000.);
+        assert!(gain_db.abs() < 0.1, "{gain_db}");
+    }
+
+    #[test]
+    fn compressor_steady_state_gain() {
+        let mut compressor = Compressor {
+            enabled: true,
+            threshold_db: -30.,
+            ratio: 4.,
+            makeup_db: 0.,
+            ..Default::default()
+        };
+        // a constant level 24 dB over the threshold is reduced to 6 dB over it
+        let level = 10f32.powf(-6. / 20.);
+        let mut samples = vec![level; RATE as usize];
+        compressor.process(&mut samples, 1, RATE);
+        let output_db = 20. * samples.last().unwrap().log10();
+        assert!((output_db + 24.).abs() < 0.1, "{output_db}");
+    }
+}
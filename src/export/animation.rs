@@ -0,0 +1,564 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::format::Pixel;
+use ffmpeg::frame::Video;
+use ffmpeg::{codec, encoder, format, Packet, Rational};
+use anyhow::Result;
+use std::ops::Range;
+use crate::export::{received, ExportReporter};
+use crate::player::{is_ffmpeg_eof_error, scale_frame, timestamp_to_millisec, VideoStreamer};
+
+/// The container of an exported animation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationFormat {
+    /// 256 colors, quantized with a palette shared by all frames.
+    Gif,
+    /// Full color animated png.
+    Apng,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "apng",
+        }
+    }
+    fn encoder_name(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "apng",
+        }
+    }
+}
+
+/// How colors that are not in the palette are approximated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    /// Use the nearest palette color. Smallest files, visible banding on gradients.
+    None,
+    /// Ordered 8x8 Bayer dithering. Compresses well, since the pattern is stable between frames.
+    Bayer,
+    /// Floyd-Steinberg error diffusion. Best looking stills, noisier between frames.
+    FloydSteinberg,
+}
+
+/// Settings of an animation export.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationOptions {
+    pub format: AnimationFormat,
+    pub start_ms: i64,
+    pub end_ms: i64,
+    /// Width of the output, the height follows the aspect ratio of the source.
+    pub width: u32,
+    pub fps: u32,
+    /// Only used for [`AnimationFormat::Gif`].
+    pub dither: Dither,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            format: AnimationFormat::Gif,
+            start_ms: 0,
+            end_ms: 5000,
+            width: 480,
+            fps: 15,
+            dither: Dither::Bayer,
+        }
+    }
+}
+
+impl AnimationOptions {
+    /// How many frames the range holds at the target framerate, at least one, so that ranges shorter than a
+    /// frame interval still export the frame shown at their start.
+    fn frame_count(&self) -> usize {
+        (((self.end_ms - self.start_ms).max(0) as u64 * self.fps as u64 / 1000) as usize).max(1)
+    }
+}
+
+/// A frame scaled to the output size, as tightly packed RGB24.
+struct RgbFrame {
+    pixels: Vec<u8>,
+    /// The output frame it is shown from, in frames of the target framerate since the start of the range.
+    index: usize,
+}
+
+/// Decodes and scales the frames of the range one at a time with the player's video pipeline,
+/// dropping frames to reach the target framerate.
+struct FrameSource {
+    streamer: VideoStreamer,
+    time_base: Rational,
+    width: u32,
+    height: u32,
+    start_ms: i64,
+    end_ms: i64,
+    fps: u32,
+    total_frames: usize,
+    produced: usize,
+}
+
+impl FrameSource {
+    /// Open `input_path` at the start of the range, producing at most `max_frames` frames.
+    fn open(input_path: &str, options: &AnimationOptions, max_frames: usize) -> Result<Self> {
+        let mut streamer = VideoStreamer::open(input_path)?;
+        let decoder = streamer.video_decoder();
+        let width = options.width.min(decoder.width()) & !1;
+        let height = ((decoder.height() as u64 * width as u64 / decoder.width() as u64) as u32).max(2) & !1;
+        let time_base = streamer.time_base();
+        streamer.seek_to_keyframe(options.start_ms)?;
+        Ok(Self {
+            streamer,
+            time_base,
+            width,
+            height,
+            start_ms: options.start_ms,
+            end_ms: options.end_ms,
+            fps: options.fps,
+            total_frames: options.frame_count().min(max_frames),
+            produced: 0,
+        })
+    }
+
+    /// The next frame of the range, `None` once the range or the file is over.
+    fn next_frame(&mut self) -> Result<Option<RgbFrame>> {
+        while self.produced < self.total_frames {
+            let frame = match self.streamer.recieve_next_decoded_frame() {
+                Ok(frame) => frame,
+                Err(e) if is_ffmpeg_eof_error(&e) => break,
+                Err(e) => return Err(e),
+            };
+            let Some(timestamp) = frame.timestamp() else {
+                continue;
+            };
+            let frame_ms = timestamp_to_millisec(timestamp, self.time_base);
+            // a range between two frames still gets the first frame after its start
+            if frame_ms >= self.end_ms && self.produced > 0 {
+                break;
+            }
+            // the next output frame is due at this time, anything before it is dropped
+            let next_frame_ms = self.start_ms + (self.produced as i64 * 1000) / self.fps as i64;
+            if frame_ms < next_frame_ms {
+                continue;
+            }
+            // a gap in the source, or a source slower than the target, holds the frame instead of
+            // speeding the animation up
+            let index = if self.produced == 0 {
+                0
+            } else {
+                (((frame_ms - self.start_ms) * self.fps as i64 / 1000) as usize).max(self.produced)
+            };
+            if index >= self.total_frames {
+                break;
+            }
+            let rgb_frame = scale_frame(&frame, Pixel::RGB24, self.width, self.height)?;
+            self.produced = index + 1;
+            return Ok(Some(RgbFrame {
+                pixels: packed_rgb(&rgb_frame),
+                index,
+            }));
+        }
+        if self.produced == 0 {
+            anyhow::bail!("no frames in the selected range");
+        }
+        Ok(None)
+    }
+
+    /// Report that the frame just produced is done, as a fraction of `progress`.
+    fn report(&self, reporter: Option<&ExportReporter>, progress: &Range<f32>) {
+        if let Some(reporter) = reporter {
+            let done = self.produced as f32 / self.total_frames as f32;
+            reporter.progress(progress.start + done * (progress.end - progress.start));
+        }
+    }
+}
+
+fn packed_rgb(frame: &Video) -> Vec<u8> {
+    let byte_width = frame.width() as usize * 3;
+    let stride = frame.stride(0);
+    let data = frame.data(0);
+    let mut pixels = Vec::with_capacity(byte_width * frame.height() as usize);
+    for line in 0..frame.height() as usize {
+        pixels.extend_from_slice(&data[line * stride..line * stride + byte_width]);
+    }
+    pixels
+}
+
+// ---- palette generation (median cut over a 15 bit color histogram) ----
+
+const HISTOGRAM_BITS: u32 = 5;
+
+#[inline]
+fn histogram_index(r: u8, g: u8, b: u8) -> usize {
+    let shift = 8 - HISTOGRAM_BITS;
+    ((r as usize >> shift) << (2 * HISTOGRAM_BITS))
+        | ((g as usize >> shift) << HISTOGRAM_BITS)
+        | (b as usize >> shift)
+}
+
+#[inline]
+fn histogram_color(index: usize) -> [u8; 3] {
+    let mask = (1 << HISTOGRAM_BITS) - 1;
+    let shift = 8 - HISTOGRAM_BITS;
+    // center of the bucket
+    let expand = |c: usize| ((c << shift) | (1 << (shift - 1))) as u8;
+    [
+        expand((index >> (2 * HISTOGRAM_BITS)) & mask),
+        expand((index >> HISTOGRAM_BITS) & mask),
+        expand(index & mask),
+    ]
+}
+
+/// Build a palette of at most `max_colors` colors that fits every frame of `source`, like ffmpeg's
+/// `palettegen`. Only the color histogram is kept, not the frames.
+fn generate_palette(
+    source: &mut FrameSource,
+    max_colors: usize,
+    reporter: Option<&ExportReporter>,
+    progress: Range<f32>,
+) -> Result<Vec<[u8; 3]>> {
+    let mut histogram = vec![0u64; 1 << (3 * HISTOGRAM_BITS)];
+    while let Some(frame) = source.next_frame()? {
+        if reporter.is_some_and(|r| r.is_cancelled()) {
+            break;
+        }
+        for p in frame.pixels.chunks_exact(3) {
+            histogram[histogram_index(p[0], p[1], p[2])] += 1;
+        }
+        source.report(reporter, &progress);
+    }
+    Ok(median_cut(&histogram, max_colors))
+}
+
+fn median_cut(histogram: &[u64], max_colors: usize) -> Vec<[u8; 3]> {
+    let colors: Vec<([u8; 3], u64)> = histogram
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(i, count)| (histogram_color(i), *count))
+        .collect();
+
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        // split the box with the widest channel range, weighted by how many pixels it holds
+        let Some((box_index, channel)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let (channel, range) = widest_channel(b);
+                let weight: u64 = b.iter().map(|(_, count)| count).sum();
+                (i, channel, range as f64 * (weight as f64).sqrt())
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2))
+            .map(|(i, channel, _)| (i, channel))
+        else {
+            break;
+        };
+        let mut split_box = boxes.swap_remove(box_index);
+        split_box.sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u64 = split_box.iter().map(|(_, count)| count).sum();
+        let mut accumulated = 0;
+        let median = split_box
+            .iter()
+            .position(|(_, count)| {
+                accumulated += count;
+                accumulated >= total / 2
+            })
+            .unwrap_or(0)
+            .clamp(0, split_box.len() - 2);
+        let upper = split_box.split_off(median + 1);
+        boxes.push(split_box);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|b| {
+            let total: u64 = b.iter().map(|(_, count)| count).sum::<u64>().max(1);
+            let mut sum = [0u64; 3];
+            for (color, count) in b {
+                for c in 0..3 {
+                    sum[c] += color[c] as u64 * count;
+                }
+            }
+            [(sum[0] / total) as u8, (sum[1] / total) as u8, (sum[2] / total) as u8]
+        })
+        .collect()
+}
+
+fn widest_channel(colors: &[([u8; 3], u64)]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let min = colors.iter().map(|(color, _)| color[c]).min().unwrap_or(0);
+            let max = colors.iter().map(|(color, _)| color[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+// ---- palette mapping (like ffmpeg's `paletteuse`) ----
+
+struct PaletteMapper {
+    palette: Vec<[u8; 3]>,
+    // nearest palette index of every histogram bucket, filled lazily
+    cache: Vec<Option<u8>>,
+}
+
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+impl PaletteMapper {
+    fn new(palette: Vec<[u8; 3]>) -> Self {
+        Self {
+            palette,
+            cache: vec![None; 1 << (3 * HISTOGRAM_BITS)],
+        }
+    }
+
+    fn nearest(&mut self, r: u8, g: u8, b: u8) -> u8 {
+        let index = histogram_index(r, g, b);
+        if let Some(nearest) = self.cache[index] {
+            return nearest;
+        }
+        let nearest = self
+            .palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, p)| {
+                let dr = p[0] as i32 - r as i32;
+                let dg = p[1] as i32 - g as i32;
+                let db = p[2] as i32 - b as i32;
+                dr * dr + dg * dg + db * db
+            })
+            .map(|(i, _)| i as u8)
+            .unwrap_or(0);
+        self.cache[index] = Some(nearest);
+        nearest
+    }
+
+    /// Map a packed RGB24 frame to palette indices.
+    fn map(&mut self, pixels: &[u8], width: usize, height: usize, dither: Dither) -> Vec<u8> {
+        let mut indices = vec![0u8; width * height];
+        match dither {
+            Dither::None => {
+                for (index, p) in indices.iter_mut().zip(pixels.chunks_exact(3)) {
+                    *index = self.nearest(p[0], p[1], p[2]);
+                }
+            }
+            Dither::Bayer => {
+                for y in 0..height {
+                    for x in 0..width {
+                        let i = y * width + x;
+                        // threshold in -16..16, about the size of a histogram bucket
+                        let offset = BAYER_8X8[y % 8][x % 8] as i32 / 2 - 16;
+                        let p = &pixels[i * 3..i * 3 + 3];
+                        let d = |c: u8| (c as i32 + offset).clamp(0, 255) as u8;
+                        indices[i] = self.nearest(d(p[0]), d(p[1]), d(p[2]));
+                    }
+                }
+            }
+            Dither::FloydSteinberg => {
+                let mut errors = vec![[0i32; 3]; width * 2];
+                for y in 0..height {
+                    let (current, next) = errors.split_at_mut(width);
+                    next.fill([0; 3]);
+                    for x in 0..width {
+                        let i = y * width + x;
+                        let mut color = [0u8; 3];
+                        for c in 0..3 {
+                            color[c] = (pixels[i * 3 + c] as i32 + current[x][c] / 16).clamp(0, 255) as u8;
+                        }
+                        let index = self.nearest(color[0], color[1], color[2]);
+                        indices[i] = index;
+                        let mapped = self.palette[index as usize];
+                        for c in 0..3 {
+                            let error = color[c] as i32 - mapped[c] as i32;
+                            if x + 1 < width {
+                                current[x + 1][c] += error * 7;
+                                next[x + 1][c] += error;
+                            }
+                            if x > 0 {
+                                next[x - 1][c] += error * 3;
+                            }
+                            next[x][c] += error * 5;
+                        }
+                    }
+                    errors.rotate_left(width);
+                }
+            }
+        }
+        indices
+    }
+}
+
+// ---- encoding ----
+
+fn open_encoder(format: AnimationFormat, width: u32, height: u32, fps: u32, global_header: bool) -> Result<encoder::Video> {
+    let codec = encoder::find_by_name(format.encoder_name())
+        .ok_or(anyhow::anyhow!("encoder {} is not available", format.encoder_name()))?;
+    let mut encoder = codec::context::Context::new_with_codec(codec).encoder().video()?;
+    encoder.set_width(width);
+    encoder.set_height(height);
+    encoder.set_format(match format {
+        AnimationFormat::Gif => Pixel::PAL8,
+        AnimationFormat::Apng => Pixel::RGB24,
+    });
+    encoder.set_time_base(Rational(1, fps as i32));
+    encoder.set_frame_rate(Some(Rational(fps as i32, 1)));
+    if global_header {
+        encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+    Ok(encoder.open()?)
+}
+
+/// Encode the frames of `source` as they are decoded, mapped to `palette` if there is one, and pass every
+/// packet to `write_packet`.
+fn encode_frames(
+    mut encoder: encoder::Video,
+    source: &mut FrameSource,
+    palette: Option<Vec<[u8; 3]>>,
+    options: &AnimationOptions,
+    reporter: Option<&ExportReporter>,
+    progress: Range<f32>,
+    mut write_packet: impl FnMut(&mut Packet) -> Result<()>,
+) -> Result<()> {
+    let (width, height) = (encoder.width(), encoder.height());
+    let mut mapper = palette.map(PaletteMapper::new);
+    let mut packet = Packet::empty();
+    while let Some(rgb_frame) = source.next_frame()? {
+        if reporter.is_some_and(|r| r.is_cancelled()) {
+            break;
+        }
+        let mut frame = Video::new(encoder.format(), width, height);
+        let stride = frame.stride(0);
+        match mapper.as_mut() {
+            Some(mapper) => {
+                let indices = mapper.map(&rgb_frame.pixels, width as usize, height as usize, options.dither);
+                let data = frame.data_mut(0);
+                for (line, row) in indices.chunks_exact(width as usize).enumerate() {
+                    data[line * stride..line * stride + row.len()].copy_from_slice(row);
+                }
+                write_palette(&mut frame, &mapper.palette);
+            }
+            None => {
+                let byte_width = width as usize * 3;
+                let data = frame.data_mut(0);
+                for (line, row) in rgb_frame.pixels.chunks_exact(byte_width).enumerate() {
+                    data[line * stride..line * stride + byte_width].copy_from_slice(row);
+                }
+            }
+        }
+        frame.set_pts(Some(rgb_frame.index as i64));
+        encoder.send_frame(&frame)?;
+        while received(encoder.receive_packet(&mut packet))? {
+            write_packet(&mut packet)?;
+        }
+        source.report(reporter, &progress);
+    }
+    encoder.send_eof()?;
+    while received(encoder.receive_packet(&mut packet))? {
+        write_packet(&mut packet)?;
+    }
+    Ok(())
+}
+
+/// The GIF palette of the range, read in a first pass over `input_path`. `None` for formats without one.
+fn palette_of(
+    input_path: &str,
+    options: &AnimationOptions,
+    max_frames: usize,
+    reporter: Option<&ExportReporter>,
+    progress: Range<f32>,
+) -> Result<Option<Vec<[u8; 3]>>> {
+    match options.format {
+        AnimationFormat::Gif => {
+            let mut source = FrameSource::open(input_path, options, max_frames)?;
+            Ok(Some(generate_palette(&mut source, 256, reporter, progress)?))
+        }
+        AnimationFormat::Apng => Ok(None),
+    }
+}
+
+fn write_palette(frame: &mut Video, palette: &[[u8; 3]]) {
+    // PAL8 frames keep 256 native endian 0xAARRGGBB entries in the second data pointer
+    unsafe {
+        let data = (*frame.as_mut_ptr()).data[1] as *mut u32;
+        if data.is_null() {
+            return;
+        }
+        for i in 0..256 {
+            let [r, g, b] = palette.get(i).copied().unwrap_or([0, 0, 0]);
+            *data.add(i) = 0xff00_0000 | (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+    }
+}
+
+/// Export a range of `input_path` as an animated GIF or APNG. Frames are encoded as they are decoded; for
+/// GIF the range is decoded twice, first to build a palette from all of its frames.
+pub fn export_animation(
+    input_path: &str,
+    output_path: &str,
+    options: &AnimationOptions,
+    reporter: &ExportReporter,
+) -> Result<()> {
+    let encode_progress = match options.format {
+        AnimationFormat::Gif => 0.5..1.,
+        AnimationFormat::Apng => 0.0..1.,
+    };
+    let palette = palette_of(input_path, options, usize::MAX, Some(reporter), 0.0..encode_progress.start)?;
+    if reporter.is_cancelled() {
+        return Ok(());
+    }
+    let mut source = FrameSource::open(input_path, options, usize::MAX)?;
+    let (width, height) = (source.width, source.height);
+
+    let mut output_context = format::output_as(&output_path, options.format.extension())?;
+    let global_header = output_context
+        .format()
+        .flags()
+        .contains(format::Flags::GLOBAL_HEADER);
+    let encoder = open_encoder(options.format, width, height, options.fps, global_header)?;
+    let encoder_time_base = Rational(1, options.fps as i32);
+    let mut output_stream = output_context.add_stream(encoder.codec())?;
+    output_stream.set_parameters(&encoder);
+    output_stream.set_time_base(encoder_time_base);
+    output_context.write_header()?;
+    let output_time_base = output_context.stream(0).unwrap().time_base();
+
+    let mut bytes_written = 0;
+    encode_frames(encoder, &mut source, palette, options, Some(reporter), encode_progress, |packet| {
+        packet.set_stream(0);
+        packet.rescale_ts(encoder_time_base, output_time_base);
+        bytes_written += packet.size() as u64;
+        reporter.bytes_written(bytes_written);
+        packet.write_interleaved(&mut output_context)?;
+        Ok(())
+    })?;
+    output_context.write_trailer()?;
+    Ok(())
+}
+
+/// Estimate the size of [`export_animation`]'s output, in bytes, by encoding the first second of the
+/// range and extrapolating.
+pub fn estimate_animation_size(input_path: &str, options: &AnimationOptions) -> Result<u64> {
+    let sample_frames = (options.fps as usize).max(1);
+    let palette = palette_of(input_path, options, sample_frames, None, 0.0..0.)?;
+    let mut source = FrameSource::open(input_path, options, sample_frames)?;
+    let encoder = open_encoder(options.format, source.width, source.height, options.fps, false)?;
+    let mut sample_bytes = 0;
+    encode_frames(encoder, &mut source, palette, options, None, 0.0..0., |packet| {
+        sample_bytes += packet.size() as u64;
+        Ok(())
+    })?;
+    // held frames cost next to nothing, so this is per output frame rather than per encoded frame
+    Ok(sample_bytes * options.frame_count() as u64 / source.produced.max(1) as u64)
+}
@@ -0,0 +1,505 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::codec::capabilities::Capabilities;
+use ffmpeg::media::Type;
+use ffmpeg::software::resampling;
+use ffmpeg::{codec, decoder, encoder, format, frame, ChannelLayout, Packet, Rational};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use anyhow::Result;
+use crate::export::{received, source_channel_layout, ExportReporter};
+use crate::player::{millisec_to_timestamp, timestamp_to_millisec, AV_TIME_BASE_RATIONAL};
+
+/// The file formats audio can be extracted to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    /// 16-bit PCM in a RIFF/WAVE file.
+    Wav,
+    Flac,
+    Opus,
+    Mp3,
+}
+
+impl AudioFormat {
+    pub const ALL: [AudioFormat; 4] = [AudioFormat::Wav, AudioFormat::Flac, AudioFormat::Opus, AudioFormat::Mp3];
+
+    /// The file extension for this format, which also selects the muxer.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Mp3 => "mp3",
+        }
+    }
+
+    /// The ffmpeg encoder used for this format. WAV is written without one.
+    fn encoder_name(&self) -> Option<&'static str> {
+        match self {
+            AudioFormat::Wav => None,
+            AudioFormat::Flac => Some("flac"),
+            AudioFormat::Opus => Some("libopus"),
+            AudioFormat::Mp3 => Some("libmp3lame"),
+        }
+    }
+
+    /// Is the bitrate option used by this format?
+    pub fn is_lossy(&self) -> bool {
+        matches!(self, AudioFormat::Opus | AudioFormat::Mp3)
+    }
+}
+
+/// Configures an audio extraction.
+#[derive(Clone, Debug)]
+pub struct AudioExportOptions {
+    pub format: AudioFormat,
+    /// The index of the audio stream to extract. The best audio stream is used if unset.
+    pub stream_index: Option<usize>,
+    /// Only extract the audio between these two timestamps, in milliseconds. The whole stream is extracted if unset.
+    pub range_ms: Option<(i64, i64)>,
+    /// The output sample rate. The source's sample rate is kept if unset (opus is always 48kHz).
+    pub sample_rate: Option<u32>,
+    /// The number of output channels. The source's channel layout is kept if unset.
+    pub channels: Option<u16>,
+    /// The bitrate of lossy formats, in bits per second.
+    pub bitrate: usize,
+}
+
+impl Default for AudioExportOptions {
+    fn default() -> Self {
+        Self {
+            format: AudioFormat::Wav,
+            stream_index: None,
+            range_ms: None,
+            sample_rate: None,
+            channels: None,
+            bitrate: 192_000,
+        }
+    }
+}
+
+/// Writes 16-bit PCM samples into a canonical 44-byte-header RIFF/WAVE file.
+struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u64,
+}
+
+impl WavWriter {
+    const HEADER_LEN: u64 = 44;
+
+    fn create(path: &str, channels: u16, sample_rate: u32) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * 2;
+        // the RIFF and data chunk sizes are patched in by `finish`
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // PCM
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { file, data_len: 0 })
+    }
+
+    /// Write interleaved samples, given as native-endian `i16` bytes.
+    fn write_samples(&mut self, samples: &[u8]) -> Result<()> {
+        if Self::HEADER_LEN + self.data_len + samples.len() as u64 > u32::MAX as u64 {
+            anyhow::bail!("the audio is too long for a WAV file (4 GB)");
+        }
+        for sample in samples.chunks_exact(2) {
+            let sample = i16::from_ne_bytes([sample[0], sample[1]]);
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += samples.len() as u64;
+        Ok(())
+    }
+
+    fn bytes_written(&self) -> u64 {
+        Self::HEADER_LEN + self.data_len
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let data_len = self.data_len as u32;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(data_len + Self::HEADER_LEN as u32 - 8).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data_len.to_le_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// The bytes of the first `samples` samples of `plane` of an audio frame.
+///
+/// `frame::Audio::data` uses the linesize of the plane, which ffmpeg only sets for the first plane.
+fn plane_bytes(frame: &frame::Audio, plane: usize, samples: usize) -> &[u8] {
+    let len = samples * bytes_per_plane_sample(frame.format(), frame.channels());
+    unsafe { std::slice::from_raw_parts((*frame.as_ptr()).data[plane], len) }
+}
+
+fn plane_bytes_mut(frame: &mut frame::Audio, plane: usize, samples: usize) -> &mut [u8] {
+    let len = samples * bytes_per_plane_sample(frame.format(), frame.channels());
+    unsafe { std::slice::from_raw_parts_mut((*frame.as_mut_ptr()).data[plane], len) }
+}
+
+fn bytes_per_plane_sample(format: format::Sample, channels: u16) -> usize {
+    if format.is_planar() {
+        format.bytes()
+    } else {
+        format.bytes() * channels as usize
+    }
+}
+
+/// Buffers resampled audio so that it can be handed to an encoder in frames of a fixed size.
+struct SampleFifo {
+    format: format::Sample,
+    channel_layout: ChannelLayout,
+    rate: u32,
+    planes: Vec<Vec<u8>>,
+    bytes_per_sample: usize,
+}
+
+impl SampleFifo {
+    fn new(format: format::Sample, channel_layout: ChannelLayout, rate: u32) -> Self {
+        let channels = channel_layout.channels() as u16;
+        let plane_count = if format.is_planar() { channels as usize } else { 1 };
+        Self {
+            format,
+            channel_layout,
+            rate,
+            planes: vec![Vec::new(); plane_count],
+            bytes_per_sample: bytes_per_plane_sample(format, channels),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.planes[0].len() / self.bytes_per_sample
+    }
+
+    /// Append `count` samples of `frame`, starting at sample `skip`.
+    fn push(&mut self, frame: &frame::Audio, skip: usize, count: usize) {
+        for (index, plane) in self.planes.iter_mut().enumerate() {
+            let bytes = plane_bytes(frame, index, skip + count);
+            plane.extend_from_slice(&bytes[skip * self.bytes_per_sample..]);
+        }
+    }
+
+    /// Take `samples` samples out of the fifo, if there are that many.
+    fn pop(&mut self, samples: usize) -> Option<frame::Audio> {
+        if samples == 0 || self.len() < samples {
+            return None;
+        }
+        let mut frame = frame::Audio::new(self.format, samples, self.channel_layout);
+        frame.set_rate(self.rate);
+        let len = samples * self.bytes_per_sample;
+        for (index, plane) in self.planes.iter_mut().enumerate() {
+            plane_bytes_mut(&mut frame, index, samples).copy_from_slice(&plane[..len]);
+            plane.drain(..len);
+        }
+        Some(frame)
+    }
+}
+
+/// Where the resampled audio ends up.
+enum AudioSink {
+    Wav(WavWriter),
+    Encoder(AudioEncoder),
+}
+
+struct AudioEncoder {
+    output_context: format::context::Output,
+    encoder: encoder::Audio,
+    time_base: Rational,
+    fifo: SampleFifo,
+    // the number of samples in each frame sent to the encoder
+    frame_size: usize,
+    // the pts of the next frame, in samples
+    next_pts: i64,
+    bytes_written: u64,
+}
+
+impl AudioEncoder {
+    fn new(
+        output_path: &str,
+        audio_format: AudioFormat,
+        decoder: &decoder::Audio,
+        metadata: ffmpeg::Dictionary<'static>,
+        options: &AudioExportOptions,
+    ) -> Result<Self> {
+        let encoder_name = audio_format.encoder_name().unwrap();
+        let codec = encoder::find_by_name(encoder_name)
+            .ok_or(anyhow::anyhow!("encoder {encoder_name} is not available"))?
+            .audio()?;
+        let mut output_context = format::output(&output_path)?;
+        let global_header = output_context
+            .format()
+            .flags()
+            .contains(format::Flags::GLOBAL_HEADER);
+        let mut output_stream = output_context.add_stream(codec)?;
+
+        let channels = options.channels.map_or(decoder.channel_layout().channels(), |c| c as i32);
+        let channel_layout = codec
+            .channel_layouts()
+            .map(|layouts| layouts.best(channels))
+            .unwrap_or(ChannelLayout::default(channels));
+        let sample_format = codec
+            .formats()
+            .and_then(|mut formats| formats.next())
+            .unwrap_or(format::Sample::F32(format::sample::Type::Planar));
+        let rate = match audio_format {
+            // opus only supports 48kHz
+            AudioFormat::Opus => 48_000,
+            _ => options.sample_rate.unwrap_or(decoder.rate()),
+        };
+        let time_base = Rational(1, rate as i32);
+
+        let mut encoder = codec::context::Context::new_with_codec(*codec)
+            .encoder()
+            .audio()?;
+        encoder.set_rate(rate as i32);
+        encoder.set_channel_layout(channel_layout);
+        encoder.set_format(sample_format);
+        encoder.set_time_base(time_base);
+        if audio_format.is_lossy() {
+            encoder.set_bit_rate(options.bitrate);
+        }
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+        let encoder = encoder.open_as(codec)?;
+        output_stream.set_parameters(&encoder);
+        output_stream.set_time_base(time_base);
+
+        output_context.set_metadata(metadata);
+        output_context.write_header()?;
+
+        let frame_size = if codec.capabilities().contains(Capabilities::VARIABLE_FRAME_SIZE)
+            || encoder.frame_size() == 0
+        {
+            4096
+        } else {
+            encoder.frame_size() as usize
+        };
+        Ok(Self {
+            fifo: SampleFifo::new(sample_format, channel_layout, rate),
+            output_context,
+            encoder,
+            time_base,
+            frame_size,
+            next_pts: 0,
+            bytes_written: 0,
+        })
+    }
+
+    fn push(&mut self, frame: &frame::Audio, skip: usize, count: usize) -> Result<()> {
+        self.fifo.push(frame, skip, count);
+        while let Some(frame) = self.fifo.pop(self.frame_size) {
+            self.send_frame(frame)?;
+        }
+        Ok(())
+    }
+
+    fn send_frame(&mut self, mut frame: frame::Audio) -> Result<()> {
+        frame.set_pts(Some(self.next_pts));
+        self.next_pts += frame.samples() as i64;
+        self.encoder.send_frame(&frame)?;
+        self.receive_packets()
+    }
+
+    fn receive_packets(&mut self) -> Result<()> {
+        let output_time_base = self.output_context.stream(0).unwrap().time_base();
+        let mut packet = Packet::empty();
+        while received(self.encoder.receive_packet(&mut packet))? {
+            packet.set_stream(0);
+            packet.rescale_ts(self.time_base, output_time_base);
+            self.bytes_written += packet.size() as u64;
+            packet.write_interleaved(&mut self.output_context)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        // the last frame may be shorter than the frame size
+        let remaining = self.fifo.len();
+        if let Some(frame) = self.fifo.pop(remaining) {
+            self.send_frame(frame)?;
+        }
+        self.encoder.send_eof()?;
+        self.receive_packets()?;
+        self.output_context.write_trailer()?;
+        Ok(())
+    }
+}
+
+impl AudioSink {
+    fn push(&mut self, frame: &frame::Audio, skip: usize, count: usize) -> Result<()> {
+        match self {
+            AudioSink::Wav(writer) => {
+                let bytes = plane_bytes(frame, 0, skip + count);
+                writer.write_samples(&bytes[skip * frame.channels() as usize * 2..])
+            }
+            AudioSink::Encoder(encoder) => encoder.push(frame, skip, count),
+        }
+    }
+
+    fn bytes_written(&self) -> u64 {
+        match self {
+            AudioSink::Wav(writer) => writer.bytes_written(),
+            AudioSink::Encoder(encoder) => encoder.bytes_written,
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            AudioSink::Wav(writer) => writer.finish(),
+            AudioSink::Encoder(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Extract an audio stream of `input_path` to `output_path`, optionally limited to a time range and converted
+/// to another sample rate or channel count. See [`AudioExportOptions`].
+pub fn export_audio(
+    input_path: &str,
+    output_path: &str,
+    options: &AudioExportOptions,
+    reporter: &ExportReporter,
+) -> Result<()> {
+    let mut input_context = format::input(&input_path)?;
+    let stream = match options.stream_index {
+        Some(index) => input_context
+            .stream(index)
+            .filter(|s| s.parameters().medium() == Type::Audio)
+            .ok_or(anyhow::anyhow!("stream {index} is not an audio stream"))?,
+        None => input_context
+            .streams()
+            .best(Type::Audio)
+            .ok_or(anyhow::anyhow!("the input has no audio stream"))?,
+    };
+    let stream_index = stream.index();
+    let time_base = stream.time_base();
+    let mut decoder = codec::context::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .audio()?;
+    let source_layout = source_channel_layout(&decoder);
+
+    let (start_ms, end_ms) = match options.range_ms {
+        Some((start_ms, end_ms)) if end_ms <= start_ms => {
+            anyhow::bail!("range end ({end_ms}ms) must be after its start ({start_ms}ms)")
+        }
+        Some(range) => range,
+        None => (0, timestamp_to_millisec(input_context.duration(), AV_TIME_BASE_RATIONAL)),
+    };
+
+    let (mut sink, output_format, output_layout, output_rate) = match options.format {
+        AudioFormat::Wav => {
+            let layout = options
+                .channels
+                .map_or(source_layout, |channels| ChannelLayout::default(channels as i32));
+            let rate = options.sample_rate.unwrap_or(decoder.rate());
+            let writer = WavWriter::create(output_path, layout.channels() as u16, rate)?;
+            (AudioSink::Wav(writer), format::Sample::I16(format::sample::Type::Packed), layout, rate)
+        }
+        audio_format => {
+            let metadata = input_context.metadata().to_owned();
+            let encoder = AudioEncoder::new(output_path, audio_format, &decoder, metadata, options)?;
+            let (format, layout, rate) = (encoder.fifo.format, encoder.fifo.channel_layout, encoder.fifo.rate);
+            (AudioSink::Encoder(encoder), format, layout, rate)
+        }
+    };
+    let mut resampler = resampling::Context::get(
+        decoder.format(),
+        source_layout,
+        decoder.rate(),
+        output_format,
+        output_layout,
+        output_rate,
+    )?;
+
+    if options.range_ms.is_some() {
+        let start_ts = millisec_to_timestamp(start_ms, AV_TIME_BASE_RATIONAL);
+        input_context.seek(start_ts, ..start_ts)?;
+    }
+    // the range in output samples, and the position of the next resampled sample
+    let start_sample = start_ms * output_rate as i64 / 1000;
+    let end_sample = match options.range_ms {
+        Some(_) => end_ms * output_rate as i64 / 1000,
+        None => i64::MAX,
+    };
+    let mut position: Option<i64> = None;
+    let range_ms = (end_ms - start_ms).max(1) as f32;
+
+    let write_resampled = |resampled: &frame::Audio, position: &mut i64, sink: &mut AudioSink| -> Result<bool> {
+        let samples = resampled.samples() as i64;
+        let skip = (start_sample - *position).clamp(0, samples);
+        let take = (end_sample - *position).clamp(0, samples) - skip;
+        *position += samples;
+        if take > 0 {
+            sink.push(resampled, skip as usize, take as usize)?;
+        }
+        Ok(*position >= end_sample)
+    };
+
+    let mut reached_end = false;
+    let mut decoded_frame = frame::Audio::empty();
+    for (stream, packet) in input_context.packets() {
+        if reporter.is_cancelled() || reached_end {
+            break;
+        }
+        if stream.index() != stream_index {
+            continue;
+        }
+        // a corrupt packet only loses its own samples, like in playback
+        if decoder.send_packet(&packet).is_err() {
+            continue;
+        }
+        while received(decoder.receive_frame(&mut decoded_frame))? {
+            let position = position.get_or_insert_with(|| {
+                let frame_ms = decoded_frame
+                    .timestamp()
+                    .map_or(start_ms, |ts| timestamp_to_millisec(ts, time_base));
+                frame_ms * output_rate as i64 / 1000
+            });
+            decoded_frame.set_channel_layout(source_layout);
+            // leave room for upsampling and the resampler's internal delay
+            let capacity = decoded_frame.samples() * output_rate as usize / decoder.rate() as usize + 256;
+            let mut resampled = frame::Audio::new(output_format, capacity, output_layout);
+            resampler.run(&decoded_frame, &mut resampled)?;
+            reached_end = write_resampled(&resampled, position, &mut sink)?;
+            if reached_end {
+                break;
+            }
+            reporter.progress((*position * 1000 / output_rate as i64 - start_ms) as f32 / range_ms);
+            reporter.bytes_written(sink.bytes_written());
+        }
+    }
+    // a cancelled export still gets a valid (shorter) file
+    if !reached_end && !reporter.is_cancelled() {
+        decoder.send_eof()?;
+        let mut position = position.unwrap_or(start_sample);
+        while !reached_end && received(decoder.receive_frame(&mut decoded_frame))? {
+            decoded_frame.set_channel_layout(source_layout);
+            let capacity = decoded_frame.samples() * output_rate as usize / decoder.rate() as usize + 256;
+            let mut resampled = frame::Audio::new(output_format, capacity, output_layout);
+            resampler.run(&decoded_frame, &mut resampled)?;
+            reached_end = write_resampled(&resampled, &mut position, &mut sink)?;
+        }
+        // drain the samples the resampler is still holding on to
+        while !reached_end {
+            let mut resampled = frame::Audio::new(output_format, 4096, output_layout);
+            resampler.flush(&mut resampled)?;
+            if resampled.samples() == 0 {
+                break;
+            }
+            reached_end = write_resampled(&resampled, &mut position, &mut sink)?;
+        }
+    }
+
+    reporter.bytes_written(sink.bytes_written());
+    sink.finish()
+}
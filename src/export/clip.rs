@@ -0,0 +1,164 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::format::stream::Disposition;
+use ffmpeg::media::Type;
+use ffmpeg::{codec, encoder, format, Rational, Rescale};
+use anyhow::Result;
+use crate::export::ExportReporter;
+use crate::player::{millisec_to_timestamp, timestamp_to_millisec, AV_TIME_BASE_RATIONAL};
+
+/// Which streams of the input end up in an exported clip.
+#[derive(Clone, Debug, Default)]
+pub enum ClipStreams {
+    /// Every video, audio and subtitle stream (attached pictures excluded).
+    #[default]
+    All,
+    /// Only the streams with these indices.
+    Indices(Vec<usize>),
+}
+
+impl ClipStreams {
+    fn includes(&self, stream: &format::stream::Stream) -> bool {
+        match self {
+            ClipStreams::All => {
+                matches!(
+                    stream.parameters().medium(),
+                    Type::Video | Type::Audio | Type::Subtitle
+                ) && !stream.disposition().contains(Disposition::ATTACHED_PIC)
+            }
+            ClipStreams::Indices(indices) => indices.contains(&stream.index()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ClipStream {
+    output_index: usize,
+    input_time_base: Rational,
+    // set once a packet of this stream has passed the end of the clip
+    finished: bool,
+}
+
+/// Copy the packets between `start_ms` and `end_ms` of `input_path` into `output_path`, without
+/// re-encoding. The start snaps back to the keyframe at or before `start_ms`, so that the clip
+/// begins with a decodable frame, and all timestamps are shifted so that the first decoding timestamp is zero.
+pub fn export_clip(
+    input_path: &str,
+    start_ms: i64,
+    end_ms: i64,
+    output_path: &str,
+    streams: &ClipStreams,
+    reporter: &ExportReporter,
+) -> Result<()> {
+    if end_ms <= start_ms {
+        anyhow::bail!("clip end ({end_ms}ms) must be after its start ({start_ms}ms)");
+    }
+    let mut input_context = format::input(&input_path)?;
+    let mut output_context = format::output(&output_path)?;
+
+    let mut clip_streams: Vec<Option<ClipStream>> = Vec::new();
+    let mut output_index = 0;
+    for stream in input_context.streams() {
+        if !streams.includes(&stream) {
+            clip_streams.push(None);
+            continue;
+        }
+        let mut output_stream = output_context.add_stream(encoder::find(codec::Id::None))?;
+        output_stream.set_parameters(stream.parameters());
+        // the codec tag of the input container may be invalid in the output container
+        unsafe {
+            (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        clip_streams.push(Some(ClipStream {
+            output_index,
+            input_time_base: stream.time_base(),
+            finished: false,
+        }));
+        output_index += 1;
+    }
+    if output_index == 0 {
+        anyhow::bail!("no streams selected for the clip");
+    }
+    let keyframe_stream_index = input_context
+        .streams()
+        .best(Type::Video)
+        .map(|s| s.index())
+        .filter(|i| clip_streams[*i].is_some());
+
+    output_context.set_metadata(input_context.metadata().to_owned());
+    output_context.write_header()?;
+
+    let start_ts = millisec_to_timestamp(start_ms, AV_TIME_BASE_RATIONAL);
+    input_context.seek(start_ts, ..start_ts)?;
+
+    // the clip starts at the first keyframe of the video stream (or at the first packet, for audio-only
+    // clips), and its timestamps are shifted by the keyframe's DTS, both in `AV_TIME_BASE` units
+    let mut clip_start: Option<(i64, i64)> = None;
+    let clip_duration_ms = (end_ms - start_ms) as f32;
+    let mut bytes_written: u64 = 0;
+
+    for (stream, mut packet) in input_context.packets() {
+        if reporter.is_cancelled() {
+            break;
+        }
+        let Some(ClipStream { output_index, input_time_base: time_base, finished }) =
+            clip_streams[stream.index()]
+        else {
+            continue;
+        };
+        let Some(packet_ts) = packet.pts().or(packet.dts()) else {
+            continue;
+        };
+        let packet_ms = timestamp_to_millisec(packet_ts, time_base);
+
+        let (clip_start, clip_offset) = match clip_start {
+            Some(clip_start) => clip_start,
+            None => {
+                let is_keyframe_stream =
+                    keyframe_stream_index.is_none_or(|i| i == stream.index());
+                if !is_keyframe_stream || !packet.is_key() {
+                    continue;
+                }
+                let snapped_start = packet_ts.rescale(time_base, AV_TIME_BASE_RATIONAL);
+                // with B-frames the keyframe is decoded before it is shown, shifting by its PTS would make
+                // the first DTS negative, which strict muxers such as MP4 reject
+                let offset = packet.dts().unwrap_or(packet_ts).rescale(time_base, AV_TIME_BASE_RATIONAL);
+                clip_start = Some((snapped_start, offset));
+                (snapped_start, offset)
+            }
+        };
+        let start = clip_start.rescale(AV_TIME_BASE_RATIONAL, time_base);
+        let offset = clip_offset.rescale(AV_TIME_BASE_RATIONAL, time_base);
+
+        if packet_ts < start || finished {
+            // audio/subtitles from before the keyframe the clip was snapped to, or anything past the end
+            continue;
+        }
+        if packet.dts().map_or(packet_ms, |dts| timestamp_to_millisec(dts, time_base)) >= end_ms {
+            if let Some(clip_stream) = clip_streams[stream.index()].as_mut() {
+                clip_stream.finished = true;
+            }
+            if clip_streams.iter().flatten().all(|s| s.finished) {
+                break;
+            }
+            continue;
+        }
+
+        let output_time_base = output_context
+            .stream(output_index)
+            .unwrap()
+            .time_base();
+        packet.set_pts(packet.pts().map(|pts| pts - offset));
+        packet.set_dts(packet.dts().map(|dts| dts - offset));
+        packet.rescale_ts(time_base, output_time_base);
+        packet.set_position(-1);
+        packet.set_stream(output_index);
+        bytes_written += packet.size() as u64;
+        packet.write_interleaved(&mut output_context)?;
+
+        reporter.progress((packet_ms - start_ms) as f32 / clip_duration_ms);
+        reporter.bytes_written(bytes_written);
+    }
+
+    output_context.write_trailer()?;
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::{decoder, ChannelLayout};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use crate::player::Shared;
+
+pub mod animation;
+pub mod audio;
+pub mod clip;
+pub mod transcode;
+
+/// Messages sent from an export thread.
+pub enum ExportMessage {
+    /// Fraction of the export that is done, in `0.0..=1.0`.
+    Progress(f32),
+    /// The size of the output file so far, in bytes.
+    BytesWritten(u64),
+    /// The export completed successfully.
+    Finished,
+    /// The export was stopped by [`ExportJob::cancel`].
+    Cancelled,
+    /// The export was aborted with an error.
+    Failed(String),
+}
+
+type ExportSender = Sender<ExportMessage>;
+type ExportReciever = Receiver<ExportMessage>;
+
+/// Handed to the export function, to report progress and check for cancellation.
+#[derive(Clone)]
+pub struct ExportReporter {
+    sender: ExportSender,
+    cancelled: Shared<bool>,
+}
+
+impl ExportReporter {
+    /// Report the fraction of the export that is done, in `0.0..=1.0`.
+    pub fn progress(&self, progress: f32) {
+        let _ = self.sender.send(ExportMessage::Progress(progress.clamp(0., 1.)));
+    }
+    /// Report the size of the output so far, in bytes.
+    pub fn bytes_written(&self, bytes: u64) {
+        let _ = self.sender.send(ExportMessage::BytesWritten(bytes));
+    }
+    /// Has the export been cancelled? Export functions should check this regularly and stop early.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+}
+
+/// The possible states of an [`ExportJob`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExportState {
+    /// The export thread is still working.
+    Running,
+    /// The output file has been written.
+    Finished,
+    /// The export was cancelled before it finished.
+    Cancelled,
+    /// The export failed, with a description of the error.
+    Failed(String),
+}
+
+/// An export running on a background thread.
+pub struct ExportJob {
+    reciever: ExportReciever,
+    cancelled: Shared<bool>,
+    started: Instant,
+    progress: f32,
+    bytes_written: u64,
+    state: ExportState,
+}
+
+impl ExportJob {
+    /// Run `export` on a new thread. The closure reports its progress through the given [`ExportReporter`].
+    pub fn spawn(export: impl FnOnce(&ExportReporter) -> Result<()> + Send + 'static) -> Self {
+        let (sender, reciever) = channel();
+        let cancelled = Shared::new(false);
+        let reporter = ExportReporter {
+            sender: sender.clone(),
+            cancelled: cancelled.clone(),
+        };
+        std::thread::spawn(move || {
+            let message = match export(&reporter) {
+                Ok(()) if reporter.is_cancelled() => ExportMessage::Cancelled,
+                Ok(()) => ExportMessage::Finished,
+                Err(e) => ExportMessage::Failed(e.to_string()),
+            };
+            let _ = sender.send(message);
+        });
+        Self {
+            reciever,
+            cancelled,
+            started: Instant::now(),
+            progress: 0.,
+            bytes_written: 0,
+            state: ExportState::Running,
+        }
+    }
+
+    /// Process messages from the export thread. Should be called every frame while the job is shown.
+    pub fn poll(&mut self) {
+        while let Ok(message) = self.reciever.try_recv() {
+            match message {
+                ExportMessage::Progress(progress) => self.progress = progress,
+                ExportMessage::BytesWritten(bytes) => self.bytes_written = bytes,
+                ExportMessage::Finished => {
+                    self.progress = 1.;
+                    self.state = ExportState::Finished;
+                }
+                ExportMessage::Cancelled => self.state = ExportState::Cancelled,
+                ExportMessage::Failed(e) => self.state = ExportState::Failed(e),
+            }
+        }
+    }
+
+    /// Ask the export thread to stop. The job ends up [`ExportState::Cancelled`] once it has.
+    pub fn cancel(&mut self) {
+        self.cancelled.set(true);
+    }
+
+    /// Fraction of the export that is done, in `0.0..=1.0`.
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    /// The size of the output so far, in bytes.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Estimated time until the export finishes, extrapolated from the progress so far.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.is_done() || self.progress <= 0. {
+            return None;
+        }
+        let elapsed = self.started.elapsed().as_secs_f32();
+        Some(Duration::from_secs_f32(elapsed / self.progress * (1. - self.progress)))
+    }
+
+    /// The state of the export.
+    pub fn state(&self) -> &ExportState {
+        &self.state
+    }
+
+    /// Has the export stopped, either successfully or not?
+    pub fn is_done(&self) -> bool {
+        !matches!(self.state, ExportState::Running)
+    }
+}
+
+/// Format a byte count for display, e.g. `12.3 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    let bytes = bytes as f64;
+    if bytes >= 1e9 {
+        format!("{:.2} GB", bytes / 1e9)
+    } else if bytes >= 1e6 {
+        format!("{:.1} MB", bytes / 1e6)
+    } else {
+        format!("{:.0} kB", bytes / 1e3)
+    }
+}
+
+/// The channel layout of the decoder, or the default layout for its channel count if the
+/// stream doesn't specify one (which swresample can't convert from).
+pub(crate) fn source_channel_layout(decoder: &decoder::Audio) -> ChannelLayout {
+    let layout = decoder.channel_layout();
+    if layout.is_empty() {
+        ChannelLayout::default(decoder.channels() as i32)
+    } else {
+        layout
+    }
+}
+
+/// Whether a `receive_frame`/`receive_packet` call produced something. Receive loops end on EAGAIN and EOF,
+/// anything else is a real error and is passed on.
+pub(crate) fn received(result: Result<(), ffmpeg::Error>) -> Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(ffmpeg::Error::Eof) => Ok(false),
+        Err(ffmpeg::Error::Other { errno }) if errno == ffmpeg::error::EAGAIN => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
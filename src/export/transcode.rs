@@ -0,0 +1,699 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::format::Pixel;
+use ffmpeg::media::Type;
+use ffmpeg::software::scaling::{context::Context as ScaleContext, flag::Flags};
+use ffmpeg::{codec, decoder, encoder, filter, format, frame, Dictionary, Packet, Rational, Rescale};
+use anyhow::Result;
+use crate::export::{received, source_channel_layout, ExportReporter};
+use crate::player::{timestamp_to_millisec, AV_TIME_BASE_RATIONAL};
+
+/// Video codecs the transcoder can encode to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VideoCodec {
+    H264,
+    H265,
+    Vp9,
+}
+
+impl VideoCodec {
+    fn encoder_name(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::H265 => "libx265",
+            VideoCodec::Vp9 => "libvpx-vp9",
+        }
+    }
+}
+
+/// Audio codecs the transcoder can encode to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioCodec {
+    Aac,
+    Opus,
+    Mp3,
+}
+
+impl AudioCodec {
+    fn encoder_name(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "libopus",
+            AudioCodec::Mp3 => "libmp3lame",
+        }
+    }
+}
+
+/// How the bitrate of the video encoder is controlled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateControl {
+    /// Constant quality, lower is better. Sensible values are around 18-28 for x264/x265, 30-40 for vp9.
+    Crf(u8),
+    /// Average bitrate, in bits per second.
+    Bitrate(usize),
+}
+
+/// The settings of a re-encoding export.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncodePreset {
+    pub video_codec: VideoCodec,
+    pub rate_control: RateControl,
+    /// Scale the video down to this height (keeping the aspect ratio), if it is taller.
+    pub max_height: Option<u32>,
+    /// Resample the video to this framerate, by dropping frames.
+    pub framerate: Option<u32>,
+    pub audio_codec: AudioCodec,
+    /// The audio bitrate, in bits per second.
+    pub audio_bitrate: usize,
+}
+
+impl EncodePreset {
+    /// H.264/AAC at 720p, plays practically everywhere.
+    pub fn h264_720p() -> Self {
+        Self {
+            video_codec: VideoCodec::H264,
+            rate_control: RateControl::Crf(23),
+            max_height: Some(720),
+            framerate: None,
+            audio_codec: AudioCodec::Aac,
+            audio_bitrate: 128_000,
+        }
+    }
+    /// H.264/AAC at 1080p.
+    pub fn h264_1080p() -> Self {
+        Self {
+            max_height: Some(1080),
+            audio_bitrate: 192_000,
+            ..Self::h264_720p()
+        }
+    }
+    /// H.265/AAC at 1080p, about half the size of [`EncodePreset::h264_1080p`].
+    pub fn h265_1080p() -> Self {
+        Self {
+            video_codec: VideoCodec::H265,
+            rate_control: RateControl::Crf(28),
+            ..Self::h264_1080p()
+        }
+    }
+    /// VP9/Opus at 720p, for webm.
+    pub fn vp9_720p() -> Self {
+        Self {
+            video_codec: VideoCodec::Vp9,
+            rate_control: RateControl::Crf(33),
+            audio_codec: AudioCodec::Opus,
+            ..Self::h264_720p()
+        }
+    }
+    /// The built-in presets, with a display name and the file extension they are meant for.
+    pub fn presets() -> Vec<(&'static str, &'static str, EncodePreset)> {
+        vec![
+            ("H.264 720p", "mp4", Self::h264_720p()),
+            ("H.264 1080p", "mp4", Self::h264_1080p()),
+            ("H.265 1080p", "mp4", Self::h265_1080p()),
+            ("VP9 720p", "webm", Self::vp9_720p()),
+        ]
+    }
+}
+
+/// Scale `width`x`height` to fit in `max_height`, keeping both dimensions even for chroma subsampling.
+fn output_size(width: u32, height: u32, max_height: Option<u32>) -> (u32, u32) {
+    let (width, height) = match max_height {
+        Some(max_height) if height > max_height => {
+            let scaled_width = (width as f64 * max_height as f64 / height as f64).round() as u32;
+            (scaled_width, max_height)
+        }
+        _ => (width, height),
+    };
+    (width & !1, height & !1)
+}
+
+struct VideoTranscoder {
+    output_index: usize,
+    input_time_base: Rational,
+    decoder: decoder::Video,
+    encoder: encoder::Video,
+    encoder_time_base: Rational,
+    scaler: Option<ScaleContext>,
+    // only set when resampling the framerate, used to drop frames that land on an already encoded pts
+    last_pts: Option<i64>,
+    resample_framerate: bool,
+}
+
+impl VideoTranscoder {
+    fn new(
+        stream: &format::stream::Stream,
+        output_context: &mut format::context::Output,
+        output_index: usize,
+        preset: &EncodePreset,
+    ) -> Result<Self> {
+        let decoder = codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .video()?;
+        let codec = encoder::find_by_name(preset.video_codec.encoder_name()).ok_or(anyhow::anyhow!(
+            "encoder {} is not available",
+            preset.video_codec.encoder_name()
+        ))?;
+        let global_header = output_context
+            .format()
+            .flags()
+            .contains(format::Flags::GLOBAL_HEADER);
+        let mut output_stream = output_context.add_stream(codec)?;
+
+        let (width, height) = output_size(decoder.width(), decoder.height(), preset.max_height);
+        let (encoder_time_base, framerate) = match preset.framerate {
+            Some(fps) => (Rational(1, fps as i32), Rational(fps as i32, 1)),
+            None => (stream.time_base(), stream.avg_frame_rate()),
+        };
+
+        let mut encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()?;
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_aspect_ratio(decoder.aspect_ratio());
+        encoder.set_format(Pixel::YUV420P);
+        encoder.set_time_base(encoder_time_base);
+        encoder.set_frame_rate(Some(framerate));
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+        let mut options = Dictionary::new();
+        match preset.rate_control {
+            RateControl::Crf(crf) => {
+                options.set("crf", &crf.to_string());
+                if preset.video_codec == VideoCodec::Vp9 {
+                    // libvpx only treats crf as constant quality with an unconstrained bitrate
+                    encoder.set_bit_rate(0);
+                }
+            }
+            RateControl::Bitrate(bitrate) => encoder.set_bit_rate(bitrate),
+        }
+        let encoder = encoder.open_with(options)?;
+        output_stream.set_parameters(&encoder);
+        output_stream.set_time_base(encoder_time_base);
+
+        Ok(Self {
+            output_index,
+            input_time_base: stream.time_base(),
+            decoder,
+            encoder,
+            encoder_time_base,
+            scaler: None,
+            last_pts: None,
+            resample_framerate: preset.framerate.is_some(),
+        })
+    }
+
+    /// Returns the timestamp of the last decoded frame, in milliseconds.
+    fn receive_frames(&mut self, output_context: &mut format::context::Output, bytes_written: &mut u64) -> Result<Option<i64>> {
+        let mut decoded_frame = frame::Video::empty();
+        let mut last_ms = None;
+        while received(self.decoder.receive_frame(&mut decoded_frame))? {
+            let Some(timestamp) = decoded_frame.timestamp() else {
+                continue;
+            };
+            last_ms = Some(timestamp_to_millisec(timestamp, self.input_time_base));
+            let pts = timestamp.rescale(self.input_time_base, self.encoder_time_base);
+            if self.resample_framerate {
+                if self.last_pts.is_some_and(|last_pts| pts <= last_pts) {
+                    continue;
+                }
+                self.last_pts = Some(pts);
+            }
+
+            // the decoded size or format can change mid-stream, e.g. at a resolution switch
+            let input_changed = self.scaler.as_ref().is_none_or(|scaler| {
+                let input = scaler.input();
+                (input.format, input.width, input.height)
+                    != (decoded_frame.format(), decoded_frame.width(), decoded_frame.height())
+            });
+            if input_changed {
+                self.scaler = Some(ScaleContext::get(
+                    decoded_frame.format(),
+                    decoded_frame.width(),
+                    decoded_frame.height(),
+                    self.encoder.format(),
+                    self.encoder.width(),
+                    self.encoder.height(),
+                    Flags::BICUBIC,
+                )?);
+            }
+            let scaler = self.scaler.as_mut().unwrap();
+            let mut scaled_frame = frame::Video::empty();
+            scaler.run(&decoded_frame, &mut scaled_frame)?;
+            scaled_frame.set_pts(Some(pts));
+            self.encoder.send_frame(&scaled_frame)?;
+            self.receive_packets(output_context, bytes_written)?;
+        }
+        Ok(last_ms)
+    }
+
+    fn receive_packets(&mut self, output_context: &mut format::context::Output, bytes_written: &mut u64) -> Result<()> {
+        let output_time_base = output_context.stream(self.output_index).unwrap().time_base();
+        let mut packet = Packet::empty();
+        while received(self.encoder.receive_packet(&mut packet))? {
+            packet.set_stream(self.output_index);
+            packet.rescale_ts(self.encoder_time_base, output_time_base);
+            *bytes_written += packet.size() as u64;
+            packet.write_interleaved(output_context)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, output_context: &mut format::context::Output, bytes_written: &mut u64) -> Result<()> {
+        self.decoder.send_eof()?;
+        self.receive_frames(output_context, bytes_written)?;
+        self.encoder.send_eof()?;
+        self.receive_packets(output_context, bytes_written)
+    }
+}
+
+struct AudioTranscoder {
+    output_index: usize,
+    decoder: decoder::Audio,
+    encoder: encoder::Audio,
+    // converts sample format, rate and layout, and cuts the samples into frames of the encoder's frame size
+    filter: filter::Graph,
+    encoder_time_base: Rational,
+}
+
+impl AudioTranscoder {
+    fn new(
+        stream: &format::stream::Stream,
+        output_context: &mut format::context::Output,
+        output_index: usize,
+        preset: &EncodePreset,
+    ) -> Result<Self> {
+        let decoder = codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .audio()?;
+        let codec = encoder::find_by_name(preset.audio_codec.encoder_name())
+            .ok_or(anyhow::anyhow!(
+                "encoder {} is not available",
+                preset.audio_codec.encoder_name()
+            ))?
+            .audio()?;
+        let global_header = output_context
+            .format()
+            .flags()
+            .contains(format::Flags::GLOBAL_HEADER);
+        let mut output_stream = output_context.add_stream(codec)?;
+
+        let channel_layout = codec
+            .channel_layouts()
+            .map(|layouts| layouts.best(source_channel_layout(&decoder).channels()))
+            .unwrap_or(ffmpeg::ChannelLayout::STEREO);
+        let sample_format = codec
+            .formats()
+            .and_then(|mut formats| formats.next())
+            .unwrap_or(format::Sample::F32(format::sample::Type::Planar));
+        // opus only supports 48kHz
+        let rate = match preset.audio_codec {
+            AudioCodec::Opus => 48_000,
+            _ => decoder.rate(),
+        };
+        let encoder_time_base = Rational(1, rate as i32);
+
+        let mut encoder = codec::context::Context::new_with_codec(*codec)
+            .encoder()
+            .audio()?;
+        encoder.set_rate(rate as i32);
+        encoder.set_channel_layout(channel_layout);
+        encoder.set_format(sample_format);
+        encoder.set_bit_rate(preset.audio_bitrate);
+        encoder.set_time_base(encoder_time_base);
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+        let encoder = encoder.open_as(codec)?;
+        output_stream.set_parameters(&encoder);
+        output_stream.set_time_base(encoder_time_base);
+
+        let filter = audio_conversion_filter(&decoder, stream.time_base(), &encoder)?;
+
+        Ok(Self {
+            output_index,
+            decoder,
+            encoder,
+            filter,
+            encoder_time_base,
+        })
+    }
+
+    fn receive_frames(&mut self, output_context: &mut format::context::Output, bytes_written: &mut u64) -> Result<()> {
+        let mut decoded_frame = frame::Audio::empty();
+        while received(self.decoder.receive_frame(&mut decoded_frame))? {
+            let timestamp = decoded_frame.timestamp();
+            decoded_frame.set_pts(timestamp);
+            self.filter.get("in").unwrap().source().add(&decoded_frame)?;
+            self.receive_filtered_frames(output_context, bytes_written)?;
+        }
+        Ok(())
+    }
+
+    fn receive_filtered_frames(&mut self, output_context: &mut format::context::Output, bytes_written: &mut u64) -> Result<()> {
+        let mut filtered_frame = frame::Audio::empty();
+        while received(self.filter.get("out").unwrap().sink().frame(&mut filtered_frame))? {
+            self.encoder.send_frame(&filtered_frame)?;
+            self.receive_packets(output_context, bytes_written)?;
+        }
+        Ok(())
+    }
+
+    fn receive_packets(&mut self, output_context: &mut format::context::Output, bytes_written: &mut u64) -> Result<()> {
+        let output_time_base = output_context.stream(self.output_index).unwrap().time_base();
+        let mut packet = Packet::empty();
+        while received(self.encoder.receive_packet(&mut packet))? {
+            packet.set_stream(self.output_index);
+            packet.rescale_ts(self.encoder_time_base, output_time_base);
+            *bytes_written += packet.size() as u64;
+            packet.write_interleaved(output_context)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, output_context: &mut format::context::Output, bytes_written: &mut u64) -> Result<()> {
+        self.decoder.send_eof()?;
+        self.receive_frames(output_context, bytes_written)?;
+        self.filter.get("in").unwrap().source().flush()?;
+        self.receive_filtered_frames(output_context, bytes_written)?;
+        self.encoder.send_eof()?;
+        self.receive_packets(output_context, bytes_written)
+    }
+}
+
+/// Build an `abuffer -> abuffersink` graph that converts the decoder's output to what the encoder accepts,
+/// including its time base (`1/rate`), so the filtered frames can be sent to the encoder as they are.
+pub(crate) fn audio_conversion_filter(
+    decoder: &decoder::Audio,
+    input_time_base: Rational,
+    encoder: &encoder::Audio,
+) -> Result<filter::Graph> {
+    let mut graph = filter::Graph::new();
+    let args = format!(
+        "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+        input_time_base,
+        decoder.rate(),
+        decoder.format().name(),
+        source_channel_layout(decoder).bits()
+    );
+    graph.add(&filter::find("abuffer").unwrap(), "in", &args)?;
+    graph.add(&filter::find("abuffersink").unwrap(), "out", "")?;
+    {
+        let mut out = graph.get("out").unwrap();
+        out.set_sample_format(encoder.format());
+        out.set_channel_layout(encoder.channel_layout());
+        out.set_sample_rate(encoder.rate());
+    }
+    // without resampling the frames would keep the input stream's time base, e.g. 1/1000 for mkv
+    graph
+        .output("in", 0)?
+        .input("out", 0)?
+        .parse(&format!("asettb=1/{}", encoder.rate()))?;
+    graph.validate()?;
+
+    let variable_frame_size = encoder.codec().is_some_and(|codec| {
+        codec
+            .capabilities()
+            .contains(codec::capabilities::Capabilities::VARIABLE_FRAME_SIZE)
+    });
+    if !variable_frame_size {
+        graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .set_frame_size(encoder.frame_size());
+    }
+    Ok(graph)
+}
+
+/// Re-encode the best video and audio streams of `input_path` into `output_path` with the given preset.
+/// The container is chosen by the extension of `output_path`.
+pub fn transcode(
+    input_path: &str,
+    output_path: &str,
+    preset: &EncodePreset,
+    reporter: &ExportReporter,
+) -> Result<()> {
+    let mut input_context = format::input(&input_path)?;
+    let mut output_context = format::output(&output_path)?;
+    let duration_ms = timestamp_to_millisec(input_context.duration(), AV_TIME_BASE_RATIONAL);
+
+    let mut output_index = 0;
+    let mut video_transcoder = match input_context.streams().best(Type::Video) {
+        Some(stream) => {
+            let transcoder = VideoTranscoder::new(&stream, &mut output_context, output_index, preset)?;
+            output_index += 1;
+            Some((stream.index(), transcoder))
+        }
+        None => None,
+    };
+    let mut audio_transcoder = match input_context.streams().best(Type::Audio) {
+        Some(stream) => Some((
+            stream.index(),
+            AudioTranscoder::new(&stream, &mut output_context, output_index, preset)?,
+        )),
+        None => None,
+    };
+    if video_transcoder.is_none() && audio_transcoder.is_none() {
+        anyhow::bail!("no video or audio stream to transcode");
+    }
+
+    output_context.set_metadata(input_context.metadata().to_owned());
+    output_context.write_header()?;
+
+    let mut bytes_written = 0;
+    for (stream, packet) in input_context.packets() {
+        if reporter.is_cancelled() {
+            break;
+        }
+        if let Some((index, transcoder)) = video_transcoder.as_mut() {
+            if stream.index() == *index {
+                // a corrupt packet only loses its own frames, like in playback
+                if transcoder.decoder.send_packet(&packet).is_err() {
+                    continue;
+                }
+                if let Some(elapsed_ms) = transcoder.receive_frames(&mut output_context, &mut bytes_written)? {
+                    reporter.progress(elapsed_ms as f32 / duration_ms as f32);
+                    reporter.bytes_written(bytes_written);
+                }
+            }
+        }
+        if let Some((index, transcoder)) = audio_transcoder.as_mut() {
+            if stream.index() == *index {
+                if transcoder.decoder.send_packet(&packet).is_err() {
+                    continue;
+                }
+                transcoder.receive_frames(&mut output_context, &mut bytes_written)?;
+                if video_transcoder.is_none() {
+                    if let Some(pts) = packet.pts() {
+                        reporter.progress(timestamp_to_millisec(pts, stream.time_base()) as f32 / duration_ms as f32);
+                        reporter.bytes_written(bytes_written);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some((_, transcoder)) = video_transcoder.as_mut() {
+        transcoder.finish(&mut output_context, &mut bytes_written)?;
+    }
+    if let Some((_, transcoder)) = audio_transcoder.as_mut() {
+        transcoder.finish(&mut output_context, &mut bytes_written)?;
+    }
+    output_context.write_trailer()?;
+    reporter.bytes_written(bytes_written);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::Shared;
+    use ffmpeg::ChannelLayout;
+    use std::f32::consts::TAU;
+
+    const TEST_FPS: i32 = 25;
+    const TEST_RATE: i32 = 48_000;
+
+    #[test]
+    fn output_size_scales_down_to_max_height() {
+        assert_eq!(output_size(1920, 1080, Some(720)), (1280, 720));
+        assert_eq!(output_size(720, 576, Some(480)), (600, 480));
+    }
+
+    #[test]
+    fn output_size_never_scales_up() {
+        assert_eq!(output_size(640, 360, Some(720)), (640, 360));
+        assert_eq!(output_size(640, 360, None), (640, 360));
+    }
+
+    #[test]
+    fn output_size_is_even() {
+        assert_eq!(output_size(1001, 501, None), (1000, 500));
+        // 853.33 rounds to 853, which is made even
+        assert_eq!(output_size(1280, 720, Some(480)), (852, 480));
+    }
+
+    #[test]
+    fn presets_match_their_extensions() {
+        let presets = EncodePreset::presets();
+        for (name, extension, preset) in &presets {
+            let expected_audio = if *extension == "webm" { AudioCodec::Opus } else { AudioCodec::Aac };
+            assert_eq!(preset.audio_codec, expected_audio, "{name}");
+            assert_eq!(preset.video_codec == VideoCodec::Vp9, *extension == "webm", "{name}");
+        }
+        let names: std::collections::HashSet<_> = presets.iter().map(|(name, _, _)| name).collect();
+        assert_eq!(names.len(), presets.len());
+    }
+
+    #[test]
+    fn derived_presets_keep_their_base_settings() {
+        let h265 = EncodePreset::h265_1080p();
+        assert_eq!(h265.max_height, Some(1080));
+        assert_eq!(h265.audio_bitrate, EncodePreset::h264_1080p().audio_bitrate);
+        assert_eq!(EncodePreset::vp9_720p().max_height, Some(720));
+    }
+
+    fn reporter() -> ExportReporter {
+        let (sender, _) = std::sync::mpsc::channel();
+        ExportReporter {
+            sender,
+            cancelled: Shared::new(false),
+        }
+    }
+
+    fn write_packets<E: std::ops::DerefMut<Target = encoder::Encoder>>(
+        encoder: &mut E,
+        output: &mut format::context::Output,
+        stream_index: usize,
+        encoder_time_base: Rational,
+    ) -> Result<()> {
+        let stream_time_base = output.stream(stream_index).unwrap().time_base();
+        let mut packet = Packet::empty();
+        while received(encoder.receive_packet(&mut packet))? {
+            packet.set_stream(stream_index);
+            packet.rescale_ts(encoder_time_base, stream_time_base);
+            packet.write_interleaved(output)?;
+        }
+        Ok(())
+    }
+
+    /// Write `seconds` of a moving gradient and a sine tone to `path`, losslessly, as mkv, whose 1/1000 time
+    /// base differs from the audio's 1/rate.
+    fn write_synthetic_input(path: &str, seconds: i32) -> Result<()> {
+        let mut output = format::output(&path)?;
+        let video_codec = encoder::find(codec::Id::FFV1).ok_or(anyhow::anyhow!("no ffv1 encoder"))?;
+        let audio_codec = encoder::find(codec::Id::PCM_S16LE).ok_or(anyhow::anyhow!("no pcm encoder"))?;
+        let video_time_base = Rational(1, TEST_FPS);
+        let audio_time_base = Rational(1, TEST_RATE);
+
+        let mut video = codec::context::Context::new_with_codec(video_codec).encoder().video()?;
+        video.set_width(64);
+        video.set_height(48);
+        video.set_format(Pixel::YUV420P);
+        video.set_time_base(video_time_base);
+        video.set_frame_rate(Some(Rational(TEST_FPS, 1)));
+        let mut video = video.open_as(video_codec)?;
+        output.add_stream(video_codec)?.set_parameters(&video);
+
+        let mut audio = codec::context::Context::new_with_codec(audio_codec).encoder().audio()?;
+        audio.set_rate(TEST_RATE);
+        audio.set_channel_layout(ChannelLayout::STEREO);
+        audio.set_format(format::Sample::I16(format::sample::Type::Packed));
+        audio.set_time_base(audio_time_base);
+        let mut audio = audio.open_as(audio_codec)?;
+        output.add_stream(audio_codec)?.set_parameters(&audio);
+
+        output.write_header()?;
+        let samples_per_frame = (TEST_RATE / TEST_FPS) as usize;
+        for index in 0..(seconds * TEST_FPS) as usize {
+            let mut frame = frame::Video::new(Pixel::YUV420P, 64, 48);
+            for plane in 0..3 {
+                let stride = frame.stride(plane);
+                for (offset, byte) in frame.data_mut(plane).iter_mut().enumerate() {
+                    *byte = (offset % stride + offset / stride + index * 4) as u8;
+                }
+            }
+            frame.set_pts(Some(index as i64));
+            video.send_frame(&frame)?;
+            write_packets(&mut video, &mut output, 0, video_time_base)?;
+
+            let mut samples = frame::Audio::new(
+                format::Sample::I16(format::sample::Type::Packed),
+                samples_per_frame,
+                ChannelLayout::STEREO,
+            );
+            samples.set_rate(TEST_RATE as u32);
+            let first_sample = index * samples_per_frame;
+            for (sample, value) in samples.plane_mut::<(i16, i16)>(0).iter_mut().enumerate() {
+                let t = (first_sample + sample) as f32 / TEST_RATE as f32;
+                let level = ((t * 440. * TAU).sin() * 8000.) as i16;
+                *value = (level, level);
+            }
+            samples.set_pts(Some(first_sample as i64));
+            audio.send_frame(&samples)?;
+            write_packets(&mut audio, &mut output, 1, audio_time_base)?;
+        }
+        video.send_eof()?;
+        write_packets(&mut video, &mut output, 0, video_time_base)?;
+        audio.send_eof()?;
+        write_packets(&mut audio, &mut output, 1, audio_time_base)?;
+        output.write_trailer()?;
+        Ok(())
+    }
+
+    /// The end of the last packet of each stream of `path`, in seconds, by stream type.
+    fn stream_ends(path: &str) -> Result<Vec<(Type, f64)>> {
+        let mut input = format::input(&path)?;
+        let mut ends: Vec<(Type, f64)> = input
+            .streams()
+            .map(|stream| (stream.parameters().medium(), 0.))
+            .collect();
+        for (stream, packet) in input.packets() {
+            let Some(pts) = packet.pts() else {
+                continue;
+            };
+            let end = (pts + packet.duration()) as f64 * f64::from(stream.time_base());
+            let (_, stream_end) = &mut ends[stream.index()];
+            *stream_end = stream_end.max(end);
+        }
+        Ok(ends)
+    }
+
+    #[test]
+    fn transcode_round_trip_keeps_audio_timing() {
+        ffmpeg::init().unwrap();
+        if encoder::find_by_name(VideoCodec::H264.encoder_name()).is_none() {
+            eprintln!("skipping, libx264 is not available");
+            return;
+        }
+        let directory = std::env::temp_dir();
+        let input_path = directory.join(format!("transcode_test_input_{}.mkv", std::process::id()));
+        let output_path = directory.join(format!("transcode_test_output_{}.mp4", std::process::id()));
+        let (input_path, output_path) = (input_path.to_str().unwrap(), output_path.to_str().unwrap());
+        write_synthetic_input(input_path, 2).unwrap();
+
+        let preset = EncodePreset {
+            max_height: Some(24),
+            ..EncodePreset::h264_720p()
+        };
+        let result = transcode(input_path, output_path, &preset, &reporter());
+        let ends = stream_ends(output_path);
+        let output_video = format::input(&output_path).ok().and_then(|input| {
+            let stream = input.streams().best(Type::Video)?;
+            let decoder = codec::context::Context::from_parameters(stream.parameters()).ok()?.decoder().video().ok()?;
+            Some((decoder.width(), decoder.height()))
+        });
+        let _ = std::fs::remove_file(input_path);
+        let _ = std::fs::remove_file(output_path);
+
+        result.unwrap();
+        assert_eq!(output_video, Some((32, 24)));
+        let ends = ends.unwrap();
+        for medium in [Type::Video, Type::Audio] {
+            let (_, end) = ends.iter().find(|(m, _)| *m == medium).expect("stream missing from the output");
+            // the encoder's priming and padding may add a few frames, a wrong time base is off by a factor
+            assert!((1.8..2.3).contains(end), "{medium:?} ends at {end}s instead of 2s");
+        }
+    }
+}
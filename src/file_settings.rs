@@ -0,0 +1,60 @@
+use crate::picture::PictureAdjustments;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use anyhow::Result;
+
+/// What is remembered about each file that was played, so that it picks up where it was left.
+#[derive(PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileSettings {
+    /// Where playback stopped, in milliseconds.
+    pub resume_position_ms: i64,
+    pub picture: PictureAdjustments,
+}
+
+impl FileSettings {
+    /// The settings remembered for `media_path`, if there are any.
+    pub fn load(media_path: &str) -> Option<Self> {
+        read_store().remove(&store_key(media_path))
+    }
+
+    /// Remember the settings for `media_path`. Settings equal to the defaults are forgotten instead.
+    pub fn save(&self, media_path: &str) -> Result<()> {
+        let path = store_path().ok_or(anyhow::anyhow!("no config directory to save settings in"))?;
+        let mut store = read_store();
+        if *self == Self::default() {
+            store.remove(&store_key(media_path));
+        } else {
+            store.insert(store_key(media_path), *self);
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&store)?)?;
+        Ok(())
+    }
+}
+
+/// The same file opened through different relative paths shares its settings.
+fn store_key(media_path: &str) -> String {
+    std::fs::canonicalize(media_path)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or(media_path.to_string())
+}
+
+/// Where the settings of every file are kept.
+fn store_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .or(std::env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or(std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("testffmpeg").join("file_settings.json"))
+}
+
+fn read_store() -> HashMap<String, FileSettings> {
+    store_path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,236 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::format::{Pixel, Sample};
+use ffmpeg::frame::{Audio, Frame, Video};
+use ffmpeg::{filter, ChannelLayout, Rational};
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use crate::media_info::describe_channel_layout;
+
+/// The kind of frames a [`FilterGraph`] was configured for. It has to be rebuilt if they change.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum FilterInput {
+    Video { width: u32, height: u32, format: Pixel },
+    Audio { format: Sample, channels: u32, rate: u32 },
+}
+
+/// A libavfilter graph with a single input and output, e.g. `yadif=mode=send_field` or `atempo=1.5`.
+pub struct FilterGraph {
+    graph: filter::Graph,
+    spec: String,
+    input: FilterInput,
+}
+
+impl FilterGraph {
+    fn build(spec: &str, source: &str, source_args: &str, sink: &str, input: FilterInput) -> Result<Self> {
+        let mut graph = filter::Graph::new();
+        let source_filter = filter::find(source).ok_or(anyhow::anyhow!("libavfilter has no {source} filter"))?;
+        let sink_filter = filter::find(sink).ok_or(anyhow::anyhow!("libavfilter has no {sink} filter"))?;
+        graph.add(&source_filter, "in", source_args)?;
+        graph.add(&sink_filter, "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        graph.validate()?;
+        Ok(Self {
+            graph,
+            spec: spec.to_string(),
+            input,
+        })
+    }
+
+    /// Build a graph running `spec` on frames like `frame`, whose timestamps are in `time_base`.
+    pub fn video(spec: &str, frame: &Video, time_base: Rational) -> Result<Self> {
+        let aspect_ratio = frame.aspect_ratio();
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+            frame.width(),
+            frame.height(),
+            ffmpeg::ffi::AVPixelFormat::from(frame.format()) as i32,
+            time_base.numerator(),
+            time_base.denominator().max(1),
+            aspect_ratio.numerator().max(1),
+            aspect_ratio.denominator().max(1),
+        );
+        let input = FilterInput::Video {
+            width: frame.width(),
+            height: frame.height(),
+            format: frame.format(),
+        };
+        Self::build(spec, "buffer", &args, "buffersink", input)
+    }
+
+    /// Build a graph running `spec` on audio in the given format. Whatever the filters do, the audio comes
+    /// out in the same format, so that it can be played the same way.
+    pub fn audio(spec: &str, format: Sample, channel_layout: ChannelLayout, rate: u32) -> Result<Self> {
+        let sample_format = ffmpeg::ffi::AVSampleFormat::from(format) as i32;
+        let layout = describe_channel_layout(channel_layout);
+        let args = format!("time_base=1/{rate}:sample_rate={rate}:sample_fmt={sample_format}:channel_layout={layout}");
+        let format_name = unsafe {
+            std::ffi::CStr::from_ptr(ffmpeg::ffi::av_get_sample_fmt_name(format.into()))
+                .to_string_lossy()
+                .into_owned()
+        };
+        let full_spec = format!("{spec},aformat=sample_fmts={format_name}:sample_rates={rate}:channel_layouts={layout}");
+        let input = FilterInput::Audio {
+            format,
+            channels: channel_layout.channels() as u32,
+            rate,
+        };
+        let mut graph = Self::build(&full_spec, "abuffer", &args, "abuffersink", input)?;
+        graph.spec = spec.to_string();
+        Ok(graph)
+    }
+
+    /// The filter description the graph was built from.
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    /// Can `frame` be pushed into this graph running `spec`, or does it need to be rebuilt?
+    pub fn accepts_video(&self, spec: &str, frame: &Video) -> bool {
+        let input = FilterInput::Video {
+            width: frame.width(),
+            height: frame.height(),
+            format: frame.format(),
+        };
+        self.spec == spec && self.input == input
+    }
+
+    /// Can `frame` be pushed into this graph running `spec`, or does it need to be rebuilt?
+    pub fn accepts_audio(&self, spec: &str, frame: &Audio) -> bool {
+        let input = FilterInput::Audio {
+            format: frame.format(),
+            channels: frame.channels() as u32,
+            rate: frame.rate(),
+        };
+        self.spec == spec && self.input == input
+    }
+
+    /// Push a frame in and collect every frame that comes out. Filters that look ahead (like deinterlacers)
+    /// return nothing until they have seen enough frames, others may return several.
+    pub fn run_video(&mut self, frame: &Video) -> Result<Vec<Video>> {
+        self.run(frame, Video::empty)
+    }
+
+    /// Same as [`FilterGraph::run_video`], for audio.
+    pub fn run_audio(&mut self, frame: &Audio) -> Result<Vec<Audio>> {
+        self.run(frame, Audio::empty)
+    }
+
+    fn run<F: DerefMut<Target = Frame>>(&mut self, frame: &Frame, empty: fn() -> F) -> Result<Vec<F>> {
+        self.graph
+            .get("in")
+            .ok_or(anyhow::anyhow!("filter graph has no input"))?
+            .source()
+            .add(frame)?;
+        let mut sink = self.graph.get("out").ok_or(anyhow::anyhow!("filter graph has no output"))?;
+        let mut filtered_frames = Vec::new();
+        loop {
+            let mut filtered = empty();
+            match sink.sink().frame(&mut filtered) {
+                Ok(()) => filtered_frames.push(filtered),
+                Err(ffmpeg::Error::Eof) => break,
+                Err(ffmpeg::Error::Other { errno }) if errno == ffmpeg::error::EAGAIN => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(filtered_frames)
+    }
+}
+
+/// A filter stage of a streamer, configured by a filter string that may change at any time (see
+/// [`PlayerOptions::video_filter`](crate::player::PlayerOptions::video_filter)). Frames pass through
+/// unchanged while the string is empty or invalid; why it is invalid is reported through `error`.
+pub(crate) struct ConfigurableFilter {
+    spec: Arc<Mutex<String>>,
+    error: Arc<Mutex<Option<String>>>,
+    graph: Option<FilterGraph>,
+    // the string that failed, so that it isn't rebuilt for every frame
+    failed_spec: Option<String>,
+}
+
+impl ConfigurableFilter {
+    pub(crate) fn new(spec: Arc<Mutex<String>>, error: Arc<Mutex<Option<String>>>) -> Self {
+        Self {
+            spec,
+            error,
+            graph: None,
+            failed_spec: None,
+        }
+    }
+
+    /// The filter string to run, if there is a (not known to be broken) one.
+    fn current_spec(&mut self) -> Option<String> {
+        let spec = self.spec.lock().unwrap().trim().to_string();
+        if spec.is_empty() {
+            self.graph = None;
+            self.failed_spec = None;
+            *self.error.lock().unwrap() = None;
+            return None;
+        }
+        if self.failed_spec.as_ref() == Some(&spec) {
+            return None;
+        }
+        Some(spec)
+    }
+
+    fn fail(&mut self, spec: String, error: anyhow::Error) {
+        *self.error.lock().unwrap() = Some(format!("couldn't apply \"{spec}\": {error}"));
+        self.graph = None;
+        self.failed_spec = Some(spec);
+    }
+
+    fn built(&mut self, graph: FilterGraph) {
+        *self.error.lock().unwrap() = None;
+        self.failed_spec = None;
+        self.graph = Some(graph);
+    }
+
+    pub(crate) fn run_video(&mut self, frame: Video, time_base: Rational) -> Vec<Video> {
+        let Some(spec) = self.current_spec() else {
+            return vec![frame];
+        };
+        if !self.graph.as_ref().is_some_and(|g| g.accepts_video(&spec, &frame)) {
+            match FilterGraph::video(&spec, &frame, time_base) {
+                Ok(graph) => self.built(graph),
+                Err(e) => {
+                    self.fail(spec, e);
+                    return vec![frame];
+                }
+            }
+        }
+        match self.graph.as_mut().unwrap().run_video(&frame) {
+            Ok(frames) => frames,
+            Err(e) => {
+                self.fail(spec, e);
+                vec![frame]
+            }
+        }
+    }
+
+    pub(crate) fn run_audio(&mut self, frame: Audio) -> Vec<Audio> {
+        let Some(spec) = self.current_spec() else {
+            return vec![frame];
+        };
+        if !self.graph.as_ref().is_some_and(|g| g.accepts_audio(&spec, &frame)) {
+            match FilterGraph::audio(&spec, frame.format(), frame.channel_layout(), frame.rate()) {
+                Ok(graph) => self.built(graph),
+                Err(e) => {
+                    self.fail(spec, e);
+                    return vec![frame];
+                }
+            }
+        }
+        match self.graph.as_mut().unwrap().run_audio(&frame) {
+            Ok(frames) => frames,
+            Err(e) => {
+                self.fail(spec, e);
+                vec![frame]
+            }
+        }
+    }
+
+    /// Drop the frames buffered inside the graph, e.g. after seeking.
+    pub(crate) fn flush(&mut self) {
+        self.graph = None;
+    }
+}
@@ -0,0 +1,19 @@
+pub mod checksum;
+pub mod compare;
+pub mod deinterlace;
+pub mod downmix;
+pub mod export;
+pub mod file_settings;
+pub mod filter;
+pub mod loudness;
+pub mod loupe;
+pub mod lut;
+pub mod media_info;
+pub mod picture;
+pub mod player;
+pub mod quality;
+pub mod scopes;
+pub mod snapshot;
+pub mod tonemap;
+pub mod viewport;
+pub mod visualizer;
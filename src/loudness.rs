@@ -0,0 +1,452 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::media::Type;
+use ffmpeg::software::resampling;
+use ffmpeg::{codec, ffi, format, frame, ChannelLayout};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use anyhow::Result;
+use crate::dsp::Biquad;
+use crate::export::source_channel_layout;
+use crate::player::packed;
+
+/// The loudness ReplayGain 2 normalizes to, in LUFS.
+pub const REPLAY_GAIN_REFERENCE_LUFS: f64 = -18.;
+/// The loudness Opus' `R128_*_GAIN` tags are relative to, in LUFS.
+const R128_TAG_REFERENCE_LUFS: f64 = -23.;
+/// The most normalization boosts or cuts by, in dB, so near-silent tracks aren't amplified into noise.
+pub const MAX_NORMALIZATION_GAIN_DB: f64 = 20.;
+/// Blocks quieter than this never count towards a measurement, in LUFS.
+const ABSOLUTE_GATE_LUFS: f64 = -70.;
+/// The level the limiter keeps samples below, about -1 dBFS.
+const LIMITER_CEILING: f32 = 0.89;
+/// How long the limiter takes to recover from gain reduction, in seconds.
+const LIMITER_RELEASE_SECS: f32 = 0.05;
+
+/// The loudness of the best audio stream of a file, measured according to EBU R128 (ITU-R BS.1770).
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct LoudnessInfo {
+    /// The gated loudness of the whole stream, in LUFS.
+    pub integrated_lufs: f64,
+    /// The highest level of the reconstructed (4x oversampled) signal, in dBTP.
+    pub true_peak_dbtp: f64,
+    /// How much the loudness varies over the stream, in LU.
+    pub loudness_range_lu: f64,
+}
+
+impl LoudnessInfo {
+    /// Label/value rows describing the measurement, for display.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("integrated", format!("{:.1} LUFS", self.integrated_lufs)),
+            ("true peak", format!("{:.1} dBTP", self.true_peak_dbtp)),
+            ("loudness range", format!("{:.1} LU", self.loudness_range_lu)),
+        ]
+    }
+}
+
+/// ReplayGain values read from a file's tags.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ReplayGain {
+    /// The gain bringing the track to the ReplayGain reference loudness, in dB.
+    pub track_gain_db: Option<f64>,
+    /// The track's peak sample, as a fraction of full scale.
+    pub track_peak: Option<f64>,
+    pub album_gain_db: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl ReplayGain {
+    /// Read `REPLAYGAIN_*` tags, or Opus' `R128_*_GAIN` tags converted to the ReplayGain reference.
+    /// `None` if neither kind is present.
+    pub fn from_tags(tags: &BTreeMap<String, String>) -> Option<Self> {
+        let tag = |key: &str| {
+            tags.iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.as_str())
+        };
+        // "-6.20 dB"
+        let gain = |key: &str| tag(key).and_then(|v| v.trim().trim_end_matches("dB").trim().parse::<f64>().ok());
+        let peak = |key: &str| tag(key).and_then(|v| v.trim().parse::<f64>().ok());
+        // Q7.8 fixed point dB
+        let r128_gain = |key: &str| {
+            tag(key)
+                .and_then(|v| v.trim().parse::<i32>().ok())
+                .map(|q| q as f64 / 256. + REPLAY_GAIN_REFERENCE_LUFS - R128_TAG_REFERENCE_LUFS)
+        };
+        let replay_gain = ReplayGain {
+            track_gain_db: gain("REPLAYGAIN_TRACK_GAIN").or_else(|| r128_gain("R128_TRACK_GAIN")),
+            track_peak: peak("REPLAYGAIN_TRACK_PEAK"),
+            album_gain_db: gain("REPLAYGAIN_ALBUM_GAIN").or_else(|| r128_gain("R128_ALBUM_GAIN")),
+            album_peak: peak("REPLAYGAIN_ALBUM_PEAK"),
+        };
+        (replay_gain.track_gain_db.is_some() || replay_gain.album_gain_db.is_some()).then_some(replay_gain)
+    }
+
+    /// Label/value rows describing the tags, for display.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let db = |v: Option<f64>| v.map_or(String::from("-"), |v| format!("{v:+.2} dB"));
+        let peak = |v: Option<f64>| v.map_or(String::from("-"), |v| format!("{v:.6}"));
+        vec![
+            ("track gain", db(self.track_gain_db)),
+            ("track peak", peak(self.track_peak)),
+            ("album gain", db(self.album_gain_db)),
+            ("album peak", peak(self.album_peak)),
+        ]
+    }
+}
+
+/// How the player evens out the loudness of different files.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NormalizationMode {
+    /// Play files as they are.
+    Off,
+    /// Apply the file's ReplayGain track gain, falling back to a loudness analysis if it has none.
+    ReplayGain,
+    /// Bring the analyzed integrated loudness to [`PlayerOptions::target_lufs`](crate::player::PlayerOptions::target_lufs).
+    Loudness,
+}
+
+impl NormalizationMode {
+    pub const ALL: [NormalizationMode; 3] =
+        [NormalizationMode::Off, NormalizationMode::ReplayGain, NormalizationMode::Loudness];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NormalizationMode::Off => "off",
+            NormalizationMode::ReplayGain => "replaygain",
+            NormalizationMode::Loudness => "loudness",
+        }
+    }
+}
+
+/// Convert decibels to a linear gain.
+pub fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.)
+}
+
+/// Convert a linear gain to decibels.
+pub fn gain_to_db(gain: f64) -> f64 {
+    20. * gain.max(1e-9).log10()
+}
+
+/// The two stage K-weighting filter of BS.1770 (a high shelf followed by a high pass), for any sample rate.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2. * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [1., 2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+    );
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1. + k / q + k * k;
+    let high_pass = Biquad::new([1., -2., 1.], [1., 2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0]);
+    [shelf, high_pass]
+}
+
+/// The weight of each channel of `layout`: surround channels count more, the LFE channel not at all.
+fn channel_weights(layout: ChannelLayout) -> Vec<f64> {
+    let channels = layout.channels() as usize;
+    let mask = layout.bits();
+    if mask.count_ones() as usize != channels {
+        return vec![1.; channels];
+    }
+    let surround = ffi::AV_CH_BACK_LEFT | ffi::AV_CH_BACK_RIGHT | ffi::AV_CH_SIDE_LEFT | ffi::AV_CH_SIDE_RIGHT;
+    (0..64)
+        .map(|bit| 1u64 << bit)
+        .filter(|channel| mask & channel != 0)
+        .map(|channel| match channel {
+            ffi::AV_CH_LOW_FREQUENCY => 0.,
+            channel if surround & channel != 0 => 1.41,
+            _ => 1.,
+        })
+        .collect()
+}
+
+/// The taps of the 4x oversampling interpolation filter used to find true peaks.
+const TRUE_PEAK_PHASES: usize = 4;
+const TRUE_PEAK_TAPS_PER_PHASE: usize = 12;
+
+/// Accumulates the measurements of [`LoudnessInfo`] over interleaved samples.
+struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    // windowed sinc, TRUE_PEAK_PHASES * TRUE_PEAK_TAPS_PER_PHASE long
+    interpolation_filter: Vec<f64>,
+    // the most recent samples of each channel, newest first
+    history: Vec<[f64; TRUE_PEAK_TAPS_PER_PHASE]>,
+    true_peak: f64,
+    samples_per_sub_block: usize,
+    sub_block_samples: usize,
+    sub_block_energy: f64,
+    // the weighted mean square of every 100ms of audio
+    sub_blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    fn new(sample_rate: u32, layout: ChannelLayout) -> Self {
+        let channels = layout.channels() as usize;
+        let len = TRUE_PEAK_PHASES * TRUE_PEAK_TAPS_PER_PHASE;
+        let center = (len - 1) as f64 / 2.;
+        let interpolation_filter = (0..len)
+            .map(|i| {
+                let t = (i as f64 - center) / TRUE_PEAK_PHASES as f64;
+                let sinc = if t == 0. { 1. } else { (PI * t).sin() / (PI * t) };
+                let window = 0.5 - 0.5 * (2. * PI * i as f64 / (len - 1) as f64).cos();
+                sinc * window
+            })
+            .collect();
+        Self {
+            filters: vec![k_weighting(sample_rate as f64); channels],
+            weights: channel_weights(layout),
+            interpolation_filter,
+            history: vec![[0.; TRUE_PEAK_TAPS_PER_PHASE]; channels],
+            true_peak: 0.,
+            samples_per_sub_block: (sample_rate as usize / 10).max(1),
+            sub_block_samples: 0,
+            sub_block_energy: 0.,
+            sub_blocks: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, interleaved: &[f32]) {
+        let channels = self.filters.len();
+        for frame in interleaved.chunks_exact(channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let sample = *sample as f64;
+
+                let history = &mut self.history[channel];
+                history.copy_within(..TRUE_PEAK_TAPS_PER_PHASE - 1, 1);
+                history[0] = sample;
+                for phase in 0..TRUE_PEAK_PHASES {
+                    let interpolated: f64 = history
+                        .iter()
+                        .enumerate()
+                        .map(|(tap, x)| x * self.interpolation_filter[tap * TRUE_PEAK_PHASES + phase])
+                        .sum();
+                    self.true_peak = self.true_peak.max(interpolated.abs());
+                }
+                self.true_peak = self.true_peak.max(sample.abs());
+
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(sample));
+                self.sub_block_energy += self.weights[channel] * weighted * weighted;
+            }
+            self.sub_block_samples += 1;
+            if self.sub_block_samples == self.samples_per_sub_block {
+                self.sub_blocks.push(self.sub_block_energy / self.samples_per_sub_block as f64);
+                self.sub_block_samples = 0;
+                self.sub_block_energy = 0.;
+            }
+        }
+    }
+
+    /// The energy of each `window` sub blocks long block, every `step` sub blocks.
+    fn block_energies(&self, window: usize, step: usize) -> Vec<f64> {
+        if self.sub_blocks.len() < window {
+            return Vec::new();
+        }
+        (0..=self.sub_blocks.len() - window)
+            .step_by(step)
+            .map(|start| self.sub_blocks[start..start + window].iter().sum::<f64>() / window as f64)
+            .collect()
+    }
+
+    fn finish(self) -> LoudnessInfo {
+        // 400ms momentary blocks with 75% overlap
+        let momentary = self.block_energies(4, 1);
+        let above_absolute: Vec<f64> = momentary
+            .into_iter()
+            .filter(|e| energy_to_lufs(*e) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        let relative_gate = energy_to_lufs(mean(&above_absolute)) - 10.;
+        let gated: Vec<f64> = above_absolute
+            .into_iter()
+            .filter(|e| energy_to_lufs(*e) > relative_gate)
+            .collect();
+        let integrated_lufs = energy_to_lufs(mean(&gated)).max(ABSOLUTE_GATE_LUFS);
+
+        // 3s short term blocks every second, EBU Tech 3342
+        let short_term: Vec<f64> = self
+            .block_energies(30, 10)
+            .into_iter()
+            .filter(|e| energy_to_lufs(*e) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        let relative_gate = energy_to_lufs(mean(&short_term)) - 20.;
+        let mut levels: Vec<f64> = short_term
+            .into_iter()
+            .map(energy_to_lufs)
+            .filter(|l| *l > relative_gate)
+            .collect();
+        levels.sort_by(f64::total_cmp);
+        let percentile = |p: f64| levels[((levels.len() - 1) as f64 * p).round() as usize];
+        let loudness_range_lu = if levels.is_empty() { 0. } else { percentile(0.95) - percentile(0.1) };
+
+        LoudnessInfo {
+            integrated_lufs,
+            true_peak_dbtp: gain_to_db(self.true_peak),
+            loudness_range_lu,
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10. * energy.max(1e-20).log10()
+}
+
+/// Decode the best audio stream of `input_path` and measure its loudness.
+pub fn analyze_loudness(input_path: &str) -> Result<LoudnessInfo> {
+    let mut input_context = format::input(&input_path)?;
+    let stream = input_context
+        .streams()
+        .best(Type::Audio)
+        .ok_or(anyhow::anyhow!("the input has no audio stream"))?;
+    let stream_index = stream.index();
+    let mut decoder = codec::context::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .audio()?;
+    let layout = source_channel_layout(&decoder);
+    let output_format = format::Sample::F32(format::sample::Type::Packed);
+    let mut resampler =
+        resampling::Context::get(decoder.format(), layout, decoder.rate(), output_format, layout, decoder.rate())?;
+    let mut meter = LoudnessMeter::new(decoder.rate(), layout);
+
+    let mut decoded_frame = frame::Audio::empty();
+    let mut measure_decoded = |decoder: &mut codec::decoder::Audio| -> Result<()> {
+        while decoder.receive_frame(&mut decoded_frame).is_ok() {
+            decoded_frame.set_channel_layout(layout);
+            let mut resampled = frame::Audio::new(output_format, decoded_frame.samples() + 256, layout);
+            resampler.run(&decoded_frame, &mut resampled)?;
+            meter.push(packed::<f32>(&resampled));
+        }
+        Ok(())
+    };
+    for (stream, packet) in input_context.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        // a corrupt packet only loses its own samples, like in playback
+        if decoder.send_packet(&packet).is_err() {
+            continue;
+        }
+        measure_decoded(&mut decoder)?;
+    }
+    decoder.send_eof()?;
+    measure_decoded(&mut decoder)?;
+    Ok(meter.finish())
+}
+
+/// Keeps boosted audio from clipping by instantly turning down whatever would exceed the ceiling,
+/// then recovering smoothly.
+pub(crate) struct Limiter {
+    channels: usize,
+    release: f32,
+    envelope: f32,
+}
+
+impl Limiter {
+    pub(crate) fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            channels: channels.max(1),
+            release: (-1. / (LIMITER_RELEASE_SECS * sample_rate as f32)).exp(),
+            envelope: 0.,
+        }
+    }
+
+    /// Apply `gain` to the interleaved samples, limiting the result.
+    pub(crate) fn process(&mut self, interleaved: &mut [f32], gain: f32) {
+        for frame in interleaved.chunks_exact_mut(self.channels) {
+            let peak = frame.iter().fold(0f32, |peak, s| peak.max((s * gain).abs()));
+            self.envelope = if peak > self.envelope {
+                peak
+            } else {
+                self.envelope * self.release + peak * (1. - self.release)
+            };
+            let reduction = if self.envelope > LIMITER_CEILING { LIMITER_CEILING / self.envelope } else { 1. };
+            for sample in frame.iter_mut() {
+                *sample *= gain * reduction;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    /// `seconds` of a stereo 1 kHz sine with a peak of `level_dbfs`.
+    fn stereo_sine(level_dbfs: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = db_to_gain(level_dbfs);
+        (0..(seconds * RATE as f64) as usize)
+            .flat_map(|i| {
+                let sample = (amplitude * (2. * PI * 1000. * i as f64 / RATE as f64).sin()) as f32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn measure(samples: &[f32]) -> LoudnessInfo {
+        let mut meter = LoudnessMeter::new(RATE, ChannelLayout::STEREO);
+        // in uneven chunks, like decoded frames
+        for chunk in samples.chunks(2 * 1153) {
+            meter.push(chunk);
+        }
+        meter.finish()
+    }
+
+    #[test]
+    fn sine_at_reference_level() {
+        // EBU Tech 3341 case 1: a stereo 1 kHz sine at -23 dBFS measures -23 LUFS
+        let info = measure(&stereo_sine(-23., 20.));
+        assert!((info.integrated_lufs + 23.).abs() < 0.1, "{}", info.integrated_lufs);
+        assert!(info.loudness_range_lu.abs() < 0.1, "{}", info.loudness_range_lu);
+    }
+
+    #[test]
+    fn gain_shifts_loudness() {
+        let info = measure(&stereo_sine(-33., 20.));
+        assert!((info.integrated_lufs + 33.).abs() < 0.1, "{}", info.integrated_lufs);
+    }
+
+    #[test]
+    fn true_peak_of_sine() {
+        let info = measure(&stereo_sine(-6., 5.));
+        assert!((info.true_peak_dbtp + 6.).abs() < 0.2, "{}", info.true_peak_dbtp);
+    }
+
+    #[test]
+    fn silence_is_gated() {
+        let info = measure(&vec![0.; 2 * RATE as usize * 5]);
+        assert_eq!(info.integrated_lufs, ABSOLUTE_GATE_LUFS);
+        assert_eq!(info.loudness_range_lu, 0.);
+    }
+
+    #[test]
+    fn loudness_range_of_two_levels() {
+        // EBU Tech 3342 case 1: 20 s at -20 dBFS followed by 20 s at -30 dBFS has a range of 10 LU
+        let mut samples = stereo_sine(-20., 20.);
+        samples.extend(stereo_sine(-30., 20.));
+        let info = measure(&samples);
+        assert!((info.loudness_range_lu - 10.).abs() < 1., "{}", info.loudness_range_lu);
+    }
+
+    #[test]
+    fn lfe_does_not_count_and_surrounds_count_more() {
+        assert_eq!(channel_weights(ChannelLayout::_5POINT1), vec![1., 1., 1., 0., 1.41, 1.41]);
+        assert_eq!(channel_weights(ChannelLayout::STEREO), vec![1., 1.]);
+    }
+}
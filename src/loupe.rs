@@ -0,0 +1,137 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::ffi::{AV_PIX_FMT_FLAG_BE, AV_PIX_FMT_FLAG_BITSTREAM, AV_PIX_FMT_FLAG_HWACCEL, AV_PIX_FMT_FLAG_PAL, AV_PIX_FMT_FLAG_RGB};
+use ffmpeg::frame::Video;
+use egui::{Color32, ColorImage, CornerRadius, FontId, Painter, Pos2, Rect, Stroke, StrokeKind, Vec2};
+
+/// How many displayed pixels the loupe shows across.
+const LOUPE_PIXELS: i32 = 15;
+/// How big each pixel is drawn in the loupe, in points.
+const LOUPE_PIXEL_SIZE: f32 = 8.;
+
+/// What is at a point of the frame: the decoded values, and the color it is displayed with.
+#[derive(Clone, Debug)]
+pub struct PixelInfo {
+    /// The position in the decoded frame, in its pixels.
+    pub x: u32,
+    pub y: u32,
+    /// The components of the decoded pixel before conversion, e.g. `[("Y", 235), ("U", 128), ("V", 128)]`.
+    pub components: Vec<(&'static str, u16)>,
+    /// The bits per component of the decoded frame.
+    pub bit_depth: u8,
+    /// The color of the displayed pixel, after conversion, filters and adjustments.
+    pub displayed: Color32,
+}
+
+impl PixelInfo {
+    pub fn describe(&self) -> Vec<String> {
+        let components = self
+            .components
+            .iter()
+            .map(|(name, value)| format!("{name} {value}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let [r, g, b, _] = self.displayed.to_array();
+        vec![
+            format!("{}, {}", self.x, self.y),
+            format!("{components}  ({} bit)", self.bit_depth),
+            format!("displayed R {r}  G {g}  B {b}  #{r:02x}{g:02x}{b:02x}"),
+        ]
+    }
+}
+
+/// The components of the pixel at `x`, `y` of a decoded frame in any byte addressed format, through its
+/// pixel format descriptor. `None` for hardware, bitstream and paletted formats.
+pub(crate) fn decoded_components(frame: &Video, x: u32, y: u32) -> Option<(Vec<(&'static str, u16)>, u8)> {
+    if x >= frame.width() || y >= frame.height() {
+        return None;
+    }
+    let descriptor = unsafe { ffmpeg::ffi::av_pix_fmt_desc_get(frame.format().into()).as_ref()? };
+    let has_flag = |flag: u32| descriptor.flags & flag as u64 != 0;
+    if has_flag(AV_PIX_FMT_FLAG_HWACCEL) || has_flag(AV_PIX_FMT_FLAG_BITSTREAM) || has_flag(AV_PIX_FMT_FLAG_PAL) {
+        return None;
+    }
+    let is_rgb = has_flag(AV_PIX_FMT_FLAG_RGB);
+    let names: [&'static str; 4] = if is_rgb { ["R", "G", "B", "A"] } else { ["Y", "U", "V", "A"] };
+    let mut components = Vec::new();
+    let mut bit_depth = 0;
+    for (index, component) in descriptor.comp.iter().take(descriptor.nb_components as usize).enumerate() {
+        // gray formats only have luma (and maybe alpha)
+        let name = if descriptor.nb_components <= 2 && index == 1 { "A" } else { names[index] };
+        let is_chroma = !is_rgb && (index == 1 || index == 2) && descriptor.nb_components > 2;
+        let (cx, cy) = if is_chroma {
+            (x >> descriptor.log2_chroma_w, y >> descriptor.log2_chroma_h)
+        } else {
+            (x, y)
+        };
+        let plane = component.plane as usize;
+        let data = frame.data(plane);
+        let offset = cy as usize * frame.stride(plane) + cx as usize * component.step as usize + component.offset as usize;
+        let raw = if component.depth + component.shift > 8 {
+            let bytes = [*data.get(offset)?, *data.get(offset + 1)?];
+            if has_flag(AV_PIX_FMT_FLAG_BE) {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            }
+        } else {
+            *data.get(offset)? as u16
+        };
+        let value = (raw >> component.shift) & ((1u32 << component.depth) - 1) as u16;
+        components.push((name, value));
+        bit_depth = bit_depth.max(component.depth as u8);
+    }
+    Some((components, bit_depth))
+}
+
+/// Draw a magnified view of `image` around the pixel at `center`, next to `anchor`, with `lines` of text below.
+pub(crate) fn draw_loupe(painter: &Painter, anchor: Pos2, image: &ColorImage, center: [usize; 2], lines: &[String]) {
+    let side = LOUPE_PIXELS as f32 * LOUPE_PIXEL_SIZE;
+    let font = FontId::monospace(11.);
+    let galleys: Vec<_> = lines
+        .iter()
+        .map(|line| painter.layout_no_wrap(line.clone(), font.clone(), Color32::WHITE))
+        .collect();
+    let text_width = galleys.iter().map(|g| g.size().x).fold(side, f32::max);
+    let text_height: f32 = galleys.iter().map(|g| g.size().y).sum();
+    let panel_size = Vec2::new(text_width + 8., side + text_height + 12.);
+    // next to the cursor, flipped to stay on screen
+    let clip = painter.clip_rect();
+    let mut origin = anchor + Vec2::splat(16.);
+    if origin.x + panel_size.x > clip.right() {
+        origin.x = anchor.x - 16. - panel_size.x;
+    }
+    if origin.y + panel_size.y > clip.bottom() {
+        origin.y = anchor.y - 16. - panel_size.y;
+    }
+    let panel = Rect::from_min_size(origin, panel_size);
+    painter.rect_filled(panel, CornerRadius::same(4), Color32::from_black_alpha(220));
+
+    let loupe_origin = panel.min + Vec2::splat(4.);
+    let [width, height] = image.size;
+    for dy in 0..LOUPE_PIXELS {
+        for dx in 0..LOUPE_PIXELS {
+            let x = center[0] as i32 + dx - LOUPE_PIXELS / 2;
+            let y = center[1] as i32 + dy - LOUPE_PIXELS / 2;
+            if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                continue;
+            }
+            let color = image.pixels[y as usize * width + x as usize];
+            let min = loupe_origin + Vec2::new(dx as f32, dy as f32) * LOUPE_PIXEL_SIZE;
+            painter.rect_filled(Rect::from_min_size(min, Vec2::splat(LOUPE_PIXEL_SIZE)), CornerRadius::ZERO, color);
+        }
+    }
+    let middle = loupe_origin + Vec2::splat((LOUPE_PIXELS / 2) as f32 * LOUPE_PIXEL_SIZE);
+    painter.rect_stroke(
+        Rect::from_min_size(middle, Vec2::splat(LOUPE_PIXEL_SIZE)),
+        CornerRadius::ZERO,
+        Stroke::new(1., Color32::WHITE),
+        StrokeKind::Outside,
+    );
+
+    let mut text_origin = loupe_origin + Vec2::new(0., side + 4.);
+    for galley in galleys {
+        let height = galley.size().y;
+        painter.galley(text_origin, galley, Color32::WHITE);
+        text_origin.y += height;
+    }
+}
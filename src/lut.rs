@@ -0,0 +1,382 @@
+use egui::{Color32, ColorImage};
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+
+/// How a 3D LUT is sampled between its grid points.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum LutInterpolation {
+    /// Blends the 8 surrounding grid points.
+    Trilinear,
+    /// Blends the 4 grid points of the tetrahedron around the color, which keeps neutral tones neutral.
+    #[default]
+    Tetrahedral,
+}
+
+impl LutInterpolation {
+    pub const ALL: [LutInterpolation; 2] = [LutInterpolation::Trilinear, LutInterpolation::Tetrahedral];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LutInterpolation::Trilinear => "trilinear",
+            LutInterpolation::Tetrahedral => "tetrahedral",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum LutTable {
+    /// One curve per channel of `size` entries, baked for 8 bit input.
+    OneDimensional { size: usize, curves: [[u8; 256]; 3] },
+    /// `size`³ colors, red varying fastest.
+    ThreeDimensional { size: usize, colors: Vec<[f32; 3]> },
+}
+
+/// A color lookup table in the Adobe/Resolve `.cube` format, 1D or 3D.
+#[derive(Clone, Debug)]
+pub struct CubeLut {
+    /// The `TITLE` of the file, if it has one.
+    pub title: Option<String>,
+    table: LutTable,
+    // for each channel and 8 bit value: the grid point below it and how far it is towards the next one
+    grid_positions: [Vec<(usize, f32)>; 3],
+}
+
+impl CubeLut {
+    /// Read and parse a `.cube` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("couldn't read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("{} isn't a valid .cube LUT", path.display()))
+    }
+
+    /// Parse the contents of a `.cube` file.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut title = None;
+        let mut size_1d = None;
+        let mut size_3d = None;
+        let mut domain_min = [0f32; 3];
+        let mut domain_max = [1f32; 3];
+        let mut colors: Vec<[f32; 3]> = Vec::new();
+        let parse_floats = |line_number: usize, values: &[&str]| -> Result<Vec<f32>> {
+            values
+                .iter()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("line {line_number}: expected numbers"))
+        };
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[0] {
+                "TITLE" => title = Some(line["TITLE".len()..].trim().trim_matches('"').to_string()),
+                "LUT_1D_SIZE" | "LUT_3D_SIZE" => {
+                    let size: usize = words
+                        .get(1)
+                        .and_then(|s| s.parse().ok())
+                        .with_context(|| format!("line {line_number}: expected a size"))?;
+                    if words[0] == "LUT_1D_SIZE" {
+                        anyhow::ensure!((2..=65536).contains(&size), "line {line_number}: 1D size out of range");
+                        size_1d = Some(size);
+                    } else {
+                        anyhow::ensure!((2..=256).contains(&size), "line {line_number}: 3D size out of range");
+                        size_3d = Some(size);
+                    }
+                }
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let values = parse_floats(line_number, &words[1..])?;
+                    anyhow::ensure!(values.len() == 3, "line {line_number}: expected 3 values");
+                    let domain = if words[0] == "DOMAIN_MIN" { &mut domain_min } else { &mut domain_max };
+                    domain.copy_from_slice(&values);
+                }
+                // Resolve's variant of the domain, the same for every channel
+                "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => {
+                    let values = parse_floats(line_number, &words[1..])?;
+                    anyhow::ensure!(values.len() == 2, "line {line_number}: expected 2 values");
+                    domain_min = [values[0]; 3];
+                    domain_max = [values[1]; 3];
+                }
+                // keywords of other applications
+                keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => (),
+                _ => {
+                    let values = parse_floats(line_number, &words)?;
+                    anyhow::ensure!(values.len() == 3, "line {line_number}: expected 3 values");
+                    colors.push([values[0], values[1], values[2]]);
+                }
+            }
+        }
+        anyhow::ensure!(
+            (0..3).all(|c| domain_max[c] > domain_min[c]),
+            "DOMAIN_MAX has to be above DOMAIN_MIN"
+        );
+        let (size, table) = match (size_1d, size_3d) {
+            (Some(_), Some(_)) => anyhow::bail!("both LUT_1D_SIZE and LUT_3D_SIZE are given"),
+            (None, None) => anyhow::bail!("no LUT_1D_SIZE or LUT_3D_SIZE is given"),
+            (Some(size), None) => {
+                anyhow::ensure!(colors.len() == size, "expected {size} entries, found {}", colors.len());
+                (size, None)
+            }
+            (None, Some(size)) => {
+                let expected = size * size * size;
+                anyhow::ensure!(colors.len() == expected, "expected {expected} entries, found {}", colors.len());
+                (size, Some(size))
+            }
+        };
+        let grid_positions: [Vec<(usize, f32)>; 3] = std::array::from_fn(|channel| {
+            (0..256)
+                .map(|value| {
+                    let x = value as f32 / 255.;
+                    let t = ((x - domain_min[channel]) / (domain_max[channel] - domain_min[channel])).clamp(0., 1.);
+                    let position = t * (size - 1) as f32;
+                    let below = (position as usize).min(size - 2);
+                    (below, position - below as f32)
+                })
+                .collect()
+        });
+        let table = match table {
+            Some(size) => LutTable::ThreeDimensional { size, colors },
+            None => LutTable::OneDimensional {
+                size,
+                curves: std::array::from_fn(|channel| {
+                    std::array::from_fn(|value| {
+                        let (below, fraction) = grid_positions[channel][value];
+                        let output = colors[below][channel] * (1. - fraction) + colors[below + 1][channel] * fraction;
+                        to_u8(output)
+                    })
+                }),
+            },
+        };
+        Ok(Self {
+            title,
+            table,
+            grid_positions,
+        })
+    }
+
+    /// "1D" or "3D", with the size of the table.
+    pub fn describe(&self) -> String {
+        match &self.table {
+            LutTable::OneDimensional { size, .. } => format!("1D, {size} entries"),
+            LutTable::ThreeDimensional { size, .. } => format!("3D, {size}³"),
+        }
+    }
+
+    /// Map every pixel of `image` through the LUT.
+    pub fn apply(&self, image: &mut ColorImage, interpolation: LutInterpolation) {
+        match &self.table {
+            LutTable::OneDimensional { curves, .. } => {
+                for pixel in image.pixels.iter_mut() {
+                    let [r, g, b, a] = pixel.to_array();
+                    *pixel = Color32::from_rgba_premultiplied(
+                        curves[0][r as usize],
+                        curves[1][g as usize],
+                        curves[2][b as usize],
+                        a,
+                    );
+                }
+            }
+            LutTable::ThreeDimensional { size, colors } => {
+                let [red, green, blue] = &self.grid_positions;
+                let (stride_g, stride_b) = (*size, size * size);
+                let map_pixels = |pixels: &mut [Color32]| {
+                    for pixel in pixels.iter_mut() {
+                        let [r, g, b, a] = pixel.to_array();
+                        let (ri, fr) = red[r as usize];
+                        let (gi, fg) = green[g as usize];
+                        let (bi, fb) = blue[b as usize];
+                        let base = ri + gi * stride_g + bi * stride_b;
+                        // the corner that is `dr`, `dg` and `db` grid points further along each axis
+                        let corner =
+                            |dr: usize, dg: usize, db: usize| colors[base + dr + dg * stride_g + db * stride_b];
+                        let color = match interpolation {
+                            LutInterpolation::Trilinear => trilinear(corner, fr, fg, fb),
+                            LutInterpolation::Tetrahedral => tetrahedral(corner, fr, fg, fb),
+                        };
+                        *pixel =
+                            Color32::from_rgba_premultiplied(to_u8(color[0]), to_u8(color[1]), to_u8(color[2]), a);
+                    }
+                };
+                // interpolating every pixel of a 1080p frame on one core is too slow to keep up with playback
+                let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+                let chunk_size = image.pixels.len().div_ceil(threads).max(1);
+                let map_pixels = &map_pixels;
+                std::thread::scope(|scope| {
+                    for chunk in image.pixels.chunks_mut(chunk_size) {
+                        scope.spawn(move || map_pixels(chunk));
+                    }
+                });
+            }
+        }
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0., 1.) * 255. + 0.5) as u8
+}
+
+fn blend(weighted: &[(f32, [f32; 3])]) -> [f32; 3] {
+    let mut color = [0.; 3];
+    for (weight, corner) in weighted {
+        for channel in 0..3 {
+            color[channel] += weight * corner[channel];
+        }
+    }
+    color
+}
+
+fn trilinear(corner: impl Fn(usize, usize, usize) -> [f32; 3], fr: f32, fg: f32, fb: f32) -> [f32; 3] {
+    let (ir, ig, ib) = (1. - fr, 1. - fg, 1. - fb);
+    blend(&[
+        (ir * ig * ib, corner(0, 0, 0)),
+        (fr * ig * ib, corner(1, 0, 0)),
+        (ir * fg * ib, corner(0, 1, 0)),
+        (fr * fg * ib, corner(1, 1, 0)),
+        (ir * ig * fb, corner(0, 0, 1)),
+        (fr * ig * fb, corner(1, 0, 1)),
+        (ir * fg * fb, corner(0, 1, 1)),
+        (fr * fg * fb, corner(1, 1, 1)),
+    ])
+}
+
+fn tetrahedral(corner: impl Fn(usize, usize, usize) -> [f32; 3], fr: f32, fg: f32, fb: f32) -> [f32; 3] {
+    let (c000, c111) = (corner(0, 0, 0), corner(1, 1, 1));
+    if fr > fg {
+        if fg > fb {
+            blend(&[(1. - fr, c000), (fr - fg, corner(1, 0, 0)), (fg - fb, corner(1, 1, 0)), (fb, c111)])
+        } else if fr > fb {
+            blend(&[(1. - fr, c000), (fr - fb, corner(1, 0, 0)), (fb - fg, corner(1, 0, 1)), (fg, c111)])
+        } else {
+            blend(&[(1. - fb, c000), (fb - fr, corner(0, 0, 1)), (fr - fg, corner(1, 0, 1)), (fg, c111)])
+        }
+    } else if fb > fg {
+        blend(&[(1. - fb, c000), (fb - fg, corner(0, 0, 1)), (fg - fr, corner(0, 1, 1)), (fr, c111)])
+    } else if fb > fr {
+        blend(&[(1. - fg, c000), (fg - fb, corner(0, 1, 0)), (fb - fr, corner(0, 1, 1)), (fr, c111)])
+    } else {
+        blend(&[(1. - fg, c000), (fg - fr, corner(0, 1, 0)), (fr - fb, corner(1, 1, 0)), (fb, c111)])
+    }
+}
+
+/// The LUT previewed on the video, see [`PlayerOptions::lut`](crate::player::PlayerOptions::lut).
+#[derive(Clone, Default)]
+pub struct LutPreview {
+    pub lut: Option<Arc<CubeLut>>,
+    /// Off shows the video as it is, for comparing before and after.
+    pub enabled: bool,
+    pub interpolation: LutInterpolation,
+}
+
+impl LutPreview {
+    /// The LUT to apply, if one is loaded and enabled.
+    pub fn active(&self) -> Option<&CubeLut> {
+        self.lut.as_deref().filter(|_| self.enabled)
+    }
+}
+
+impl PartialEq for LutPreview {
+    fn eq(&self, other: &Self) -> bool {
+        let same_lut = match (&self.lut, &other.lut) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        same_lut && self.enabled == other.enabled && self.interpolation == other.interpolation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `size`³ table mapping every color to `map` of it.
+    fn cube_3d(size: usize, map: impl Fn([f32; 3]) -> [f32; 3]) -> String {
+        let mut text = format!("TITLE \"generated\"\nLUT_3D_SIZE {size}\n");
+        let step = |i: usize| i as f32 / (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let [r, g, b] = map([step(r), step(g), step(b)]);
+                    text += &format!("{r} {g} {b}\n");
+                }
+            }
+        }
+        text
+    }
+
+    fn test_image() -> ColorImage {
+        let pixels: Vec<u8> = (0..=255u8).flat_map(|v| [v, v.wrapping_mul(7), 255 - v]).collect();
+        ColorImage::from_rgb([16, 16], &pixels)
+    }
+
+    #[test]
+    fn identity_3d_keeps_colors() {
+        let lut = CubeLut::parse(&cube_3d(17, |c| c)).unwrap();
+        assert_eq!(lut.title.as_deref(), Some("generated"));
+        assert_eq!(lut.describe(), "3D, 17³");
+        for interpolation in LutInterpolation::ALL {
+            let mut image = test_image();
+            lut.apply(&mut image, interpolation);
+            assert_eq!(image.pixels, test_image().pixels, "{interpolation:?}");
+        }
+    }
+
+    #[test]
+    fn red_varies_fastest() {
+        // swap red and blue, which is only symmetric if the order of the entries is read right
+        let lut = CubeLut::parse(&cube_3d(2, |[r, g, b]| [b, g, r])).unwrap();
+        let mut image = ColorImage::from_rgb([1, 1], &[255, 128, 0]);
+        lut.apply(&mut image, LutInterpolation::Tetrahedral);
+        assert_eq!(image.pixels[0].to_array(), [0, 128, 255, 255]);
+    }
+
+    #[test]
+    fn inverting_1d() {
+        let text = "# comment\nLUT_1D_SIZE 2\nLUT_1D_INPUT_RANGE 0.0 1.0\n\n1 1 1\n0 0 0\n";
+        let lut = CubeLut::parse(text).unwrap();
+        assert_eq!(lut.title, None);
+        assert_eq!(lut.describe(), "1D, 2 entries");
+        let mut image = ColorImage::from_rgb([1, 1], &[0, 100, 255]);
+        lut.apply(&mut image, LutInterpolation::Trilinear);
+        assert_eq!(image.pixels[0].to_array(), [255, 155, 0, 255]);
+    }
+
+    #[test]
+    fn domain_scales_input() {
+        // only the lower half of the input range maps onto the table, the rest clamps to its end
+        let text = "LUT_1D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 0.5 0.5 0.5\n0 0 0\n1 1 1\n";
+        let lut = CubeLut::parse(text).unwrap();
+        let mut image = ColorImage::from_rgb([1, 1], &[0, 64, 200]);
+        lut.apply(&mut image, LutInterpolation::Trilinear);
+        let [r, g, b, _] = image.pixels[0].to_array();
+        assert_eq!((r, b), (0, 255));
+        assert!(g.abs_diff(128) <= 1, "{g}");
+    }
+
+    #[test]
+    fn other_applications_keywords_are_ignored() {
+        let text = format!("LUT_3D_INPUT_RANGE 0 1\nLUT_IN_VIDEO_RANGE\n{}", cube_3d(2, |c| c));
+        assert!(CubeLut::parse(&text).is_ok());
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let invalid = [
+            ("0 0 0\n1 1 1\n", "no LUT_1D_SIZE or LUT_3D_SIZE"),
+            ("LUT_1D_SIZE 2\nLUT_3D_SIZE 2\n", "both"),
+            ("LUT_1D_SIZE 3\n0 0 0\n1 1 1\n", "expected 3 entries, found 2"),
+            ("LUT_3D_SIZE 1\n", "3D size out of range"),
+            ("LUT_3D_SIZE\n", "expected a size"),
+            ("LUT_1D_SIZE 2\n0 0 0\n1 x 1\n", "line 3: expected numbers"),
+            ("LUT_1D_SIZE 2\n0 0\n1 1 1\n", "line 2: expected 3 values"),
+            ("LUT_1D_SIZE 2\nDOMAIN_MAX 0 0 0\n0 0 0\n1 1 1\n", "DOMAIN_MAX has to be above DOMAIN_MIN"),
+        ];
+        for (text, message) in invalid {
+            let error = CubeLut::parse(text).unwrap_err();
+            assert!(format!("{error:#}").contains(message), "{text:?}: {error:#}");
+        }
+    }
+}
@@ -1,26 +1,108 @@
 use egui::{CentralPanel,TextEdit,Sense,Window,Grid,DragValue,Slider};
 use eframe::NativeOptions;
-use crate::player::FFMpegPlayer;
+use testffmpeg::player::{AudioDevice, FFMpegPlayer, PlayerState, MAX_AUDIO_DELAY_MS};
+use testffmpeg::compare::{CompareMode, Comparison};
+use testffmpeg::export::{format_bytes, ExportJob, ExportState};
+use testffmpeg::export::clip::{export_clip, ClipStreams};
+use testffmpeg::export::transcode::{transcode, EncodePreset};
+use testffmpeg::export::animation::{estimate_animation_size, export_animation, AnimationFormat, AnimationOptions, Dither};
+use testffmpeg::export::audio::{export_audio, AudioExportOptions, AudioFormat};
+use testffmpeg::visualizer::{FrequencyScale, VisualizerMode};
+use testffmpeg::loudness::NormalizationMode;
+use testffmpeg::dsp::EQUALIZER_BANDS_HZ;
+use testffmpeg::downmix::DownmixMatrix;
+use testffmpeg::deinterlace::DeinterlaceMode;
+use testffmpeg::picture::PictureAdjustments;
+use testffmpeg::lut::{CubeLut, LutInterpolation};
+use testffmpeg::tonemap::ToneMapOperator;
+use testffmpeg::scopes::{ScopeKind, Scopes};
+use testffmpeg::viewport::{AspectRatio, FitMode};
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
 
-mod player;
+/// How much the audio delay shortcuts change the delay, in milliseconds.
+const AUDIO_DELAY_STEP_MS: i64 = 50;
 
 struct App {
-    // audio_device: AudioDevice,
+    // playback works without sound if no audio device could be opened
+    audio_device: Option<AudioDevice>,
     player: Option<FFMpegPlayer>,
+    // takes over the player while comparing it against another file
+    comparison: Option<Comparison>,
 
     media_path: String,
     stream_size_scale: f32,
     seek_frac: f32,
+    snapshot_format: &'static str,
+    // where the last snapshot was saved, or why it couldn't be
+    snapshot_result: Option<String>,
+    mark_in_ms: Option<i64>,
+    mark_out_ms: Option<i64>,
+    export_job: Option<ExportJob>,
+    transcode_preset: usize,
+    animation_options: AnimationOptions,
+    animation_size_estimate: Option<Receiver<anyhow::Result<u64>>>,
+    animation_size_text: String,
+    audio_export_options: AudioExportOptions,
+    show_inspector: bool,
+    show_scopes: bool,
+    scopes: Scopes,
+    inspector_tab: usize,
+    load_error: Option<String>,
+    // why the last file's settings couldn't be saved
+    settings_error: Option<String>,
 }
 
 impl Default for App {
     fn default() -> Self {
         Self {
-            // audio_device: AudioDevice::new().unwrap(),
+            audio_device: AudioDevice::new().ok(),
             media_path: String::new(),
             stream_size_scale: 1.,
             seek_frac: 0.,
+            snapshot_format: "png",
+            snapshot_result: None,
+            mark_in_ms: None,
+            mark_out_ms: None,
+            export_job: None,
+            transcode_preset: 0,
+            animation_options: AnimationOptions::default(),
+            animation_size_estimate: None,
+            animation_size_text: String::new(),
+            audio_export_options: AudioExportOptions::default(),
+            show_inspector: false,
+            show_scopes: false,
+            scopes: Scopes::default(),
+            inspector_tab: 0,
+            load_error: None,
+            settings_error: None,
             player: None,
+            comparison: None,
+        }
+    }
+}
+
+impl App {
+    /// The player of the loaded file, which a running comparison holds as its reference.
+    fn loaded_player(&self) -> Option<&FFMpegPlayer> {
+        self.player
+            .as_ref()
+            .or(self.comparison.as_ref().map(|comparison| &comparison.reference))
+    }
+
+    /// Save where the current file was left and how its picture was adjusted.
+    fn remember_file_settings(&mut self) {
+        if let Some(Err(e)) = self.loaded_player().map(|player| player.remember_file_settings()) {
+            self.settings_error = Some(format!("{e:#}"));
+        }
+    }
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        // there is no window left to show a failure in
+        if let Some(player) = self.loaded_player() {
+            let _ = player.remember_file_settings();
         }
     }
 }
@@ -28,26 +110,70 @@ impl Default for App {
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         ctx.request_repaint();
+        if let Some(load_error) = self.load_error.as_ref() {
+            let modal = egui::Modal::new(egui::Id::new("load_error")).show(ctx, |ui| {
+                ui.heading("couldn't load the file");
+                ui.label(load_error);
+                ui.button("ok").clicked()
+            });
+            if modal.inner || modal.should_close() {
+                self.load_error = None;
+            }
+        }
+        if let Some(settings_error) = self.settings_error.as_ref() {
+            let modal = egui::Modal::new(egui::Id::new("settings_error")).show(ctx, |ui| {
+                ui.heading("couldn't save the file settings");
+                ui.label(settings_error);
+                ui.button("ok").clicked()
+            });
+            if modal.inner || modal.should_close() {
+                self.settings_error = None;
+            }
+        }
         CentralPanel::default().show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.add_enabled_ui(!self.media_path.is_empty(), |ui| {
                     if ui.button("load").clicked() {
                         match FFMpegPlayer::new(ctx, &self.media_path.replace("\"", ""))
-                        // .and_then(|p| {
-                        //     p.with_audio(&mut self.audio_device)
-                        //         .and_then(|p| p.with_subtitles())
-                        // }) 
+                            .and_then(|p| match self.audio_device.as_mut() {
+                                Some(audio_device) => p.with_audio(audio_device),
+                                None => Ok(p),
+                            })
+                        // .and_then(|p| p.with_subtitles())
                         {
-                            Ok(player) => {
+                            Ok(mut player) => {
+                                self.remember_file_settings();
+                                player.restore_file_settings();
                                 self.player = Some(player);
+                                self.comparison = None;
                             }
-                            Err(e) => println!("failed to make stream: {e}"),
+                            Err(e) => self.load_error = Some(e.to_string()),
                         }
                     }
                 });
                 ui.add_enabled_ui(!self.media_path.is_empty(), |ui| {
                     if ui.button("clear").clicked() {
+                        self.remember_file_settings();
                         self.player = None;
+                        self.comparison = None;
+                    }
+                });
+                ui.add_enabled_ui(self.player.is_some(), |ui| {
+                    if ui.button("compare with...").clicked() {
+                        if let Some(path_buf) = rfd::FileDialog::new()
+                            .add_filter("videos", &["mp4", "gif", "webm", "mkv", "ogg"])
+                            .pick_file()
+                        {
+                            match FFMpegPlayer::new(ctx, &path_buf.as_path().to_string_lossy().to_string()) {
+                                Ok(distorted) => {
+                                    let reference = self.player.take().unwrap();
+                                    let mut comparison = Comparison::new(reference, distorted);
+                                    comparison.start();
+                                    self.comparison = Some(comparison);
+                                }
+                                Err(e) => self.load_error = Some(e.to_string()),
+                            }
+                        }
                     }
                 });
 
@@ -68,6 +194,7 @@ impl eframe::App for App {
                 {
                     if let Some(path_buf) = rfd::FileDialog::new()
                         .add_filter("videos", &["mp4", "gif", "webm", "mkv", "ogg"])
+                        .add_filter("audio", &["mp3", "flac", "ogg", "opus", "m4a", "wav"])
                         .pick_file()
                     {
                         self.media_path = path_buf.as_path().to_string_lossy().to_string();
@@ -94,17 +221,106 @@ impl eframe::App for App {
                         ui.label(format!("{:?}", player.player_state.get()));
                         ui.end_row();
 
-                        ui.label("has audio?");
-                        // ui.label(player.audio_streamer.is_some().to_string());
-                        ui.label("99999");
+                        ui.label("audio streams");
+                        ui.label(player.media_info.streams_of_kind("audio").count().to_string());
+                        ui.end_row();
+
+                        ui.label("subtitle streams");
+                        ui.label(player.media_info.streams_of_kind("subtitle").count().to_string());
+                        ui.end_row();
+
+                        ui.label("scan");
+                        ui.label(player.scan_type().name());
+                        ui.end_row();
+
+                        ui.label("dynamic range");
+                        ui.label(player.dynamic_range().name());
                         ui.end_row();
 
-                        ui.label("has subtitles?");
-                        // ui.label(player.subtitle_streamer.is_some().to_string());
-                        ui.label("字幕");
+                        ui.label("audio output");
+                        ui.label(player.audio_output().unwrap_or("-"));
+                        ui.end_row();
+
+                        ui.label("loudness");
+                        match player.loudness() {
+                            Some(loudness) => ui.label(format!("{:.1} LUFS", loudness.integrated_lufs)),
+                            None if player.is_analyzing_loudness() => ui.spinner(),
+                            None => match player.loudness_error() {
+                                Some(error) => ui.colored_label(ui.visuals().error_fg_color, error),
+                                None => ui.label("-"),
+                            },
+                        };
                         ui.end_row();
                     });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.show_inspector, "inspector");
+                        ui.add_enabled(player.video_streamer.is_some(), egui::Checkbox::new(&mut self.show_scopes, "scopes"));
+                        let can_analyze = player.loudness().is_none() && !player.is_analyzing_loudness();
+                        if ui.add_enabled(can_analyze, egui::Button::new("analyze loudness")).clicked() {
+                            player.analyze_loudness();
+                        }
+                    });
                 });
+                if self.show_scopes && player.video_streamer.is_some() {
+                    self.scopes.update(ctx, player.displayed_frame());
+                    let scopes = &mut self.scopes;
+                    Window::new("scopes").open(&mut self.show_scopes).show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            for kind in ScopeKind::ALL {
+                                ui.selectable_value(&mut scopes.kind, kind, kind.name());
+                            }
+                        });
+                        scopes.show(ui, 384.);
+                    });
+                }
+                let media_info = &player.media_info;
+                Window::new("inspector")
+                    .open(&mut self.show_inspector)
+                    .show(ctx, |ui| {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.selectable_value(&mut self.inspector_tab, 0, "format");
+                            for (i, stream) in media_info.streams.iter().enumerate() {
+                                ui.selectable_value(&mut self.inspector_tab, i + 1, stream.title());
+                            }
+                        });
+                        ui.separator();
+                        let (rows, tags) = match self.inspector_tab.checked_sub(1) {
+                            Some(i) if i < media_info.streams.len() => {
+                                (media_info.streams[i].rows(), &media_info.streams[i].tags)
+                            }
+                            _ => {
+                                let mut rows = media_info.rows();
+                                if let Some(loudness) = player.loudness() {
+                                    rows.extend(loudness.rows());
+                                }
+                                (rows, &media_info.tags)
+                            }
+                        };
+                        egui::ScrollArea::vertical().max_height(400.).show(ui, |ui| {
+                            Grid::new("inspector_grid").striped(true).show(ui, |ui| {
+                                for (label, value) in rows {
+                                    ui.label(label);
+                                    ui.label(value);
+                                    ui.end_row();
+                                }
+                            });
+                            if !tags.is_empty() {
+                                ui.separator();
+                                ui.label("tags");
+                                Grid::new("inspector_tags_grid").striped(true).show(ui, |ui| {
+                                    for (key, value) in tags {
+                                        ui.label(key);
+                                        ui.label(value);
+                                        ui.end_row();
+                                    }
+                                });
+                            }
+                        });
+                        ui.separator();
+                        if ui.button("copy as JSON").clicked() {
+                            ctx.copy_text(media_info.to_json());
+                        }
+                    });
                 Window::new("controls").show(ctx, |ui| {
                     ui.horizontal(|ui| {
                         if ui.button("seek to:").clicked() {
@@ -117,9 +333,253 @@ impl eframe::App for App {
                         );
                         ui.checkbox(&mut player.options.looping, "loop");
                     });
+                    ui.horizontal(|ui| {
+                        let take_snapshot = ui.button("snapshot").clicked()
+                            || (!ctx.wants_keyboard_input() && ctx.input(|i| i.key_pressed(egui::Key::S)));
+                        for format in ["png", "jpg", "webp"] {
+                            ui.selectable_value(&mut self.snapshot_format, format, format);
+                        }
+                        if take_snapshot {
+                            let path = player.default_snapshot_filename(self.snapshot_format);
+                            self.snapshot_result = Some(match player.save_snapshot(&path) {
+                                Ok(()) => format!("saved snapshot to {path}"),
+                                Err(e) => format!("failed to save snapshot: {e}"),
+                            });
+                        }
+                        if let Some(result) = self.snapshot_result.as_ref() {
+                            ui.label(result);
+                        }
+                    });
                     ui.horizontal(|ui| {
                         ui.label("size scale");
                         ui.add(Slider::new(&mut self.stream_size_scale, 0.0..=2.));
+                        ui.checkbox(&mut player.options.pixel_inspector, "pixel inspector");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("view");
+                        for fit in FitMode::ALL {
+                            ui.selectable_value(&mut player.viewport.fit, fit, fit.name());
+                        }
+                        if ui
+                            .button(format!("reset zoom ({:.0}%)", player.viewport.zoom() * 100.))
+                            .clicked()
+                        {
+                            player.viewport.reset_zoom();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("aspect");
+                        for aspect in AspectRatio::ALL {
+                            ui.selectable_value(&mut player.viewport.aspect, aspect, aspect.name());
+                        }
+                        ui.checkbox(&mut player.viewport.auto_crop, "crop black bars");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("deinterlace");
+                        let mut deinterlace = player.options.deinterlace.get();
+                        for mode in DeinterlaceMode::ALL {
+                            ui.selectable_value(&mut deinterlace, mode, mode.name());
+                        }
+                        player.options.deinterlace.set(deinterlace);
+                    });
+                    ui.add_enabled_ui(player.dynamic_range().is_hdr(), |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("tone map");
+                            let mut tone_map = player.options.tone_map.get();
+                            for operator in ToneMapOperator::ALL {
+                                ui.selectable_value(&mut tone_map, operator, operator.name());
+                            }
+                            player.options.tone_map.set(tone_map);
+                        });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("video filter");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut *player.options.video_filter.lock().unwrap())
+                                .hint_text("hflip,eq=contrast=1.2"),
+                        );
+                    });
+                    if let Some(error) = player.video_filter_error() {
+                        ui.colored_label(ui.visuals().error_fg_color, error);
+                    }
+                    egui::CollapsingHeader::new("picture").show(ui, |ui| {
+                        let mut picture = player.options.picture.get();
+                        Grid::new("picture_grid").show(ui, |ui| {
+                            ui.label("brightness");
+                            ui.add(Slider::new(&mut picture.brightness, -1.0..=1.0));
+                            ui.end_row();
+                            ui.label("contrast");
+                            ui.add(Slider::new(&mut picture.contrast, 0.0..=2.0));
+                            ui.end_row();
+                            ui.label("saturation");
+                            ui.add(Slider::new(&mut picture.saturation, 0.0..=3.0));
+                            ui.end_row();
+                            ui.label("gamma");
+                            ui.add(Slider::new(&mut picture.gamma, 0.1..=3.0));
+                            ui.end_row();
+                            ui.label("hue");
+                            ui.add(Slider::new(&mut picture.hue_degrees, -180.0..=180.0).suffix("°"));
+                            ui.end_row();
+                        });
+                        if ui.add_enabled(!picture.is_identity(), egui::Button::new("reset")).clicked() {
+                            picture = PictureAdjustments::default();
+                        }
+                        player.options.picture.set(picture);
+                    });
+                    ui.horizontal(|ui| {
+                        let mut lut = player.options.lut.lock().unwrap();
+                        ui.label("LUT");
+                        if ui.button("load").clicked() {
+                            if let Some(path_buf) = rfd::FileDialog::new().add_filter("LUT", &["cube"]).pick_file() {
+                                match CubeLut::load(&path_buf) {
+                                    Ok(cube) => {
+                                        lut.lut = Some(Arc::new(cube));
+                                        lut.enabled = true;
+                                    }
+                                    Err(e) => self.load_error = Some(format!("{e:#}")),
+                                }
+                            }
+                        }
+                        ui.add_enabled_ui(lut.lut.is_some(), |ui| {
+                            let toggle = !ctx.wants_keyboard_input() && ctx.input(|i| i.key_pressed(egui::Key::L));
+                            if toggle {
+                                lut.enabled = !lut.enabled;
+                            }
+                            ui.checkbox(&mut lut.enabled, "apply (L)");
+                            for interpolation in LutInterpolation::ALL {
+                                ui.selectable_value(&mut lut.interpolation, interpolation, interpolation.name());
+                            }
+                        });
+                        if let Some(cube) = lut.lut.as_ref() {
+                            ui.label(match cube.title.as_ref() {
+                                Some(title) => format!("{title} ({})", cube.describe()),
+                                None => cube.describe(),
+                            });
+                        }
+                    });
+                    ui.add_enabled_ui(player.audio_streamer.is_some(), |ui| {
+                        let options = &mut player.visualizer.options;
+                        ui.horizontal(|ui| {
+                            ui.label("visualizer");
+                            for mode in VisualizerMode::ALL {
+                                ui.selectable_value(&mut options.mode, mode, mode.name());
+                            }
+                            ui.checkbox(&mut options.overlay, "over video");
+                        });
+                        ui.add_enabled_ui(options.mode == VisualizerMode::Spectrum, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.selectable_value(&mut options.frequency_scale, FrequencyScale::Log, "log");
+                                ui.selectable_value(&mut options.frequency_scale, FrequencyScale::Linear, "linear");
+                                ui.checkbox(&mut options.peak_hold, "peak hold");
+                            });
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("normalize");
+                            for mode in NormalizationMode::ALL {
+                                ui.selectable_value(&mut player.options.normalization, mode, mode.name());
+                            }
+                            if player.options.normalization == NormalizationMode::Loudness {
+                                ui.add(
+                                    DragValue::new(&mut player.options.target_lufs)
+                                        .speed(0.5)
+                                        .range(-40.0..=-5.0)
+                                        .suffix(" LUFS"),
+                                );
+                            }
+                            match player.normalization_gain_db() {
+                                Some(gain_db) => ui.label(format!("{gain_db:+.1} dB")),
+                                None => ui.spinner(),
+                            };
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("downmix");
+                            let mut downmix = player.options.downmix.get();
+                            for matrix in DownmixMatrix::ALL {
+                                ui.selectable_value(&mut downmix, matrix, matrix.name());
+                            }
+                            player.options.downmix.set(downmix);
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("audio filter");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut *player.options.audio_filter.lock().unwrap())
+                                    .hint_text("atempo=1.5"),
+                            );
+                        });
+                        if let Some(error) = player.audio_filter_error() {
+                            ui.colored_label(ui.visuals().error_fg_color, error);
+                        }
+                        ui.horizontal(|ui| {
+                            ui.label("audio delay");
+                            let mut delay_ms = player.options.audio_delay_ms.get();
+                            let delay_resp = ui.add(
+                                DragValue::new(&mut delay_ms)
+                                    .speed(5)
+                                    .range(-MAX_AUDIO_DELAY_MS..=MAX_AUDIO_DELAY_MS)
+                                    .suffix(" ms"),
+                            );
+                            if delay_resp.changed() {
+                                player.set_audio_delay_ms(delay_ms);
+                            }
+                            ui.label("[ and ] adjust, \\ resets");
+                        });
+                        // brackets are also part of the filter syntax
+                        let typing = ctx.wants_keyboard_input();
+                        let (earlier, later, reset) = ctx.input(|i| {
+                            (
+                                !typing && i.key_pressed(egui::Key::OpenBracket),
+                                !typing && i.key_pressed(egui::Key::CloseBracket),
+                                !typing && i.key_pressed(egui::Key::Backslash),
+                            )
+                        });
+                        if earlier {
+                            player.adjust_audio_delay_ms(-AUDIO_DELAY_STEP_MS);
+                        }
+                        if later {
+                            player.adjust_audio_delay_ms(AUDIO_DELAY_STEP_MS);
+                        }
+                        if reset {
+                            player.set_audio_delay_ms(0);
+                        }
+                        egui::CollapsingHeader::new("audio effects").show(ui, |ui| {
+                            let mut dsp = player.options.dsp.lock().unwrap();
+                            ui.checkbox(&mut dsp.equalizer.enabled, "equalizer");
+                            ui.add_enabled_ui(dsp.equalizer.enabled, |ui| {
+                                ui.horizontal(|ui| {
+                                    for (gain_db, frequency) in dsp.equalizer.gains_db.iter_mut().zip(EQUALIZER_BANDS_HZ) {
+                                        ui.vertical(|ui| {
+                                            ui.add(Slider::new(gain_db, -12.0..=12.0).vertical().show_value(false));
+                                            ui.label(match frequency {
+                                                f if f >= 1000. => format!("{}k", f / 1000.),
+                                                f => f.to_string(),
+                                            });
+                                        });
+                                    }
+                                });
+                                if ui.button("flat").clicked() {
+                                    dsp.equalizer.gains_db = [0.; 10];
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut dsp.bass_boost.enabled, "bass boost");
+                                ui.add(DragValue::new(&mut dsp.bass_boost.gain_db).range(0.0..=15.0).suffix(" dB"));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut dsp.compressor.enabled, "night mode");
+                                ui.add(
+                                    DragValue::new(&mut dsp.compressor.threshold_db)
+                                        .range(-60.0..=0.0)
+                                        .prefix("threshold ")
+                                        .suffix(" dB"),
+                                );
+                                ui.add(DragValue::new(&mut dsp.compressor.ratio).speed(0.1).range(1.0..=20.0).suffix(":1"));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut dsp.stereo_widener.enabled, "widen");
+                                ui.add(Slider::new(&mut dsp.stereo_widener.width, 0.0..=3.0));
+                            });
+                            ui.checkbox(&mut dsp.mono.enabled, "mono");
+                        });
                     });
                     ui.separator();
                     ui.horizontal(|ui| {
@@ -136,6 +596,242 @@ impl eframe::App for App {
                             player.stop();
                         }
                     });
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui.button("mark in").clicked() {
+                            self.mark_in_ms = Some(player.elapsed_ms());
+                        }
+                        if ui.button("mark out").clicked() {
+                            self.mark_out_ms = Some(player.elapsed_ms());
+                        }
+                        let format_mark = |mark: Option<i64>| {
+                            mark.map(|ms| format!("{:.2}s", ms as f64 / 1000.))
+                                .unwrap_or(String::from("-"))
+                        };
+                        ui.label(format!(
+                            "{} → {}",
+                            format_mark(self.mark_in_ms),
+                            format_mark(self.mark_out_ms)
+                        ));
+                    });
+                    ui.horizontal(|ui| {
+                        let export_running = self.export_job.as_ref().is_some_and(|j| !j.is_done());
+                        let marks = self
+                            .mark_in_ms
+                            .zip(self.mark_out_ms)
+                            .filter(|(mark_in, mark_out)| mark_in < mark_out);
+                        ui.add_enabled_ui(marks.is_some() && !export_running, |ui| {
+                            if ui.button("export clip").clicked() {
+                                if let (Some((start_ms, end_ms)), Some(path_buf)) = (
+                                    marks,
+                                    rfd::FileDialog::new()
+                                        .add_filter("videos", &["mp4", "mkv", "mov", "webm"])
+                                        .save_file(),
+                                ) {
+                                    let input_path = player.input_path().to_string();
+                                    let output_path = path_buf.as_path().to_string_lossy().to_string();
+                                    self.export_job = Some(ExportJob::spawn(move |reporter| {
+                                        export_clip(
+                                            &input_path,
+                                            start_ms,
+                                            end_ms,
+                                            &output_path,
+                                            &ClipStreams::All,
+                                            reporter,
+                                        )
+                                    }));
+                                }
+                            }
+                        });
+                    });
+                    ui.horizontal(|ui| {
+                        let export_running = self.export_job.as_ref().is_some_and(|j| !j.is_done());
+                        let options = &mut self.audio_export_options;
+                        for format in AudioFormat::ALL {
+                            ui.selectable_value(&mut options.format, format, format.extension());
+                        }
+                        let track_text = |index: Option<usize>| match index {
+                            Some(index) => format!("stream #{index}"),
+                            None => String::from("best track"),
+                        };
+                        egui::ComboBox::from_id_salt("audio_export_track")
+                            .selected_text(track_text(options.stream_index))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut options.stream_index, None, track_text(None));
+                                for index in player.audio_stream_indices() {
+                                    ui.selectable_value(&mut options.stream_index, Some(*index), track_text(Some(*index)));
+                                }
+                            });
+                        egui::ComboBox::from_id_salt("audio_export_rate")
+                            .selected_text(options.sample_rate.map_or(String::from("source rate"), |r| format!("{r} Hz")))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut options.sample_rate, None, "source rate");
+                                for rate in [44_100, 48_000] {
+                                    ui.selectable_value(&mut options.sample_rate, Some(rate), format!("{rate} Hz"));
+                                }
+                            });
+                        egui::ComboBox::from_id_salt("audio_export_channels")
+                            .selected_text(match options.channels {
+                                None => "source channels",
+                                Some(1) => "mono",
+                                Some(_) => "stereo",
+                            })
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut options.channels, None, "source channels");
+                                ui.selectable_value(&mut options.channels, Some(1), "mono");
+                                ui.selectable_value(&mut options.channels, Some(2), "stereo");
+                            });
+                        ui.add_enabled_ui(options.format.is_lossy(), |ui| {
+                            ui.label("kbps");
+                            let mut kbps = options.bitrate / 1000;
+                            if ui.add(DragValue::new(&mut kbps).range(32..=320)).changed() {
+                                options.bitrate = kbps * 1000;
+                            }
+                        });
+                        ui.add_enabled_ui(!export_running, |ui| {
+                            if ui.button("export audio").clicked() {
+                                let extension = options.format.extension();
+                                if let Some(path_buf) = rfd::FileDialog::new()
+                                    .add_filter(extension, &[extension])
+                                    .save_file()
+                                {
+                                    let input_path = player.input_path().to_string();
+                                    let output_path = path_buf.as_path().to_string_lossy().to_string();
+                                    let mut options = options.clone();
+                                    // the marked range, or the whole track if nothing is marked
+                                    options.range_ms = self
+                                        .mark_in_ms
+                                        .zip(self.mark_out_ms)
+                                        .filter(|(mark_in, mark_out)| mark_in < mark_out);
+                                    self.export_job = Some(ExportJob::spawn(move |reporter| {
+                                        export_audio(&input_path, &output_path, &options, reporter)
+                                    }));
+                                }
+                            }
+                        });
+                    });
+                    ui.horizontal(|ui| {
+                        let export_running = self.export_job.as_ref().is_some_and(|j| !j.is_done());
+                        let presets = EncodePreset::presets();
+                        egui::ComboBox::from_id_salt("transcode_preset")
+                            .selected_text(presets[self.transcode_preset].0)
+                            .show_ui(ui, |ui| {
+                                for (i, (name, _, _)) in presets.iter().enumerate() {
+                                    ui.selectable_value(&mut self.transcode_preset, i, *name);
+                                }
+                            });
+                        ui.add_enabled_ui(!export_running, |ui| {
+                            if ui.button("export (re-encode)").clicked() {
+                                let (_, extension, preset) = presets[self.transcode_preset].clone();
+                                if let Some(path_buf) = rfd::FileDialog::new()
+                                    .add_filter("videos", &[extension])
+                                    .save_file()
+                                {
+                                    let input_path = player.input_path().to_string();
+                                    let output_path = path_buf.as_path().to_string_lossy().to_string();
+                                    self.export_job = Some(ExportJob::spawn(move |reporter| {
+                                        transcode(&input_path, &output_path, &preset, reporter)
+                                    }));
+                                }
+                            }
+                        });
+                    });
+                    ui.horizontal(|ui| {
+                        let options = &mut self.animation_options;
+                        ui.selectable_value(&mut options.format, AnimationFormat::Gif, "gif");
+                        ui.selectable_value(&mut options.format, AnimationFormat::Apng, "apng");
+                        ui.label("width");
+                        ui.add(DragValue::new(&mut options.width).range(16..=1920));
+                        ui.label("fps");
+                        ui.add(DragValue::new(&mut options.fps).range(1..=50));
+                        ui.add_enabled_ui(options.format == AnimationFormat::Gif, |ui| {
+                            egui::ComboBox::from_id_salt("animation_dither")
+                                .selected_text(format!("{:?}", options.dither))
+                                .show_ui(ui, |ui| {
+                                    for dither in [Dither::None, Dither::Bayer, Dither::FloydSteinberg] {
+                                        ui.selectable_value(&mut options.dither, dither, format!("{dither:?}"));
+                                    }
+                                });
+                        });
+                    });
+                    ui.horizontal(|ui| {
+                        let export_running = self.export_job.as_ref().is_some_and(|j| !j.is_done());
+                        let marks = self
+                            .mark_in_ms
+                            .zip(self.mark_out_ms)
+                            .filter(|(mark_in, mark_out)| mark_in < mark_out);
+                        if let Some((start_ms, end_ms)) = marks {
+                            self.animation_options.start_ms = start_ms;
+                            self.animation_options.end_ms = end_ms;
+                        }
+                        ui.add_enabled_ui(marks.is_some() && self.animation_size_estimate.is_none(), |ui| {
+                            if ui.button("estimate size").clicked() {
+                                let (sender, reciever) = std::sync::mpsc::channel();
+                                let input_path = player.input_path().to_string();
+                                let options = self.animation_options.clone();
+                                std::thread::spawn(move || {
+                                    let _ = sender.send(estimate_animation_size(&input_path, &options));
+                                });
+                                self.animation_size_estimate = Some(reciever);
+                                self.animation_size_text = String::from("estimating…");
+                            }
+                        });
+                        if let Some(estimate) = self.animation_size_estimate.as_ref() {
+                            if let Ok(result) = estimate.try_recv() {
+                                self.animation_size_text = match result {
+                                    Ok(bytes) => format!("~{}", format_bytes(bytes)),
+                                    Err(e) => format!("failed to estimate: {e}"),
+                                };
+                                self.animation_size_estimate = None;
+                            }
+                        }
+                        ui.label(&self.animation_size_text);
+                        ui.add_enabled_ui(marks.is_some() && !export_running, |ui| {
+                            if ui.button("export animation").clicked() {
+                                let extension = self.animation_options.format.extension();
+                                if let Some(path_buf) = rfd::FileDialog::new()
+                                    .add_filter(extension, &[extension])
+                                    .save_file()
+                                {
+                                    let input_path = player.input_path().to_string();
+                                    let output_path = path_buf.as_path().to_string_lossy().to_string();
+                                    let options = self.animation_options.clone();
+                                    self.export_job = Some(ExportJob::spawn(move |reporter| {
+                                        export_animation(&input_path, &output_path, &options, reporter)
+                                    }));
+                                }
+                            }
+                        });
+                    });
+                    if let Some(export_job) = self.export_job.as_mut() {
+                        export_job.poll();
+                        ui.horizontal(|ui| match export_job.state() {
+                            ExportState::Running => {
+                                ui.add(
+                                    egui::ProgressBar::new(export_job.progress())
+                                        .desired_width(150.)
+                                        .show_percentage(),
+                                );
+                                let eta = export_job
+                                    .eta()
+                                    .map(|eta| format!("{}s left", eta.as_secs()))
+                                    .unwrap_or_default();
+                                ui.label(format!("{} {}", format_bytes(export_job.bytes_written()), eta));
+                                if ui.button("cancel").clicked() {
+                                    export_job.cancel();
+                                }
+                            }
+                            ExportState::Finished => {
+                                ui.label(format!("export finished ({})", format_bytes(export_job.bytes_written())));
+                            }
+                            ExportState::Cancelled => {
+                                ui.label("export cancelled");
+                            }
+                            ExportState::Failed(e) => {
+                                ui.label(format!("export failed: {e}"));
+                            }
+                        });
+                    }
                     ui.horizontal(|ui| {
                         ui.label("volume");
                         let mut volume = player.options.audio_volume.get();
@@ -153,6 +849,58 @@ impl eframe::App for App {
 
                 player.ui(ui, player.size * self.stream_size_scale);
             }
+            let mut end_comparison = false;
+            if let Some(comparison) = self.comparison.as_mut() {
+                Window::new("compare").show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        for mode in CompareMode::ALL {
+                            ui.selectable_value(&mut comparison.mode, mode, mode.name());
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let playing = comparison.reference.player_state.get() == PlayerState::Playing;
+                        if ui.button(if playing { "pause" } else { "play" }).clicked() {
+                            comparison.toggle_playback();
+                        }
+                        if ui.button("step").clicked() {
+                            comparison.step_frame();
+                        }
+                        if ui.button("seek to:").clicked() {
+                            comparison.seek(self.seek_frac);
+                        }
+                        ui.add(
+                            DragValue::new(&mut self.seek_frac)
+                                .speed(0.05)
+                                .range(0.0..=1.0),
+                        );
+                        ui.label(comparison.reference.duration_text());
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut comparison.measure_quality, "measure PSNR / SSIM");
+                        if ui.button("clear").clicked() {
+                            comparison.clear_quality();
+                        }
+                        if let Some(mean) = comparison.mean_quality() {
+                            ui.label(format!("mean PSNR {:.2} dB  SSIM {:.4}", mean.psnr, mean.ssim));
+                        }
+                    });
+                    if comparison.measure_quality || !comparison.quality().is_empty() {
+                        comparison.show_quality_graph(ui, 384.);
+                    }
+                    if ui.button("end comparison").clicked() {
+                        end_comparison = true;
+                    }
+                });
+                let size = comparison.reference.size * self.stream_size_scale;
+                let size = match comparison.mode {
+                    CompareMode::SideBySide => egui::vec2(size.x * 2., size.y),
+                    _ => size,
+                };
+                comparison.ui(ui, size);
+            }
+            if end_comparison {
+                self.player = self.comparison.take().map(|comparison| comparison.reference);
+            }
         });
     }
 }
@@ -0,0 +1,329 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::format::context::input::Input;
+use ffmpeg::format::stream::{Disposition, Stream};
+use ffmpeg::media::Type;
+use ffmpeg::{codec, ChannelLayout, DictionaryRef, Rational};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use anyhow::Result;
+use crate::loudness::ReplayGain;
+use crate::player::{timestamp_to_millisec, AV_TIME_BASE_RATIONAL};
+
+/// A description of a media file and its streams, produced by probing its input context.
+#[derive(Clone, Debug, Serialize)]
+pub struct MediaInfo {
+    pub path: String,
+    /// The short name of the container format, e.g. `matroska,webm`.
+    pub format_name: String,
+    /// The descriptive name of the container format.
+    pub format_long_name: String,
+    pub duration_ms: Option<i64>,
+    /// The overall bitrate of the file, in bits per second.
+    pub bit_rate: Option<i64>,
+    pub size_bytes: Option<u64>,
+    pub tags: BTreeMap<String, String>,
+    pub streams: Vec<MediaStreamInfo>,
+    pub chapters: Vec<ChapterInfo>,
+    /// The ReplayGain tags of the file or its best audio stream.
+    pub replay_gain: Option<ReplayGain>,
+}
+
+/// A chapter of a [`MediaInfo`].
+#[derive(Clone, Debug, Serialize)]
+pub struct ChapterInfo {
+    pub id: i64,
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub title: Option<String>,
+}
+
+/// A single stream of a [`MediaInfo`].
+#[derive(Clone, Debug, Serialize)]
+pub struct MediaStreamInfo {
+    pub index: usize,
+    /// `video`, `audio`, `subtitle`, `data` or `attachment`.
+    pub kind: String,
+    pub codec_name: String,
+    pub codec_long_name: Option<String>,
+    pub profile: Option<String>,
+    pub level: Option<i32>,
+    /// The bitrate of the stream, in bits per second, if the container declares it.
+    pub bit_rate: Option<i64>,
+    pub time_base: String,
+    pub duration_ms: Option<i64>,
+    pub frames: Option<i64>,
+    pub language: Option<String>,
+    pub disposition: Vec<String>,
+    pub tags: BTreeMap<String, String>,
+    pub video: Option<VideoStreamDetails>,
+    pub audio: Option<AudioStreamDetails>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct VideoStreamDetails {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: String,
+    pub frame_rate: Option<f64>,
+    pub sample_aspect_ratio: Option<String>,
+    pub color_range: Option<String>,
+    pub color_space: Option<String>,
+    pub color_primaries: Option<String>,
+    pub color_transfer: Option<String>,
+    pub chroma_location: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AudioStreamDetails {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub channel_layout: String,
+    pub sample_format: String,
+}
+
+const DISPOSITION_NAMES: [(Disposition, &str); 14] = [
+    (Disposition::DEFAULT, "default"),
+    (Disposition::DUB, "dub"),
+    (Disposition::ORIGINAL, "original"),
+    (Disposition::COMMENT, "comment"),
+    (Disposition::LYRICS, "lyrics"),
+    (Disposition::KARAOKE, "karaoke"),
+    (Disposition::FORCED, "forced"),
+    (Disposition::HEARING_IMPAIRED, "hearing impaired"),
+    (Disposition::VISUAL_IMPAIRED, "visual impaired"),
+    (Disposition::CLEAN_EFFECTS, "clean effects"),
+    (Disposition::ATTACHED_PIC, "attached picture"),
+    (Disposition::CAPTIONS, "captions"),
+    (Disposition::DESCRIPTIONS, "descriptions"),
+    (Disposition::METADATA, "metadata"),
+];
+
+fn tags(metadata: DictionaryRef) -> BTreeMap<String, String> {
+    metadata
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn positive(value: i64) -> Option<i64> {
+    (value > 0).then_some(value)
+}
+
+fn rational_to_f64(rational: Rational) -> Option<f64> {
+    (rational.numerator() > 0 && rational.denominator() > 0)
+        .then(|| rational.numerator() as f64 / rational.denominator() as f64)
+}
+
+/// A human readable channel layout, e.g. `5.1(side)`.
+pub(crate) fn describe_channel_layout(layout: ChannelLayout) -> String {
+    let mut buf = [0 as std::ffi::c_char; 64];
+    unsafe {
+        if ffmpeg::ffi::av_channel_layout_describe(&layout.0, buf.as_mut_ptr(), buf.len()) < 0 {
+            return format!("{} channels", layout.channels());
+        }
+        CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+    }
+}
+
+impl MediaStreamInfo {
+    fn from_stream(stream: &Stream) -> Self {
+        let parameters = stream.parameters();
+        let codec_id = parameters.id();
+        let (profile, level, bit_rate) = unsafe {
+            let raw = &*parameters.as_ptr();
+            let profile = ffmpeg::ffi::avcodec_profile_name(codec_id.into(), raw.profile);
+            let profile = (!profile.is_null()).then(|| CStr::from_ptr(profile).to_string_lossy().into_owned());
+            (profile, raw.level, raw.bit_rate)
+        };
+        let kind = match parameters.medium() {
+            Type::Video => "video",
+            Type::Audio => "audio",
+            Type::Subtitle => "subtitle",
+            Type::Data => "data",
+            Type::Attachment => "attachment",
+            Type::Unknown => "unknown",
+        };
+        let decoder = || {
+            codec::context::Context::from_parameters(parameters.clone())
+                .ok()
+                .map(|context| context.decoder())
+        };
+
+        let video = decoder()
+            .filter(|_| parameters.medium() == Type::Video)
+            .and_then(|decoder| decoder.video().ok())
+            .map(|decoder| VideoStreamDetails {
+                width: decoder.width(),
+                height: decoder.height(),
+                pixel_format: decoder
+                    .format()
+                    .descriptor()
+                    .map_or(String::from("unknown"), |d| d.name().to_string()),
+                frame_rate: rational_to_f64(stream.avg_frame_rate()),
+                sample_aspect_ratio: (decoder.aspect_ratio().numerator() > 0)
+                    .then(|| decoder.aspect_ratio().to_string()),
+                color_range: decoder.color_range().name().map(String::from),
+                color_space: decoder.color_space().name().map(String::from),
+                color_primaries: decoder.color_primaries().name().map(String::from),
+                color_transfer: decoder.color_transfer_characteristic().name().map(String::from),
+                chroma_location: format!("{:?}", decoder.chroma_location()),
+            });
+        let audio = decoder()
+            .filter(|_| parameters.medium() == Type::Audio)
+            .and_then(|decoder| decoder.audio().ok())
+            .map(|decoder| AudioStreamDetails {
+                sample_rate: decoder.rate(),
+                channels: decoder.channels(),
+                channel_layout: describe_channel_layout(decoder.channel_layout()),
+                sample_format: decoder.format().name().to_string(),
+            });
+
+        let disposition = DISPOSITION_NAMES
+            .iter()
+            .filter(|(flag, _)| stream.disposition().contains(*flag))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        Self {
+            index: stream.index(),
+            kind: kind.to_string(),
+            codec_name: codec_id.name().to_string(),
+            codec_long_name: codec::decoder::find(codec_id).map(|c| c.description().to_string()),
+            profile,
+            level: (level >= 0).then_some(level),
+            bit_rate: positive(bit_rate),
+            time_base: stream.time_base().to_string(),
+            duration_ms: positive(stream.duration()).map(|d| timestamp_to_millisec(d, stream.time_base())),
+            frames: positive(stream.frames()),
+            language: stream.metadata().get("language").map(String::from),
+            disposition,
+            tags: tags(stream.metadata()),
+            video,
+            audio,
+        }
+    }
+
+    /// The label of the stream's tab in the inspector, e.g. `#1 audio (aac, eng)`.
+    pub fn title(&self) -> String {
+        match self.language.as_ref() {
+            Some(language) => format!("#{} {} ({}, {})", self.index, self.kind, self.codec_name, language),
+            None => format!("#{} {} ({})", self.index, self.kind, self.codec_name),
+        }
+    }
+
+    /// Label/value rows describing the stream, for display.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let mut rows = vec![
+            ("codec", self.codec_long_name.clone().unwrap_or(self.codec_name.clone())),
+            ("profile", self.profile.clone().unwrap_or(String::from("-"))),
+            ("level", self.level.map_or(String::from("-"), |l| l.to_string())),
+            ("bitrate", format_bit_rate(self.bit_rate)),
+            ("time base", self.time_base.clone()),
+            ("duration", format_duration_ms(self.duration_ms)),
+            ("frames", self.frames.map_or(String::from("-"), |f| f.to_string())),
+            ("language", self.language.clone().unwrap_or(String::from("-"))),
+            ("disposition", self.disposition.join(", ")),
+        ];
+        if let Some(video) = self.video.as_ref() {
+            rows.extend([
+                ("size", format!("{}x{}", video.width, video.height)),
+                ("pixel format", video.pixel_format.clone()),
+                ("frame rate", video.frame_rate.map_or(String::from("-"), |f| format!("{f:.3}"))),
+                ("sample aspect ratio", video.sample_aspect_ratio.clone().unwrap_or(String::from("-"))),
+                ("color range", video.color_range.clone().unwrap_or(String::from("-"))),
+                ("color space", video.color_space.clone().unwrap_or(String::from("-"))),
+                ("color primaries", video.color_primaries.clone().unwrap_or(String::from("-"))),
+                ("color transfer", video.color_transfer.clone().unwrap_or(String::from("-"))),
+                ("chroma location", video.chroma_location.clone()),
+            ]);
+        }
+        if let Some(audio) = self.audio.as_ref() {
+            rows.extend([
+                ("sample rate", format!("{} Hz", audio.sample_rate)),
+                ("channels", audio.channels.to_string()),
+                ("channel layout", audio.channel_layout.clone()),
+                ("sample format", audio.sample_format.clone()),
+            ]);
+        }
+        rows
+    }
+}
+
+impl MediaInfo {
+    /// Open `path` and probe it.
+    pub fn probe(path: &str) -> Result<Self> {
+        let input_context = ffmpeg::format::input(&path)?;
+        Ok(Self::from_input(&input_context, path))
+    }
+
+    /// Describe an already opened input context.
+    pub fn from_input(input_context: &Input, path: &str) -> Self {
+        let format = input_context.format();
+        let tags = tags(input_context.metadata());
+        // ogg and opus keep them on the stream instead of the container
+        let replay_gain = ReplayGain::from_tags(&tags).or_else(|| {
+            input_context
+                .streams()
+                .best(Type::Audio)
+                .and_then(|stream| ReplayGain::from_tags(&self::tags(stream.metadata())))
+        });
+        Self {
+            path: path.to_string(),
+            format_name: format.name().to_string(),
+            format_long_name: format.description().to_string(),
+            duration_ms: positive(input_context.duration())
+                .map(|d| timestamp_to_millisec(d, AV_TIME_BASE_RATIONAL)),
+            bit_rate: positive(input_context.bit_rate()),
+            size_bytes: std::fs::metadata(path).ok().map(|m| m.len()),
+            tags,
+            replay_gain,
+            streams: input_context
+                .streams()
+                .map(|stream| MediaStreamInfo::from_stream(&stream))
+                .collect(),
+            chapters: input_context
+                .chapters()
+                .map(|chapter| ChapterInfo {
+                    id: chapter.id(),
+                    start_ms: timestamp_to_millisec(chapter.start(), chapter.time_base()),
+                    end_ms: timestamp_to_millisec(chapter.end(), chapter.time_base()),
+                    title: chapter.metadata().get("title").map(String::from),
+                })
+                .collect(),
+        }
+    }
+
+    /// The streams of the given kind (`video`, `audio`, ...).
+    pub fn streams_of_kind<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a MediaStreamInfo> {
+        self.streams.iter().filter(move |s| s.kind == kind)
+    }
+
+    /// Label/value rows describing the container, for display.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let mut rows = vec![
+            ("path", self.path.clone()),
+            ("format", format!("{} ({})", self.format_long_name, self.format_name)),
+            ("duration", format_duration_ms(self.duration_ms)),
+            ("bitrate", format_bit_rate(self.bit_rate)),
+            ("size", self.size_bytes.map_or(String::from("-"), crate::export::format_bytes)),
+            ("streams", self.streams.len().to_string()),
+            ("chapters", self.chapters.len().to_string()),
+        ];
+        if let Some(replay_gain) = self.replay_gain.as_ref() {
+            rows.extend(replay_gain.rows());
+        }
+        rows
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+fn format_bit_rate(bit_rate: Option<i64>) -> String {
+    bit_rate.map_or(String::from("-"), |b| format!("{} kb/s", b / 1000))
+}
+
+pub fn format_duration_ms(duration_ms: Option<i64>) -> String {
+    duration_ms.map_or(String::from("-"), |ms| format!("{:.3}s", ms as f64 / 1000.))
+}
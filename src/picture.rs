@@ -0,0 +1,129 @@
+use egui::{Color32, ColorImage};
+use bytemuck::NoUninit;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::lut::LutPreview;
+
+/// Picture controls applied to video frames before they are displayed.
+#[derive(PartialEq, Clone, Copy, Debug, NoUninit, Serialize, Deserialize)]
+#[repr(C)]
+#[serde(default)]
+pub struct PictureAdjustments {
+    /// Added to every channel, from -1 to 1.
+    pub brightness: f32,
+    /// The scale of the distance from mid gray, 1 leaves the picture unchanged.
+    pub contrast: f32,
+    /// 0 is grayscale, 1 leaves the picture unchanged.
+    pub saturation: f32,
+    /// Above 1 brightens the midtones, below 1 darkens them.
+    pub gamma: f32,
+    /// The rotation of every hue around the color wheel, in degrees.
+    pub hue_degrees: f32,
+}
+
+impl Default for PictureAdjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.,
+            contrast: 1.,
+            saturation: 1.,
+            gamma: 1.,
+            hue_degrees: 0.,
+        }
+    }
+}
+
+impl PictureAdjustments {
+    /// Do these adjustments leave the picture unchanged?
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The table brightness, contrast and gamma map each channel value through.
+    fn levels(&self) -> [u8; 256] {
+        let mut levels = [0; 256];
+        for (value, level) in levels.iter_mut().enumerate() {
+            let x = (value as f32 / 255. - 0.5) * self.contrast + 0.5 + self.brightness;
+            let x = x.clamp(0., 1.).powf(1. / self.gamma.max(0.01));
+            *level = (x * 255.).round() as u8;
+        }
+        levels
+    }
+
+    /// The color matrix implementing saturation and hue, with the luma weights of the W3C filter effects.
+    /// `None` if it would change nothing.
+    fn color_matrix(&self) -> Option<[[f32; 3]; 3]> {
+        if self.saturation == 1. && self.hue_degrees == 0. {
+            return None;
+        }
+        let s = self.saturation;
+        let saturate = [
+            [0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s],
+            [0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s],
+            [0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s],
+        ];
+        let (sin, cos) = self.hue_degrees.to_radians().sin_cos();
+        let rotate = [
+            [0.213 + cos * 0.787 - sin * 0.213, 0.715 - cos * 0.715 - sin * 0.715, 0.072 - cos * 0.072 + sin * 0.928],
+            [0.213 - cos * 0.213 + sin * 0.143, 0.715 + cos * 0.285 + sin * 0.140, 0.072 - cos * 0.072 - sin * 0.283],
+            [0.213 - cos * 0.213 - sin * 0.787, 0.715 - cos * 0.715 + sin * 0.715, 0.072 + cos * 0.928 + sin * 0.072],
+        ];
+        let mut matrix = [[0.; 3]; 3];
+        for (row, matrix_row) in matrix.iter_mut().enumerate() {
+            for (column, value) in matrix_row.iter_mut().enumerate() {
+                *value = (0..3).map(|i| saturate[row][i] * rotate[i][column]).sum();
+            }
+        }
+        Some(matrix)
+    }
+}
+
+/// Applies a [`LutPreview`] and then [`PictureAdjustments`] to images, keeping the tables for the last
+/// adjustments around.
+#[derive(Default)]
+pub(crate) struct PictureProcessor {
+    designed_for: Option<PictureAdjustments>,
+    applied_lut: Option<LutPreview>,
+    levels: Vec<u8>,
+    color_matrix: Option<[[f32; 3]; 3]>,
+}
+
+impl PictureProcessor {
+    /// Was the last image processed with these settings?
+    pub(crate) fn is_current(&self, adjustments: PictureAdjustments, lut: &LutPreview) -> bool {
+        self.designed_for == Some(adjustments) && self.applied_lut.as_ref() == Some(lut)
+    }
+
+    /// The adjusted `image`. Shares `image` itself when there is nothing to apply.
+    pub(crate) fn apply(&mut self, adjustments: PictureAdjustments, lut: &LutPreview, image: &Arc<ColorImage>) -> Arc<ColorImage> {
+        if self.designed_for != Some(adjustments) {
+            self.levels = adjustments.levels().to_vec();
+            self.color_matrix = adjustments.color_matrix();
+            self.designed_for = Some(adjustments);
+        }
+        if self.applied_lut.as_ref() != Some(lut) {
+            self.applied_lut = Some(lut.clone());
+        }
+        if lut.active().is_none() && adjustments.is_identity() {
+            return image.clone();
+        }
+        let mut adjusted = ColorImage::clone(image);
+        if let Some(active) = lut.active() {
+            active.apply(&mut adjusted, lut.interpolation);
+        }
+        if adjustments.is_identity() {
+            return Arc::new(adjusted);
+        }
+        for pixel in adjusted.pixels.iter_mut() {
+            let [mut r, mut g, mut b, a] = pixel.to_array();
+            if let Some(m) = self.color_matrix.as_ref() {
+                let rgb = [r as f32, g as f32, b as f32];
+                let mix = |row: &[f32; 3]| (row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]).clamp(0., 255.) as u8;
+                (r, g, b) = (mix(&m[0]), mix(&m[1]), mix(&m[2]));
+            }
+            let levels = &self.levels;
+            *pixel = Color32::from_rgba_premultiplied(levels[r as usize], levels[g as usize], levels[b as usize], a);
+        }
+        Arc::new(adjusted)
+    }
+}
@@ -4,11 +4,38 @@ use ffmpeg::format::context::input::Input;
 use ffmpeg::format::input;
 use ffmpeg::Rational;
 use egui::{ColorImage,Color32,Ui,Response,Rect,vec2,Shadow,CornerRadius,Spinner,FontId,Align2};
-use egui::{TextureHandle,Vec2,TextureOptions};
+use egui::{TextureHandle,Vec2,Pos2,TextureOptions,ImageData};
 use timer::{Guard, Timer};
 use bytemuck::NoUninit;
 use std::sync::{Arc,Mutex,Weak};
+use std::path::Path;
 use anyhow::Result;
+use image::RgbaImage;
+use crate::snapshot::{self, SnapshotOptions};
+use crate::media_info::MediaInfo;
+use crate::visualizer::{AudioTap, Visualizer};
+use crate::dsp::{AudioProcessor, DspChain};
+use crate::deinterlace::{DeinterlaceMode, ScanType};
+use crate::file_settings::FileSettings;
+use crate::filter::{ConfigurableFilter, FilterGraph};
+use crate::loupe::{self, decoded_components, PixelInfo};
+use crate::lut::LutPreview;
+use crate::picture::{PictureAdjustments, PictureProcessor};
+use crate::tonemap::{DynamicRange, ToneMapOperator, ToneMapper};
+use crate::viewport::{Viewport, ViewportLayout};
+use std::collections::VecDeque;
+use crate::downmix::{audio_resampler, needs_downmix, supported_output_channels, DownmixMatrix};
+use crate::export::source_channel_layout;
+use crate::media_info::describe_channel_layout;
+use crate::loudness::{
+    analyze_loudness, db_to_gain, gain_to_db, Limiter, LoudnessInfo, NormalizationMode, MAX_NORMALIZATION_GAIN_DB,
+    REPLAY_GAIN_REFERENCE_LUFS,
+};
+use ffmpeg::format::stream::{Disposition, Stream};
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::wrap::caching::Caching;
+use ringbuf::HeapRb;
+use sdl2::audio::{self, AudioCallback, AudioSpecDesired};
 
 
 #[derive(Clone, Debug)]
@@ -44,8 +71,40 @@ pub struct PlayerOptions {
     pub max_audio_volume: f32,
     /// The texture options for the displayed video frame.
     pub texture_options: TextureOptions,
+    /// How the loudness of the audio stream is evened out.
+    pub normalization: NormalizationMode,
+    /// The loudness [`NormalizationMode::Loudness`] aims for, in LUFS.
+    pub target_lufs: f64,
+    /// The effects applied to the audio stream. Lock it to change them while playing.
+    pub dsp: Arc<Mutex<DspChain>>,
+    /// How much later than the video the audio plays, in milliseconds. Negative values play it earlier.
+    /// The latency of the output device is compensated on top of this. Clamped to
+    /// [`MAX_AUDIO_DELAY_MS`] either way.
+    pub audio_delay_ms: Shared<i64>,
+    /// How surround audio is folded down when the output device has fewer channels.
+    pub downmix: Shared<DownmixMatrix>,
+    /// How interlaced video is displayed.
+    pub deinterlace: Shared<DeinterlaceMode>,
+    /// A libavfilter graph description the video is run through before it is displayed, e.g.
+    /// `hflip,eq=contrast=1.2`. Empty for none. It can be changed while playing; if it is invalid, frames
+    /// are shown unfiltered and [`FFMpegPlayer::video_filter_error`] says why.
+    pub video_filter: Arc<Mutex<String>>,
+    /// Like [`PlayerOptions::video_filter`], for the audio, e.g. `atempo=1.5`. It runs before the
+    /// [`PlayerOptions::dsp`] effects.
+    pub audio_filter: Arc<Mutex<String>>,
+    /// How HDR video is fitted into the range of the display.
+    pub tone_map: Shared<ToneMapOperator>,
+    /// Brightness, contrast, saturation, gamma and hue of the displayed video.
+    pub picture: Shared<PictureAdjustments>,
+    /// A color grading LUT previewed on the video, applied before [`PlayerOptions::picture`].
+    pub lut: Arc<Mutex<LutPreview>>,
+    /// Show a magnified view and the values of the pixel under the cursor.
+    pub pixel_inspector: bool,
 }
 
+/// The largest [`PlayerOptions::audio_delay_ms`] that can be applied, either way.
+pub const MAX_AUDIO_DELAY_MS: i64 = 2000;
+
 impl Default for PlayerOptions {
     fn default() -> Self {
         Self {
@@ -53,10 +112,103 @@ impl Default for PlayerOptions {
             max_audio_volume: 1.,
             audio_volume: Shared::new(0.5),
             texture_options: TextureOptions::default(),
+            normalization: NormalizationMode::Off,
+            target_lufs: REPLAY_GAIN_REFERENCE_LUFS,
+            dsp: Arc::new(Mutex::new(DspChain::default())),
+            audio_delay_ms: Shared::new(0),
+            downmix: Shared::new(DownmixMatrix::Itu),
+            deinterlace: Shared::new(DeinterlaceMode::Off),
+            video_filter: Arc::new(Mutex::new(String::new())),
+            audio_filter: Arc::new(Mutex::new(String::new())),
+            tone_map: Shared::new(ToneMapOperator::Bt2390),
+            picture: Shared::new(PictureAdjustments::default()),
+            lut: Arc::new(Mutex::new(LutPreview::default())),
+            pixel_inspector: false,
+        }
+    }
+}
+
+/// Budgets for each phase of creating a [`FFMpegPlayer`], so that unplayable input fails instead of hanging.
+#[derive(Clone, Copy, Debug)]
+pub struct OpenLimits {
+    /// How long opening the input and probing its streams may take.
+    pub open_timeout: std::time::Duration,
+    /// How long decoding the first video frame may take.
+    pub first_frame_timeout: std::time::Duration,
+    /// How many packets may be read while looking for the first video frame.
+    pub first_frame_max_packets: usize,
+}
+
+impl Default for OpenLimits {
+    fn default() -> Self {
+        Self {
+            open_timeout: std::time::Duration::from_secs(10),
+            first_frame_timeout: std::time::Duration::from_secs(5),
+            first_frame_max_packets: 2000,
+        }
+    }
+}
+
+/// Why a [`FFMpegPlayer`] couldn't be created.
+#[derive(Debug)]
+pub enum PlayerError {
+    /// The input file couldn't be read.
+    IoError(std::io::Error),
+    /// The container couldn't be opened or probed, or doing so took longer than [`OpenLimits::open_timeout`].
+    OpenFailed(ffmpeg::Error),
+    /// The input has no video stream. The player opens audio-only files without one, this is only
+    /// returned by `VideoStreamer::open`, for the tools that need frames like the exports.
+    NoVideoStream,
+    /// The input has neither a video nor an audio stream.
+    NoPlayableStream,
+    /// The decoder for the video stream is missing or couldn't be opened.
+    DecoderOpenFailed(ffmpeg::Error),
+    /// No video frame could be decoded within the [`OpenLimits`].
+    NoFrameDecoded {
+        packets_read: usize,
+        elapsed: std::time::Duration,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayerError::IoError(e) => write!(f, "couldn't read the file: {e}"),
+            PlayerError::OpenFailed(ffmpeg::Error::Exit) => write!(f, "opening the file timed out"),
+            PlayerError::OpenFailed(e) => write!(f, "couldn't open the file: {e}"),
+            PlayerError::NoVideoStream => write!(f, "the file has no video stream"),
+            PlayerError::NoPlayableStream => write!(f, "the file has no video or audio stream"),
+            PlayerError::DecoderOpenFailed(e) => write!(f, "couldn't open the video decoder: {e}"),
+            PlayerError::NoFrameDecoded {
+                packets_read,
+                elapsed,
+                reason,
+            } => write!(
+                f,
+                "no video frame could be decoded ({reason}, after {packets_read} packets and {}ms)",
+                elapsed.as_millis()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlayerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlayerError::IoError(e) => Some(e),
+            PlayerError::OpenFailed(e) | PlayerError::DecoderOpenFailed(e) => Some(e),
+            _ => None,
         }
     }
 }
 
+impl From<std::io::Error> for PlayerError {
+    fn from(e: std::io::Error) -> Self {
+        PlayerError::IoError(e)
+    }
+}
+
 impl PlayerOptions {
     /// Set the maxmimum player volume, and scale the actual player volume to the
     /// same current ratio.
@@ -123,18 +275,18 @@ fn millisec_approx_eq(a: i64, b: i64) -> bool {
 }
 
 use ffmpeg_next::ffi::AV_TIME_BASE;
-const AV_TIME_BASE_RATIONAL: Rational = Rational(1, AV_TIME_BASE);
+pub(crate) const AV_TIME_BASE_RATIONAL: Rational = Rational(1, AV_TIME_BASE);
 const MILLISEC_TIME_BASE: Rational = Rational(1, 1000);
 
 use ffmpeg_next::Rescale;
-fn millisec_to_timestamp(millisec: i64, time_base: Rational) -> i64 {
+pub(crate) fn millisec_to_timestamp(millisec: i64, time_base: Rational) -> i64 {
     millisec.rescale(MILLISEC_TIME_BASE, time_base)
 }
-fn timestamp_to_millisec(timestamp: i64, time_base: Rational) -> i64 {
+pub(crate) fn timestamp_to_millisec(timestamp: i64, time_base: Rational) -> i64 {
     timestamp.rescale(time_base, MILLISEC_TIME_BASE)
 }
 
-fn is_ffmpeg_eof_error(error: &anyhow::Error) -> bool {
+pub(crate) fn is_ffmpeg_eof_error(error: &anyhow::Error) -> bool {
     matches!(
         error.downcast_ref::<ffmpeg::Error>(),
         Some(ffmpeg::Error::Eof)
@@ -168,6 +320,7 @@ pub trait Streamer: Send {
             // TODO: propogate error
             if self.input_context().seek(target_ts, ..target_ts).is_ok() {
                 self.decoder().flush();
+                self.flush_processed_frames();
                 let mut previous_elapsed_ms = self.elapsed_ms().get();
 
                 // this drop frame loop lets us refresh until current_ts is accurate
@@ -211,6 +364,10 @@ pub trait Streamer: Send {
     fn stream_type(&self) -> Type;
     /// The primary streamer will control most of the state/syncing.
     fn is_primary_streamer(&self) -> bool;
+    /// How far ahead of the primary streamer this streamer may decode, in milliseconds.
+    fn lead_ms(&self) -> i64 {
+        0
+    }
     /// The stream index.
     fn stream_index(&self) -> StreamIndex;
     /// Move to the next stream index, if possible, and return the new_stream_index.
@@ -265,7 +422,10 @@ pub trait Streamer: Send {
         let beginning_seek = beginning.rescale((1, 1), ffmpeg_next::rescale::TIME_BASE);
         let _ = self.input_context().seek(beginning_seek, ..beginning_seek);
         self.decoder().flush();
+        self.flush_processed_frames();
     }
+    /// Drop frames held on to after decoding (e.g. by filters), whenever the decoder is flushed.
+    fn flush_processed_frames(&mut self) {}
     /// Keep recieving packets until a frame can be decoded.
     fn recieve_next_packet_until_frame(&mut self) -> Result<Self::ProcessedFrame> {
         match self.recieve_next_frame() {
@@ -337,10 +497,28 @@ pub struct VideoStreamer {
     input_context: Input,
     video_elapsed_ms: Shared<i64>,
     _audio_elapsed_ms: Shared<i64>,
+    // the clock frames are shown by, the streamer's own unless it follows another player
+    clock: Shared<i64>,
     apply_video_frame_fn: Option<ApplyVideoFrameFn>,
+    deinterlace: Shared<DeinterlaceMode>,
+    deinterlacer: Option<FilterGraph>,
+    filter: ConfigurableFilter,
+    // frames the filters produced beyond the one returned, shown on the following ticks
+    queued_frames: VecDeque<(ColorImage, Video)>,
+    // the decoded frame of the image returned last, until it is applied
+    pending_source: Option<Video>,
+    // the decoded frame of the displayed image
+    source_frame: Arc<Mutex<Option<Video>>>,
+    scan_type: Shared<ScanType>,
+    // how many frames are currently shown per decoded frame
+    frames_per_frame: Shared<u8>,
+    tone_map: Shared<ToneMapOperator>,
+    tone_mapper: ToneMapper,
+    dynamic_range: Shared<DynamicRange>,
 
 }
 use ffmpeg_next::software::scaling::{context::Context as ScaleContext, flag::Flags};
+use ffmpeg_next::format::Pixel;
 
 impl Streamer for VideoStreamer {
     type Frame = Video;
@@ -367,7 +545,7 @@ impl Streamer for VideoStreamer {
         &self.video_elapsed_ms
     }
     fn primary_elapsed_ms(&self) -> &Shared<i64> {
-        &self.video_elapsed_ms
+        &self.clock
     }
     fn duration_ms(&self) -> i64 {
         self.duration_ms
@@ -381,35 +559,587 @@ impl Streamer for VideoStreamer {
         Ok(decoded_frame)
     }
     fn apply_frame(&mut self, frame: Self::ProcessedFrame) {
+        self.publish_source_frame();
         if let Some(apply_video_frame_fn) = self.apply_video_frame_fn.as_mut() {
             apply_video_frame_fn(frame)
         }
     }
     fn process_frame(&mut self, frame: Self::Frame) -> Result<Self::ProcessedFrame> {
-        let mut rgb_frame = Video::empty();
-        let mut scaler = ScaleContext::get(
-            frame.format(),
-            frame.width(),
-            frame.height(),
-            ffmpeg_next::format::Pixel::RGB24,
-            frame.width(),
-            frame.height(),
-            Flags::BILINEAR,
-        )?;
-        scaler.run(&frame, &mut rgb_frame)?;
-
-        let image = video_frame_to_image(rgb_frame);
+        self.scan_type.set(ScanType::of(&frame));
+        self.dynamic_range.set(DynamicRange::of(&frame));
+        let time_base = self.time_base();
+        let deinterlaced = self.deinterlace(frame)?;
+        let filtered: Vec<Video> = deinterlaced
+            .into_iter()
+            .flat_map(|frame| self.filter.run_video(frame, time_base))
+            .collect();
+        let mut images = filtered
+            .into_iter()
+            .map(|frame| Ok((self.frame_to_image(&frame)?, frame)))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter();
+        // deinterlacers (and some other filters) need to see the next frame first
+        let (image, source) = images
+            .next()
+            .ok_or(ffmpeg::Error::Other { errno: ffmpeg_next::error::EAGAIN })?;
+        self.pending_source = Some(source);
+        self.queued_frames.extend(images);
         Ok(image)
     }
+    fn recieve_next_frame(&mut self) -> Result<Self::ProcessedFrame> {
+        if let Some((image, source)) = self.queued_frames.pop_front() {
+            self.pending_source = Some(source);
+            return Ok(image);
+        }
+        let decoded_frame = self.decode_frame()?;
+        self.process_frame(decoded_frame)
+    }
+    fn flush_processed_frames(&mut self) {
+        self.deinterlacer = None;
+        self.filter.flush();
+        self.queued_frames.clear();
+    }
+}
+
+impl VideoStreamer {
+    /// Make a streamer for the best video stream of `input_context`.
+    pub(crate) fn new(
+        input_context: Input,
+        player_state: Shared<PlayerState>,
+        video_elapsed_ms: Shared<i64>,
+        audio_elapsed_ms: Shared<i64>,
+    ) -> Result<Self, PlayerError> {
+        let video_stream = best_video_stream(&input_context).ok_or(PlayerError::NoVideoStream)?;
+        let video_stream_index = StreamIndex(video_stream.index());
+        let video_decoder = ffmpeg::codec::context::Context::from_parameters(video_stream.parameters())
+            .and_then(|video_context| video_context.decoder().video())
+            .map_err(PlayerError::DecoderOpenFailed)?;
+        let duration_ms = timestamp_to_millisec(input_context.duration(), AV_TIME_BASE_RATIONAL);
+        Ok(Self {
+            apply_video_frame_fn: None,
+            deinterlace: Shared::new(DeinterlaceMode::Off),
+            deinterlacer: None,
+            filter: ConfigurableFilter::new(Arc::default(), Arc::default()),
+            queued_frames: VecDeque::new(),
+            pending_source: None,
+            source_frame: Arc::new(Mutex::new(None)),
+            scan_type: Shared::new(ScanType::Unknown),
+            frames_per_frame: Shared::new(1),
+            tone_map: Shared::new(ToneMapOperator::Bt2390),
+            tone_mapper: ToneMapper::default(),
+            dynamic_range: Shared::new(DynamicRange::Unknown),
+            duration_ms,
+            video_decoder,
+            video_stream_index,
+            _audio_elapsed_ms: audio_elapsed_ms,
+            clock: video_elapsed_ms.clone(),
+            video_elapsed_ms,
+            input_context,
+            player_state,
+        })
+    }
+
+    /// Open `input_path` for decoding outside of a player, e.g. for exports.
+    pub(crate) fn open(input_path: &str) -> Result<Self> {
+        Ok(Self::new(
+            input(&input_path)?,
+            Shared::new(PlayerState::Stopped),
+            Shared::new(0),
+            Shared::new(0),
+        )?)
+    }
+
+    /// Make the decoded frame of the image about to be displayed available to the player.
+    fn publish_source_frame(&mut self) {
+        if let Some(source) = self.pending_source.take() {
+            *self.source_frame.lock().unwrap() = Some(source);
+        }
+    }
+
+    /// Convert a decoded (and filtered) frame to an image for display, tone mapping HDR video.
+    fn frame_to_image(&mut self, frame: &Video) -> Result<ColorImage> {
+        let dynamic_range = DynamicRange::of(frame);
+        let operator = self.tone_map.get();
+        if dynamic_range.is_hdr() && operator != ToneMapOperator::Off {
+            return self.tone_mapper.convert(frame, dynamic_range, operator);
+        }
+        scale_frame(frame, Pixel::RGB24, frame.width(), frame.height()).map(video_frame_to_image)
+    }
+
+    /// Run `frame` through the deinterlacer of the current [`DeinterlaceMode`], if there is one.
+    fn deinterlace(&mut self, frame: Video) -> Result<Vec<Video>> {
+        let mode = self.deinterlace.get();
+        let Some(spec) = mode.filter_spec() else {
+            self.deinterlacer = None;
+            self.frames_per_frame.set(1);
+            return Ok(vec![frame]);
+        };
+        if !self.deinterlacer.as_ref().is_some_and(|d| d.accepts_video(spec, &frame)) {
+            self.deinterlacer = Some(FilterGraph::video(spec, &frame, self.time_base())?);
+        }
+        // the rate only depends on the mode, auto never doubles it
+        self.frames_per_frame.set(mode.frames_per_frame());
+        self.deinterlacer.as_mut().unwrap().run_video(&frame)
+    }
+
+    /// The decoder of the video stream.
+    pub(crate) fn video_decoder(&self) -> &ffmpeg::decoder::Video {
+        &self.video_decoder
+    }
+
+    /// The time base of the video stream.
+    pub(crate) fn time_base(&self) -> Rational {
+        self.input_context
+            .stream(self.video_stream_index.0)
+            .map(|s| s.time_base())
+            .unwrap_or(MILLISEC_TIME_BASE)
+    }
+
+    /// Seek to the keyframe at or before `target_ms`.
+    pub(crate) fn seek_to_keyframe(&mut self, target_ms: i64) -> Result<()> {
+        let target_ts = millisec_to_timestamp(target_ms, AV_TIME_BASE_RATIONAL);
+        self.input_context.seek(target_ts, ..target_ts)?;
+        self.video_decoder.flush();
+        Ok(())
+    }
+
+    /// Read packets until the first frame is decoded, giving up once `max_packets` packets have been read
+    /// or `timeout` has passed. Unlike [`Streamer::recieve_next_packet_until_frame`], read errors and
+    /// corrupt packets are skipped instead of retried forever.
+    fn recieve_first_frame(&mut self, max_packets: usize, timeout: std::time::Duration) -> Result<ColorImage, PlayerError> {
+        let started = std::time::Instant::now();
+        let mut packets_read = 0;
+        let mut sent_eof = false;
+        let no_frame = |packets_read, reason: &str| PlayerError::NoFrameDecoded {
+            packets_read,
+            elapsed: started.elapsed(),
+            reason: reason.to_string(),
+        };
+        loop {
+            match self.decode_frame() {
+                Ok(frame) => match self.process_frame(frame) {
+                    Ok(image) => {
+                        self.publish_source_frame();
+                        return Ok(image);
+                    }
+                    Err(e) if is_ffmpeg_incomplete_error(&e) => (),
+                    Err(e) => return Err(no_frame(packets_read, &e.to_string())),
+                },
+                Err(e) if is_ffmpeg_incomplete_error(&e) => (),
+                Err(e) if is_ffmpeg_eof_error(&e) => return Err(no_frame(packets_read, "reached the end of the file")),
+                Err(e) => return Err(no_frame(packets_read, &e.to_string())),
+            }
+            if packets_read >= max_packets {
+                return Err(no_frame(packets_read, "packet limit reached"));
+            }
+            if started.elapsed() > timeout {
+                return Err(no_frame(packets_read, "timed out"));
+            }
+            let mut packet = ffmpeg::Packet::empty();
+            match packet.read(&mut self.input_context) {
+                Ok(()) => {
+                    packets_read += 1;
+                    if packet.stream() == self.video_stream_index.0 {
+                        // a corrupt packet is not fatal, the next keyframe may still decode
+                        let _ = self.video_decoder.send_packet(&packet);
+                    }
+                }
+                Err(ffmpeg::Error::Eof) if !sent_eof => {
+                    sent_eof = true;
+                    let _ = self.video_decoder.send_eof();
+                }
+                Err(ffmpeg::Error::Eof) => return Err(no_frame(packets_read, "reached the end of the file")),
+                Err(ffmpeg::Error::Exit) => return Err(no_frame(packets_read, "timed out")),
+                Err(_) => packets_read += 1,
+            }
+        }
+    }
+
+    /// Keep recieving packets until a frame can be decoded, and return it processed the way it would be
+    /// displayed, along with the (deinterlaced and filtered) frame the image was converted from.
+    pub(crate) fn recieve_next_displayed_frame(&mut self) -> Result<(ColorImage, Video)> {
+        let image = self.recieve_next_packet_until_frame()?;
+        let source = self
+            .pending_source
+            .take()
+            .ok_or(anyhow::anyhow!("the processed frame has no source frame"))?;
+        Ok((image, source))
+    }
+
+    /// The index of the video stream in the input.
+    pub(crate) fn video_stream_index(&self) -> usize {
+        self.video_stream_index.0
+    }
+
+    /// Keep recieving packets until a frame can be decoded, and return it without processing it.
+    pub(crate) fn recieve_next_decoded_frame(&mut self) -> Result<Video> {
+        loop {
+            match self.decode_frame() {
+                Ok(frame) => return Ok(frame),
+                Err(e) if is_ffmpeg_incomplete_error(&e) => self.recieve_next_packet()?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// The best video stream of `input_context`, ignoring attached pictures (cover art).
+pub(crate) fn best_video_stream(input_context: &Input) -> Option<Stream<'_>> {
+    let is_attached_pic = |stream: &Stream| stream.disposition().contains(Disposition::ATTACHED_PIC);
+    input_context
+        .streams()
+        .best(Type::Video)
+        .filter(|stream| !is_attached_pic(stream))
+        .or_else(|| {
+            input_context
+                .streams()
+                .find(|stream| stream.parameters().medium() == Type::Video && !is_attached_pic(stream))
+        })
+}
+
+/// Decode the first attached picture (e.g. the cover art of an mp3) of `input_path`.
+fn decode_cover_art(input_path: &str) -> Option<ColorImage> {
+    let mut input_context = input(&input_path).ok()?;
+    let stream_index = input_context
+        .streams()
+        .find(|stream| stream.disposition().contains(Disposition::ATTACHED_PIC))?
+        .index();
+    let mut decoder = ffmpeg::codec::context::Context::from_parameters(
+        input_context.stream(stream_index)?.parameters(),
+    )
+    .ok()?
+    .decoder()
+    .video()
+    .ok()?;
+    // attached pictures are returned before any other packets
+    let (_, packet) = input_context
+        .packets()
+        .take(32)
+        .find(|(stream, _)| stream.index() == stream_index)?;
+    decoder.send_packet(&packet).ok()?;
+    decoder.send_eof().ok()?;
+    let mut frame = Video::empty();
+    decoder.receive_frame(&mut frame).ok()?;
+    let rgb_frame = scale_frame(&frame, Pixel::RGB24, frame.width(), frame.height()).ok()?;
+    Some(video_frame_to_image(rgb_frame))
+}
+
+/// The playback device. Needs to be initialized (and kept alive!) for a [`FFMpegPlayer`] to output audio.
+pub struct AudioDevice(pub(crate) audio::AudioDevice<AudioDeviceCallback>);
+
+impl AudioDevice {
+    /// Create a new [`AudioDevice`] from an existing [`sdl2::AudioSubsystem`], with as many channels as
+    /// the default output device supports.
+    pub fn from_subsystem(audio_sys: &sdl2::AudioSubsystem) -> Result<AudioDevice, String> {
+        Self::from_subsystem_with_channels(audio_sys, preferred_output_channels().unwrap_or(2))
+    }
+
+    /// Create a new [`AudioDevice`] from an existing [`sdl2::AudioSubsystem`] with (at most) `channels`
+    /// channels. Audio with more channels is downmixed.
+    pub fn from_subsystem_with_channels(audio_sys: &sdl2::AudioSubsystem, channels: u8) -> Result<AudioDevice, String> {
+        let audio_spec = AudioSpecDesired {
+            freq: Some(44_100),
+            channels: Some(supported_output_channels(channels)),
+            samples: None,
+        };
+        let device = audio_sys.open_playback(None, &audio_spec, |_spec| AudioDeviceCallback {
+            sample_streams: vec![],
+        })?;
+        Ok(AudioDevice(device))
+    }
+
+    /// Create a new [`AudioDevice`]. Creates an [`sdl2::AudioSubsystem`].
+    pub fn new() -> Result<AudioDevice, String> {
+        // without setting this hint, SDL captures SIGINT (Ctrl+C) and because we are not handling SDL events
+        // this prevents the application from closing
+        sdl2::hint::set("SDL_NO_SIGNAL_HANDLERS", "1");
+        Self::from_subsystem(&sdl2::init()?.audio()?)
+    }
+
+    /// The number of channels audio is played with.
+    pub fn channels(&self) -> u8 {
+        self.0.spec().channels
+    }
+
+    /// How long the device buffers audio before it is heard, in milliseconds.
+    pub fn latency_ms(&self) -> i64 {
+        let spec = self.0.spec();
+        spec.samples as i64 * 1000 / spec.freq.max(1) as i64
+    }
+}
+
+/// The channel count the default output device prefers, if SDL knows it.
+fn preferred_output_channels() -> Option<u8> {
+    let mut name = std::ptr::null_mut();
+    let mut spec = std::mem::MaybeUninit::<sdl2::sys::SDL_AudioSpec>::zeroed();
+    let result = unsafe { sdl2::sys::SDL_GetDefaultAudioInfo(&mut name, spec.as_mut_ptr(), 0) };
+    if !name.is_null() {
+        unsafe { sdl2::sys::SDL_free(name as *mut std::ffi::c_void) };
+    }
+    (result == 0).then(|| unsafe { spec.assume_init() }.channels)
+}
+
+type RingbufProducer<T> = Caching<Arc<HeapRb<T>>, true, false>;
+type RingbufConsumer<T> = Caching<Arc<HeapRb<T>>, false, true>;
+
+type AudioSampleProducer = RingbufProducer<f32>;
+type AudioSampleConsumer = RingbufConsumer<f32>;
+
+pub(crate) struct AudioDeviceCallback {
+    sample_streams: Vec<AudioSampleStream>,
+}
+
+struct AudioSampleStream {
+    sample_consumer: AudioSampleConsumer,
+    audio_volume: Shared<f32>,
+}
+
+impl AudioCallback for AudioDeviceCallback {
+    type Channel = f32;
+    fn callback(&mut self, output: &mut [Self::Channel]) {
+        // streams of dropped players have nothing left to play
+        self.sample_streams
+            .retain(|s| s.sample_consumer.write_is_held() || !s.sample_consumer.is_empty());
+        for x in output.iter_mut() {
+            *x = self
+                .sample_streams
+                .iter_mut()
+                .map(|s| s.sample_consumer.try_pop().unwrap_or(0.) * s.audio_volume.get())
+                .sum()
+        }
+    }
+}
+
+// Interpret the audio frame's data as packed (alternating channels, 12121212, as opposed to planar 11112222)
+pub(crate) fn packed<T: ffmpeg::frame::audio::Sample>(frame: &ffmpeg::frame::Audio) -> &[T] {
+    if !frame.is_packed() {
+        panic!("data is not packed");
+    }
+    if !<T as ffmpeg::frame::audio::Sample>::is_valid(frame.format(), frame.channels()) {
+        panic!("unsupported type");
+    }
+    unsafe {
+        std::slice::from_raw_parts(
+            (*frame.as_ptr()).data[0] as *const T,
+            frame.samples() * frame.channels() as usize,
+        )
+    }
+}
+
+/// Streams audio.
+pub struct AudioStreamer {
+    audio_decoder: ffmpeg::decoder::Audio,
+    audio_stream_index: StreamIndex,
+    resampler: ffmpeg::software::resampling::Context,
+    audio_sample_producer: AudioSampleProducer,
+    input_context: Input,
+    player_state: Shared<PlayerState>,
+    duration_ms: i64,
+    // how far the audio has been decoded
+    audio_elapsed_ms: Shared<i64>,
+    // the clock the audio follows: the video's when there is one, otherwise its own
+    primary_elapsed_ms: Shared<i64>,
+    // the position of the audio the device is playing, behind `audio_elapsed_ms` by what is buffered
+    played_elapsed_ms: Shared<i64>,
+    is_primary: bool,
+    audio_tap: AudioTap,
+    // the linear gain normalization applies, limited so it can't clip
+    normalization_gain: Shared<f32>,
+    limiter: Limiter,
+    dsp: Arc<Mutex<DspChain>>,
+    audio_delay_ms: Shared<i64>,
+    output_latency_ms: i64,
+    // the frames of silence currently delaying the audio
+    applied_delay_frames: i64,
+    source_layout: ffmpeg::ChannelLayout,
+    downmix: Shared<DownmixMatrix>,
+    // the matrix the resampler was built with
+    applied_downmix: DownmixMatrix,
+    filter: ConfigurableFilter,
+    // the timestamp of the next frame going into the filter, in samples
+    filter_pts: i64,
+    // samples that didn't fit in the device's buffer when playback stopped, pushed before the next frame's
+    pending_samples: Vec<f32>,
+}
+
+impl AudioStreamer {
+    /// The shift of the audio relative to the video, in milliseconds: positive is later, negative earlier.
+    fn delay_ms(&self) -> i64 {
+        if self.is_primary {
+            return 0;
+        }
+        self.audio_delay_ms.get().clamp(-MAX_AUDIO_DELAY_MS, MAX_AUDIO_DELAY_MS) - self.output_latency_ms
+    }
+
+    /// Pad silence or drop samples so that the audio is delayed by [`AudioStreamer::delay_ms`]. Playing the
+    /// audio earlier is left to [`Streamer::lead_ms`], which decodes ahead of the video.
+    fn apply_delay(&mut self, audio_samples: &mut Vec<f32>, channels: usize, sample_rate: u32) {
+        let target_frames = self.delay_ms().max(0) * sample_rate as i64 / 1000;
+        let change_frames = target_frames - self.applied_delay_frames;
+        if change_frames > 0 {
+            audio_samples.splice(0..0, std::iter::repeat_n(0., change_frames as usize * channels));
+            self.applied_delay_frames += change_frames;
+        } else if change_frames < 0 {
+            let dropped_frames = (-change_frames).min((audio_samples.len() / channels) as i64);
+            audio_samples.drain(..dropped_frames as usize * channels);
+            self.applied_delay_frames -= dropped_frames;
+        }
+    }
+}
+
+impl Streamer for AudioStreamer {
+    type Frame = ffmpeg::frame::Audio;
+    type ProcessedFrame = ();
+    fn stream_type(&self) -> Type {
+        Type::Audio
+    }
+    fn is_primary_streamer(&self) -> bool {
+        self.is_primary
+    }
+    fn lead_ms(&self) -> i64 {
+        (-self.delay_ms()).max(0)
+    }
+    fn stream_index(&self) -> StreamIndex {
+        self.audio_stream_index
+    }
+    fn cycle_stream(&mut self) -> StreamIndex {
+        self.audio_stream_index
+    }
+    fn decoder(&mut self) -> &mut ffmpeg::decoder::Opened {
+        &mut self.audio_decoder.0
+    }
+    fn input_context(&mut self) -> &mut ffmpeg::format::context::Input {
+        &mut self.input_context
+    }
+    fn elapsed_ms(&self) -> &Shared<i64> {
+        &self.audio_elapsed_ms
+    }
+    fn primary_elapsed_ms(&self) -> &Shared<i64> {
+        &self.primary_elapsed_ms
+    }
+    fn duration_ms(&self) -> i64 {
+        self.duration_ms
+    }
+    fn player_state(&self) -> &Shared<PlayerState> {
+        &self.player_state
+    }
+    fn decode_frame(&mut self) -> Result<Self::Frame> {
+        let mut decoded_frame = ffmpeg::frame::Audio::empty();
+        self.audio_decoder.receive_frame(&mut decoded_frame)?;
+        Ok(decoded_frame)
+    }
+    fn process_frame(&mut self, mut frame: Self::Frame) -> Result<Self::ProcessedFrame> {
+        let time_base = self.input_context.stream(self.audio_stream_index.0).map(|stream| stream.time_base());
+        let frame_end_ms = frame.timestamp().zip(time_base).map(|(timestamp, time_base)| {
+            timestamp_to_millisec(timestamp, time_base) + frame.samples() as i64 * 1000 / frame.rate().max(1) as i64
+        });
+        if frame.channel_layout().is_empty() {
+            frame.set_channel_layout(ffmpeg::ChannelLayout::default(frame.channels() as i32));
+        }
+        let output = *self.resampler.output();
+        let downmix = self.downmix.get();
+        if downmix != self.applied_downmix {
+            if needs_downmix(self.source_layout, output.channel_layout.channels() as u16) {
+                self.resampler = audio_resampler(
+                    self.audio_decoder.format(),
+                    self.source_layout,
+                    self.audio_decoder.rate(),
+                    output.channel_layout.channels() as u16,
+                    output.rate,
+                    downmix,
+                )?;
+            }
+            self.applied_downmix = downmix;
+        }
+        // leave room for upsampling, which would otherwise pile up inside the resampler
+        let capacity = frame.samples() * output.rate as usize / frame.rate().max(1) as usize + 256;
+        let mut resampled_frame = ffmpeg::frame::Audio::new(output.format, capacity, output.channel_layout);
+        self.resampler.run(&frame, &mut resampled_frame)?;
+        resampled_frame.set_rate(output.rate);
+        resampled_frame.set_pts(Some(self.filter_pts));
+        self.filter_pts += resampled_frame.samples() as i64;
+        let mut audio_samples: Vec<f32> = self
+            .filter
+            .run_audio(resampled_frame)
+            .iter()
+            .flat_map(|filtered| packed::<f32>(filtered).iter().copied())
+            .collect();
+        let dsp_active = {
+            let mut dsp = self.dsp.lock().unwrap();
+            dsp.process(&mut audio_samples, output.channel_layout.channels() as usize, output.rate);
+            dsp.is_active()
+        };
+        // effects can boost as well, so they are limited too
+        let normalization_gain = self.normalization_gain.get();
+        if normalization_gain != 1. || dsp_active {
+            self.limiter.process(&mut audio_samples, normalization_gain);
+        }
+        let channels = output.channel_layout.channels() as usize;
+        self.apply_delay(&mut audio_samples, channels, output.rate);
+        self.audio_tap.push(&audio_samples);
+        let mut samples = std::mem::take(&mut self.pending_samples);
+        samples.append(&mut audio_samples);
+        self.audio_tap
+            .set_unplayed_frames((self.audio_sample_producer.occupied_len() + samples.len()) / channels);
+        // wait for the device to make room, unless playback stopped in the meantime
+        while self.audio_sample_producer.vacant_len() < samples.len()
+            && self.player_state.get() == PlayerState::Playing
+        {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        // whatever doesn't fit (after a pause) is kept for the next frame instead of being dropped
+        let pushed = self.audio_sample_producer.push_slice(&samples);
+        samples.drain(..pushed);
+        self.pending_samples = samples;
+        // the device is still working through what is buffered, which the clock and the visualization leave out
+        let unplayed_frames = (self.audio_sample_producer.occupied_len() + self.pending_samples.len()) / channels;
+        self.audio_tap.set_unplayed_frames(unplayed_frames);
+        if let Some(frame_end_ms) = frame_end_ms {
+            let unplayed_ms = unplayed_frames as i64 * 1000 / output.rate as i64;
+            self.played_elapsed_ms.set((frame_end_ms - unplayed_ms).max(0));
+        }
+        Ok(())
+    }
+    fn reset(&mut self) {
+        let beginning: i64 = 0;
+        let beginning_seek = beginning.rescale((1, 1), ffmpeg_next::rescale::TIME_BASE);
+        let _ = self.input_context().seek(beginning_seek, ..beginning_seek);
+        self.decoder().flush();
+        self.flush_processed_frames();
+        self.dsp.lock().unwrap().reset();
+        self.audio_elapsed_ms.set(0);
+    }
+    fn flush_processed_frames(&mut self) {
+        self.audio_tap.clear();
+        self.filter.flush();
+        self.filter_pts = 0;
+        self.pending_samples.clear();
+    }
+}
+
+/// Convert a decoded frame to `format` at the given size.
+pub(crate) fn scale_frame(frame: &Video, format: Pixel, width: u32, height: u32) -> Result<Video> {
+    let mut scaled_frame = Video::empty();
+    let mut scaler = ScaleContext::get(
+        frame.format(),
+        frame.width(),
+        frame.height(),
+        format,
+        width,
+        height,
+        Flags::BILINEAR,
+    )?;
+    scaler.run(frame, &mut scaled_frame)?;
+    Ok(scaled_frame)
 }
 
 
 pub struct FFMpegPlayer {
-    /// The video streamer of the player.
-    pub video_streamer: Arc<Mutex<VideoStreamer>>,
-    /// The audio streamer of the player. Won't exist unless [`Player::with_audio`] is called and there exists
+    /// The video streamer of the player. Won't exist for audio-only files.
+    pub video_streamer: Option<Arc<Mutex<VideoStreamer>>>,
+    /// The audio streamer of the player. Won't exist unless [`FFMpegPlayer::with_audio`] is called and there exists
     /// a valid audio stream in the file.
-    // pub audio_streamer: Option<Arc<Mutex<AudioStreamer>>>,
+    pub audio_streamer: Option<Arc<Mutex<AudioStreamer>>>,
     /// The subtitle streamer of the player. Won't exist unless [`Player::with_subtitles`] is called and there exists
     /// a valid subtitle stream in the file.
     // pub subtitle_streamer: Option<Arc<Mutex<SubtitleStreamer>>>,
@@ -425,6 +1155,12 @@ pub struct FFMpegPlayer {
     pub framerate: f64,
     /// Configures certain aspects of this [`Player`].
     pub options: PlayerOptions,
+    /// A description of the input file and all of its streams.
+    pub media_info: MediaInfo,
+    /// Draws the audio, as the main view of audio-only files or optionally over the video.
+    pub visualizer: Visualizer,
+    /// How the frame is fitted, zoomed, panned and cropped into the space it is drawn in.
+    pub viewport: Viewport,
     // audio_stream_info: StreamInfo,
     // subtitle_stream_info: StreamInfo,
     message_sender: PlayerMessageSender,
@@ -447,10 +1183,43 @@ pub struct FFMpegPlayer {
     // subtitles_queue: SubtitleQueue,
     // current_subtitles: Vec<Subtitle>,
     input_path: String,
+    current_frame: CurrentFrame,
+    audio_stream_indices: Vec<usize>,
+    audio_tap: Option<AudioTap>,
+    // audio-only files show their cover art instead of a visualization
+    has_cover_art: bool,
+    loudness: Option<LoudnessInfo>,
+    loudness_reciever: Option<Receiver<Result<LoudnessInfo>>>,
+    // why the last loudness analysis failed; normalization doesn't start another one by itself
+    loudness_error: Option<String>,
+    normalization_gain: Shared<f32>,
+    // a message shown over the frame, and when it was shown
+    osd_message: Option<(String, std::time::Instant)>,
+    audio_output: Option<String>,
+    scan_type: Shared<ScanType>,
+    frames_per_frame: Shared<u8>,
+    // the frames per decoded frame the video timer was scheduled for
+    timer_frames_per_frame: u8,
+    dynamic_range: Shared<DynamicRange>,
+    video_filter_error: Arc<Mutex<Option<String>>>,
+    audio_filter_error: Arc<Mutex<Option<String>>>,
+    // the current frame stays as decoded, the texture shows it adjusted
+    picture_processor: Arc<Mutex<PictureProcessor>>,
+    displayed_frame: CurrentFrame,
+    source_frame: Arc<Mutex<Option<Video>>>,
 }
 
+/// Positions closer than this to the end of the file aren't resumed from, in milliseconds.
+const RESUME_END_MARGIN_MS: i64 = 5000;
+
+/// How long an on-screen message stays visible, in seconds.
+const OSD_DURATION_SECS: f32 = 2.;
+
+/// The most recently displayed frame, at the resolution of the source.
+type CurrentFrame = Arc<Mutex<Option<Arc<ColorImage>>>>;
+
 use chrono::{DateTime, Duration, Utc};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::UNIX_EPOCH;
 fn format_duration(dur: Duration) -> String {
     let dt = DateTime::<Utc>::from(UNIX_EPOCH) + dur;
     if dt.format("%H").to_string().parse::<i64>().unwrap() > 0 {
@@ -467,7 +1236,26 @@ impl FFMpegPlayer {
         self.video_elapsed_ms_override
             .as_ref()
             .map(|i| *i)
-            .unwrap_or(self.video_elapsed_ms.get())
+            .unwrap_or(self.primary_elapsed_ms().get())
+    }
+
+    /// The clock playback follows: the video stream's, or the audio stream's for audio-only files.
+    fn primary_elapsed_ms(&self) -> &Shared<i64> {
+        if self.video_streamer.is_some() {
+            &self.video_elapsed_ms
+        } else {
+            &self.audio_elapsed_ms
+        }
+    }
+
+    /// The path or URL the player was opened with.
+    pub fn input_path(&self) -> &str {
+        &self.input_path
+    }
+
+    /// Is there only audio (and maybe cover art) to play?
+    pub fn is_audio_only(&self) -> bool {
+        self.video_streamer.is_none()
     }
 
     
@@ -484,10 +1272,12 @@ impl FFMpegPlayer {
         self.video_elapsed_ms_override = None;
         self.video_elapsed_ms.set(0);
         self.audio_elapsed_ms.set(0);
-        self.video_streamer.lock().unwrap().reset();
-        // if let Some(audio_decoder) = self.audio_streamer.as_mut() {
-        //     audio_decoder.lock().reset();
-        // }
+        if let Some(video_streamer) = self.video_streamer.as_ref() {
+            video_streamer.lock().unwrap().reset();
+        }
+        if let Some(audio_streamer) = self.audio_streamer.as_ref() {
+            audio_streamer.lock().unwrap().reset();
+        }
     }
     
     fn set_state(&mut self, new_state: PlayerState) {
@@ -512,6 +1302,35 @@ impl FFMpegPlayer {
         self.elapsed_ms() as f32 / self.duration_ms as f32
     }
 
+    /// Show the next video frame and stay paused. Playing video is paused first.
+    pub fn step_frame(&mut self) {
+        match self.player_state.get() {
+            PlayerState::Playing => self.pause(),
+            PlayerState::Paused => (),
+            _ => return,
+        }
+        let Some(video_streamer) = self.video_streamer.as_ref() else {
+            return;
+        };
+        let mut video_streamer = video_streamer.lock().unwrap();
+        if let Ok(frame) = video_streamer.recieve_next_packet_until_frame() {
+            video_streamer.apply_frame(frame);
+        }
+    }
+
+    /// The clock this player's video is shown by, in milliseconds. See [`FFMpegPlayer::follow_clock`].
+    pub fn clock(&self) -> Shared<i64> {
+        self.primary_elapsed_ms().clone()
+    }
+
+    /// Show video frames by `clock` (another player's [`FFMpegPlayer::clock`]) instead of this player's
+    /// own, so the two play in lock-step. The video never gets ahead of the clock.
+    pub fn follow_clock(&mut self, clock: Shared<i64>) {
+        if let Some(video_streamer) = self.video_streamer.as_ref() {
+            video_streamer.lock().unwrap().clock = clock;
+        }
+    }
+
     /// Seek to a location in the stream.
     pub fn seek(&mut self, seek_frac: f32) {
         let current_state = self.player_state.get();
@@ -528,18 +1347,18 @@ impl FFMpegPlayer {
             }
 
             let video_streamer = self.video_streamer.clone();
-            // let mut audio_streamer = self.audio_streamer.clone();
+            let audio_streamer = self.audio_streamer.clone();
             // let mut subtitle_streamer = self.subtitle_streamer.clone();
             // let subtitle_queue = self.subtitles_queue.clone();
 
             self.last_seek_ms = Some((seek_frac as f64 * self.duration_ms as f64) as i64);
             self.set_state(PlayerState::SeekingInProgress);
 
-            // if let Some(audio_streamer) = audio_streamer.take() {
-            //     std::thread::spawn(move || {
-            //         audio_streamer.lock().seek(seek_frac);
-            //     });
-            // };
+            if let Some(audio_streamer) = audio_streamer {
+                std::thread::spawn(move || {
+                    audio_streamer.lock().unwrap().seek(seek_frac);
+                });
+            };
             // if let Some(subtitle_streamer) = subtitle_streamer.take() {
             //     self.current_subtitles.clear();
             //     std::thread::spawn(move || {
@@ -547,9 +1366,15 @@ impl FFMpegPlayer {
             //         subtitle_streamer.lock().seek(seek_frac);
             //     });
             // };
-            std::thread::spawn(move || {
-                video_streamer.lock().unwrap().seek(seek_frac);
-            });
+            if let Some(video_streamer) = video_streamer {
+                std::thread::spawn(move || {
+                    video_streamer.lock().unwrap().seek(seek_frac);
+                });
+            }
+            // audio-only files played without an audio device have no streamer to finish the seek
+            if self.video_streamer.is_none() && self.audio_streamer.is_none() {
+                self.set_state(PlayerState::SeekingFinished);
+            }
         }
     }
 
@@ -562,15 +1387,19 @@ impl FFMpegPlayer {
         let mut texture_handle = self.texture_handle.clone();
         let texture_options = self.options.texture_options;
         let ctx = self.ctx_ref.clone();
-        let wait_duration = Duration::milliseconds((1000. / 60.0) as i64);
-        println!("wait_duration:{:?}",wait_duration);
-        // let wait_duration = Duration::milliseconds((1000. /60.0) as i64);
+        // double rate deinterlacing shows two frames per decoded frame, so 50i plays as 50p
+        self.timer_frames_per_frame = self.frames_per_frame.get().max(1);
+        let frame_rate = if self.framerate.is_finite() && self.framerate > 0. {
+            self.framerate * self.timer_frames_per_frame as f64
+        } else {
+            60.
+        };
+        let wait_duration = Duration::microseconds((1_000_000. / frame_rate) as i64);
         fn play<T: Streamer>(streamer: &Weak<Mutex<T>>) {
-            println!("当前时间:{:?}",SystemTime::now());
             if let Some(streamer) = streamer.upgrade() {
                 if let Ok(mut streamer) = streamer.try_lock() {
                     if (streamer.player_state().get() == PlayerState::Playing)
-                        && streamer.primary_elapsed_ms().get() >= streamer.elapsed_ms().get()
+                        && streamer.primary_elapsed_ms().get() + streamer.lead_ms() >= streamer.elapsed_ms().get()
                     {
                         match streamer.recieve_next_packet_until_frame() {
                             Ok(frame) => streamer.apply_frame(frame),
@@ -584,27 +1413,47 @@ impl FFMpegPlayer {
                 }
             }
         }
-        let mut vs = self.video_streamer.lock().unwrap();
-        vs.apply_video_frame_fn = Some(Box::new(move |frame| {
-            texture_handle.set(frame, texture_options)
-        }));
-
-        let video_streamer_ref = Arc::downgrade(&self.video_streamer);
-
-        let video_timer_guard = self.video_timer.schedule_repeating(wait_duration, move || {
-            play(&video_streamer_ref);
-            ctx.request_repaint();
-        });
+        if let Some(video_streamer) = self.video_streamer.as_ref() {
+            let current_frame = self.current_frame.clone();
+            let picture = self.options.picture.clone();
+            let lut = self.options.lut.clone();
+            let picture_processor = self.picture_processor.clone();
+            let displayed_frame = self.displayed_frame.clone();
+            let mut vs = video_streamer.lock().unwrap();
+            vs.apply_video_frame_fn = Some(Box::new(move |frame| {
+                let lut = lut.lock().unwrap().clone();
+                let frame = Arc::new(frame);
+                let adjusted = picture_processor.lock().unwrap().apply(picture.get(), &lut, &frame);
+                *current_frame.lock().unwrap() = Some(frame);
+                *displayed_frame.lock().unwrap() = Some(adjusted.clone());
+                texture_handle.set(ImageData::Color(adjusted), texture_options)
+            }));
+
+            let video_streamer_ref = Arc::downgrade(video_streamer);
+
+            let video_timer_guard = self.video_timer.schedule_repeating(wait_duration, move || {
+                play(&video_streamer_ref);
+                ctx.request_repaint();
+            });
 
-        self.video_thread = Some(video_timer_guard);
+            self.video_thread = Some(video_timer_guard);
+        }
 
-        // if let Some(audio_decoder) = self.audio_streamer.as_ref() {
-        //     let audio_decoder_ref = Arc::downgrade(audio_decoder);
-        //     let audio_timer_guard = self
-        //         .audio_timer
-        //         .schedule_repeating(Duration::zero(), move || play(&audio_decoder_ref));
-        //     self.audio_thread = Some(audio_timer_guard);
-        // }
+        if let Some(audio_streamer) = self.audio_streamer.as_ref() {
+            let audio_streamer_ref = Arc::downgrade(audio_streamer);
+            // without video frames nothing else keeps the visualization repainting
+            let repaint_ctx = self.video_streamer.is_none().then(|| self.ctx_ref.clone());
+            // the audio streamer blocks while the device's buffer is full, so a short interval is enough
+            let audio_timer_guard = self
+                .audio_timer
+                .schedule_repeating(Duration::milliseconds(5), move || {
+                    play(&audio_streamer_ref);
+                    if let Some(ctx) = repaint_ctx.as_ref() {
+                        ctx.request_repaint();
+                    }
+                });
+            self.audio_thread = Some(audio_timer_guard);
+        }
 
         // if let Some(subtitle_decoder) = self.subtitle_streamer.as_ref() {
         //     let subtitle_decoder_ref = Arc::downgrade(subtitle_decoder);
@@ -682,30 +1531,162 @@ impl FFMpegPlayer {
                 },
             }
         }
+        if let Some(loudness_reciever) = self.loudness_reciever.as_ref() {
+            if let Ok(loudness) = loudness_reciever.try_recv() {
+                match loudness {
+                    Ok(loudness) => self.loudness = Some(loudness),
+                    Err(e) => self.loudness_error = Some(e.to_string()),
+                }
+                self.loudness_reciever = None;
+            }
+        }
+        self.update_normalization_gain();
+        self.update_picture();
+        // the deinterlacer started or stopped doubling the frame rate
+        if self.video_thread.is_some() && self.frames_per_frame.get() != self.timer_frames_per_frame {
+            self.spawn_timers();
+        }
         if reset_stream {
             self.reset();
             self.resume();
         }
     }
 
-    /// Create the [`egui::Image`] for the video frame.
-    pub fn generate_frame_image(&self, size: Vec2) -> Image<'_> {
-        Image::new(SizedTexture::new(self.texture_handle.id(), size)).sense(Sense::click())
+    /// Measure the loudness of the audio in the background. The result is available from
+    /// [`FFMpegPlayer::loudness`] once [`FFMpegPlayer::process_state`] picks it up, or from
+    /// [`FFMpegPlayer::loudness_error`] if it fails. Calling it again after a failure retries.
+    pub fn analyze_loudness(&mut self) {
+        if self.loudness.is_some() || self.loudness_reciever.is_some() {
+            return;
+        }
+        self.loudness_error = None;
+        let (sender, reciever) = channel();
+        let input_path = self.input_path.clone();
+        std::thread::spawn(move || sender.send(analyze_loudness(&input_path)));
+        self.loudness_reciever = Some(reciever);
+    }
+
+    /// The measured loudness of the audio, if [`FFMpegPlayer::analyze_loudness`] finished.
+    pub fn loudness(&self) -> Option<&LoudnessInfo> {
+        self.loudness.as_ref()
+    }
+
+    /// Why the last loudness analysis failed, if it did.
+    pub fn loudness_error(&self) -> Option<&str> {
+        self.loudness_error.as_deref()
+    }
+
+    /// Whether the video is interlaced, according to the flags of the last decoded frame.
+    pub fn scan_type(&self) -> ScanType {
+        self.scan_type.get()
+    }
+
+    /// Whether the video is HDR, according to the transfer function of the last frame decoded.
+    pub fn dynamic_range(&self) -> DynamicRange {
+        self.dynamic_range.get()
+    }
+
+    /// Why [`PlayerOptions::video_filter`] couldn't be applied, if it couldn't.
+    pub fn video_filter_error(&self) -> Option<String> {
+        self.video_filter_error.lock().unwrap().clone()
+    }
+
+    /// Why [`PlayerOptions::audio_filter`] couldn't be applied, if it couldn't.
+    pub fn audio_filter_error(&self) -> Option<String> {
+        self.audio_filter_error.lock().unwrap().clone()
+    }
+
+    /// The channel layout audio is played with, and what it is downmixed from if it has to be.
+    pub fn audio_output(&self) -> Option<&str> {
+        self.audio_output.as_deref()
+    }
+
+    /// Is a loudness analysis running?
+    pub fn is_analyzing_loudness(&self) -> bool {
+        self.loudness_reciever.is_some()
+    }
+
+    /// The gain normalization currently applies, in dB. `None` while it still waits for the loudness analysis.
+    pub fn normalization_gain_db(&self) -> Option<f64> {
+        let analyzed_gain_db = |target_lufs: f64| self.loudness.map(|l| target_lufs - l.integrated_lufs);
+        let gain_db = match self.options.normalization {
+            NormalizationMode::Off => Some(0.),
+            // ReplayGain's clipping prevention: never raise the peak above full scale
+            NormalizationMode::ReplayGain => {
+                let tagged = self.media_info.replay_gain.and_then(|rg| Some((rg.track_gain_db?, rg.track_peak)));
+                match tagged {
+                    Some((gain_db, peak)) => Some(peak.map_or(gain_db, |peak| gain_db.min(-gain_to_db(peak)))),
+                    None => analyzed_gain_db(REPLAY_GAIN_REFERENCE_LUFS)
+                        .zip(self.loudness)
+                        .map(|(gain_db, l)| gain_db.min(-l.true_peak_dbtp)),
+                }
+            }
+            NormalizationMode::Loudness => analyzed_gain_db(self.options.target_lufs),
+        };
+        gain_db.map(|gain_db| gain_db.clamp(-MAX_NORMALIZATION_GAIN_DB, MAX_NORMALIZATION_GAIN_DB))
+    }
+
+    fn update_normalization_gain(&mut self) {
+        let gain_db = self.normalization_gain_db();
+        if gain_db.is_none() && self.audio_streamer.is_some() && self.loudness_error.is_none() {
+            self.analyze_loudness();
+        }
+        self.normalization_gain.set(db_to_gain(gain_db.unwrap_or(0.)) as f32);
+    }
+
+    /// Where the frame goes when drawn in `rect`, with the current [`FFMpegPlayer::viewport`].
+    pub fn frame_layout(&self, rect: Rect) -> ViewportLayout {
+        self.viewport
+            .layout(rect, self.texture_handle.size_vec2(), self.ctx_ref.pixels_per_point())
+    }
+
+    /// Create the [`egui::Image`] for the visible part of the video frame in `layout`.
+    pub fn generate_frame_image(&self, layout: &ViewportLayout) -> Image<'_> {
+        Image::new(SizedTexture::new(self.texture_handle.id(), layout.visible.size())).uv(layout.uv)
     }
 
     /// Draw the video frame with a specific rect (without controls). Make sure to call [`Player::process_state`].
-    pub fn render_frame(&self, ui: &mut Ui, size: Vec2) -> Response {
-        ui.add(self.generate_frame_image(size))
+    pub fn render_frame(&mut self, ui: &mut Ui, size: Vec2) -> Response {
+        let (_, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+        self.paint_frame(ui, response)
     }
 
     /// Draw the video frame (without controls). Make sure to call [`Player::process_state`].
-    pub fn render_frame_at(&self, ui: &mut Ui, rect: Rect) -> Response {
-        ui.put(rect, self.generate_frame_image(rect.size()))
+    pub fn render_frame_at(&mut self, ui: &mut Ui, rect: Rect) -> Response {
+        let response = ui.allocate_rect(rect, Sense::click_and_drag());
+        self.paint_frame(ui, response)
+    }
+
+    /// Draw the frame in the space of `response` through the [`FFMpegPlayer::viewport`]. Scrolling over it
+    /// zooms around the cursor and dragging it pans.
+    fn paint_frame(&mut self, ui: &mut Ui, response: Response) -> Response {
+        let rect = response.rect;
+        if let Some(frame) = self.current_frame.lock().unwrap().clone() {
+            self.viewport.detect_crop(&frame);
+        }
+        if let Some(hover_pos) = response.hover_pos() {
+            let (scroll, pinch) = ui.input(|i| (i.smooth_scroll_delta.y, i.zoom_delta()));
+            let factor = pinch * (scroll / 200.).exp();
+            if factor != 1. {
+                self.viewport.zoom_at(rect, hover_pos, factor);
+            }
+        }
+        if response.dragged_by(egui::PointerButton::Primary) {
+            self.viewport.pan_by(response.drag_delta());
+        }
+        let layout = self.frame_layout(rect);
+        if layout.visible.is_positive() {
+            self.generate_frame_image(&layout).paint_at(ui, layout.visible);
+        }
+        response
     }
 
     /// Draw the video frame and player controls and process state changes.
     pub fn ui(&mut self, ui: &mut Ui, size: Vec2) -> egui::Response {
         let frame_response = self.render_frame(ui, size);
+        self.render_visualization(ui, &frame_response);
+        self.render_loupe(ui, &frame_response);
+        self.render_osd(ui, &frame_response);
         self.render_controls(ui, &frame_response);
         // self.render_subtitles(ui, &frame_response);
         self.process_state();
@@ -715,12 +1696,130 @@ impl FFMpegPlayer {
     /// Draw the video frame and player controls with a specific rect, and process state changes.
     pub fn ui_at(&mut self, ui: &mut Ui, rect: Rect) -> egui::Response {
         let frame_response = self.render_frame_at(ui, rect);
+        self.render_visualization(ui, &frame_response);
+        self.render_loupe(ui, &frame_response);
+        self.render_osd(ui, &frame_response);
         self.render_controls(ui, &frame_response);
         // self.render_subtitles(ui, &frame_response);
         self.process_state();
         frame_response
     }
 
+    /// What is at `pos` of the frame drawn in `frame_rect` (as returned by [`FFMpegPlayer::render_frame`] or
+    /// [`FFMpegPlayer::render_frame_at`]): the decoded pixel there and its displayed color.
+    pub fn pixel_at(&self, frame_rect: Rect, pos: Pos2) -> Option<PixelInfo> {
+        let uv = self.frame_layout(frame_rect).uv_at(pos)?;
+        let (displayed, [dx, dy]) = self.displayed_pixel_at(uv)?;
+        let source = self.source_frame.lock().unwrap();
+        let source = source.as_ref()?;
+        let x = ((uv.x * source.width() as f32) as u32).min(source.width().saturating_sub(1));
+        let y = ((uv.y * source.height() as f32) as u32).min(source.height().saturating_sub(1));
+        let (components, bit_depth) = decoded_components(source, x, y)?;
+        Some(PixelInfo {
+            x,
+            y,
+            components,
+            bit_depth,
+            displayed: displayed.pixels[dy * displayed.size[0] + dx],
+        })
+    }
+
+    /// The displayed frame and the position of its pixel at texture coordinates `uv`.
+    fn displayed_pixel_at(&self, uv: Pos2) -> Option<(Arc<ColorImage>, [usize; 2])> {
+        let displayed = self.displayed_frame()?;
+        let [width, height] = displayed.size;
+        let x = ((uv.x * width as f32) as usize).min(width.saturating_sub(1));
+        let y = ((uv.y * height as f32) as usize).min(height.saturating_sub(1));
+        Some((displayed, [x, y]))
+    }
+
+    /// Draw a magnified view of the pixels under the cursor and their values, if
+    /// [`PlayerOptions::pixel_inspector`] is set.
+    pub fn render_loupe(&self, ui: &mut Ui, frame_response: &Response) {
+        if !self.options.pixel_inspector {
+            return;
+        }
+        let Some(pos) = frame_response.hover_pos() else {
+            return;
+        };
+        let Some((displayed, center)) = self
+            .frame_layout(frame_response.rect)
+            .uv_at(pos)
+            .and_then(|uv| self.displayed_pixel_at(uv))
+        else {
+            return;
+        };
+        let lines = match self.pixel_at(frame_response.rect, pos) {
+            Some(info) => info.describe(),
+            None => vec![String::from("no decoded values")],
+        };
+        loupe::draw_loupe(ui.painter(), pos, &displayed, center, &lines);
+    }
+
+    /// Draw a visualization of the audio in place of the frame of audio-only files without cover art,
+    /// or over the bottom of the frame if [`VisualizerOptions::overlay`](crate::visualizer::VisualizerOptions::overlay) is set.
+    pub fn render_visualization(&mut self, ui: &mut Ui, frame_response: &Response) {
+        let Some(audio_tap) = self.audio_tap.as_ref() else {
+            return;
+        };
+        if self.video_streamer.is_none() && !self.has_cover_art {
+            self.visualizer
+                .draw(ui.painter(), frame_response.rect, audio_tap, Color32::from_gray(16));
+        } else if self.visualizer.options.overlay {
+            let mut overlay_rect = frame_response.rect;
+            overlay_rect.set_top(overlay_rect.bottom() - overlay_rect.height() / 3.);
+            self.visualizer
+                .draw(ui.painter(), overlay_rect, audio_tap, Color32::from_black_alpha(120));
+        }
+    }
+
+    /// Show `message` over the top left of the frame for a moment.
+    pub fn show_osd(&mut self, message: impl Into<String>) {
+        self.osd_message = Some((message.into(), std::time::Instant::now()));
+    }
+
+    /// Draw the current on-screen message, if there is one.
+    pub fn render_osd(&mut self, ui: &mut Ui, frame_response: &Response) {
+        let Some((message, shown_at)) = self.osd_message.as_ref() else {
+            return;
+        };
+        let age_secs = shown_at.elapsed().as_secs_f32();
+        if age_secs > OSD_DURATION_SECS {
+            self.osd_message = None;
+            return;
+        }
+        // fade out over the last half second
+        let opacity = ((OSD_DURATION_SECS - age_secs) / 0.5).min(1.);
+        let font_id = FontId {
+            size: 18.,
+            ..Default::default()
+        };
+        let galley = ui
+            .painter()
+            .layout_no_wrap(message.clone(), font_id, Color32::WHITE.linear_multiply(opacity));
+        let text_pos = frame_response.rect.left_top() + vec2(12., 12.);
+        let background_rect = Rect::from_min_size(text_pos, galley.size()).expand(6.);
+        ui.painter().rect_filled(
+            background_rect,
+            CornerRadius::same(4),
+            Color32::from_black_alpha(160).linear_multiply(opacity),
+        );
+        ui.painter().galley(text_pos, galley, Color32::WHITE);
+        ui.ctx().request_repaint();
+    }
+
+    /// Set [`PlayerOptions::audio_delay_ms`], showing the new delay on screen.
+    pub fn set_audio_delay_ms(&mut self, delay_ms: i64) {
+        let delay_ms = delay_ms.clamp(-MAX_AUDIO_DELAY_MS, MAX_AUDIO_DELAY_MS);
+        self.options.audio_delay_ms.set(delay_ms);
+        self.show_osd(format!("audio delay: {delay_ms:+} ms"));
+    }
+
+    /// Change [`PlayerOptions::audio_delay_ms`] by `delta_ms`, showing the new delay on screen.
+    pub fn adjust_audio_delay_ms(&mut self, delta_ms: i64) {
+        self.set_audio_delay_ms(self.options.audio_delay_ms.get() + delta_ms);
+    }
+
     /// Draw the player controls. Make sure to call [`Player::process_state()`]. Unless you are explicitly
     /// drawing something in between the video frames and controls, it is probably better to use
     /// [`Player::ui`] or [`Player::ui_at`].
@@ -1028,87 +2127,130 @@ impl FFMpegPlayer {
         //         .memory_mut(|m| m.data.insert_temp(stream_anim_id, stream_anim_frac));
         // }
 
-        // if self.audio_streamer.is_some() {
-            // let sound_icon_rect = ui.painter().text(
-            //     sound_icon_pos,
-            //     Align2::RIGHT_BOTTOM,
-            //     sound_icon,
-            //     icon_font_id.clone(),
-            //     text_color,
-            // );
-            // if ui
-            //     .interact(
-            //         sound_icon_rect,
-            //         frame_response.id.with("sound_icon_sense"),
-            //         Sense::click(),
-            //     )
-            //     .clicked()
-            // {
-            //     if self.options.audio_volume.get() != 0. {
-            //         self.options.audio_volume.set(0.)
-            //     } else {
-            //         self.options
-            //             .audio_volume
-            //             .set(self.options.max_audio_volume / 2.)
-            //     }
-            // }
-
-            // let sound_slider_outer_height = 75.;
-
-            // let mut sound_slider_rect = sound_icon_rect;
-            // sound_slider_rect.set_bottom(sound_icon_rect.top() - icon_margin);
-            // sound_slider_rect.set_top(sound_slider_rect.top() - sound_slider_outer_height);
-
-            // let sound_slider_interact_rect = sound_slider_rect.expand(icon_margin);
-            // let sound_hovered = ui.rect_contains_pointer(sound_icon_rect);
-            // let sound_slider_hovered = ui.rect_contains_pointer(sound_slider_interact_rect);
-            // let sound_anim_id = frame_response.id.with("sound_anim");
-            // let mut sound_anim_frac: f32 = ui
-            //     .ctx()
-            //     .memory_mut(|m| *m.data.get_temp_mut_or_default(sound_anim_id));
-            // sound_anim_frac = ui.ctx().animate_bool_with_time(
-            //     sound_anim_id,
-            //     sound_hovered || (sound_slider_hovered && sound_anim_frac > 0.),
-            //     0.2,
-            // );
-            // ui.ctx()
-            //     .memory_mut(|m| m.data.insert_temp(sound_anim_id, sound_anim_frac));
-            // let sound_slider_bg_color =
-            //     Color32::from_black_alpha(contraster_alpha).linear_multiply(sound_anim_frac);
-            // let sound_bar_color =
-            //     Color32::from_white_alpha(contraster_alpha).linear_multiply(sound_anim_frac);
-            // let mut sound_bar_rect = sound_slider_rect;
-            // sound_bar_rect
-            //     .set_top(sound_bar_rect.bottom() - audio_volume_frac * sound_bar_rect.height());
-
-            // ui.painter()
-            //     .rect_filled(sound_slider_rect, Rounding::same(5.), sound_slider_bg_color);
-
-            // ui.painter()
-            //     .rect_filled(sound_bar_rect, Rounding::same(5.), sound_bar_color);
-            // let sound_slider_resp = ui.interact(
-            //     sound_slider_rect,
-            //     frame_response.id.with("sound_slider_sense"),
-            //     Sense::click_and_drag(),
-            // );
-            // if sound_anim_frac > 0. && sound_slider_resp.clicked() || sound_slider_resp.dragged() {
-            //     if let Some(hover_pos) = ui.ctx().input(|i| i.pointer.hover_pos()) {
-            //         let sound_frac = 1.
-            //             - ((hover_pos - sound_slider_rect.left_top()).y
-            //                 / sound_slider_rect.height())
-            //             .clamp(0., 1.);
-            //         self.options
-            //             .audio_volume
-            //             .set(sound_frac * self.options.max_audio_volume);
-            //     }
-            // }
-        // }
+        if self.audio_streamer.is_some() {
+            let sound_icon_rect = ui.painter().text(
+                sound_icon_pos,
+                Align2::RIGHT_BOTTOM,
+                sound_icon,
+                icon_font_id.clone(),
+                text_color,
+            );
+            if ui
+                .interact(
+                    sound_icon_rect,
+                    frame_response.id.with("sound_icon_sense"),
+                    Sense::click(),
+                )
+                .clicked()
+            {
+                if self.options.audio_volume.get() != 0. {
+                    self.options.audio_volume.set(0.)
+                } else {
+                    self.options
+                        .audio_volume
+                        .set(self.options.max_audio_volume / 2.)
+                }
+            }
+
+            let sound_slider_outer_height = 75.;
+
+            let mut sound_slider_rect = sound_icon_rect;
+            sound_slider_rect.set_bottom(sound_icon_rect.top() - icon_margin);
+            sound_slider_rect.set_top(sound_slider_rect.top() - sound_slider_outer_height);
+
+            let sound_slider_interact_rect = sound_slider_rect.expand(icon_margin);
+            let sound_hovered = ui.rect_contains_pointer(sound_icon_rect);
+            let sound_slider_hovered = ui.rect_contains_pointer(sound_slider_interact_rect);
+            let sound_anim_id = frame_response.id.with("sound_anim");
+            let mut sound_anim_frac: f32 = ui
+                .ctx()
+                .memory_mut(|m| *m.data.get_temp_mut_or_default(sound_anim_id));
+            sound_anim_frac = ui.ctx().animate_bool_with_time(
+                sound_anim_id,
+                sound_hovered || (sound_slider_hovered && sound_anim_frac > 0.),
+                0.2,
+            );
+            ui.ctx()
+                .memory_mut(|m| m.data.insert_temp(sound_anim_id, sound_anim_frac));
+            let sound_slider_bg_color =
+                Color32::from_black_alpha(contraster_alpha).linear_multiply(sound_anim_frac);
+            let sound_bar_color =
+                Color32::from_white_alpha(contraster_alpha).linear_multiply(sound_anim_frac);
+            let mut sound_bar_rect = sound_slider_rect;
+            sound_bar_rect
+                .set_top(sound_bar_rect.bottom() - audio_volume_frac * sound_bar_rect.height());
+
+            ui.painter()
+                .rect_filled(sound_slider_rect, CornerRadius::same(5), sound_slider_bg_color);
+
+            ui.painter()
+                .rect_filled(sound_bar_rect, CornerRadius::same(5), sound_bar_color);
+            let sound_slider_resp = ui.interact(
+                sound_slider_rect,
+                frame_response.id.with("sound_slider_sense"),
+                Sense::click_and_drag(),
+            );
+            if sound_anim_frac > 0. && sound_slider_resp.clicked() || sound_slider_resp.dragged() {
+                if let Some(hover_pos) = ui.ctx().input(|i| i.pointer.hover_pos()) {
+                    let sound_frac = 1.
+                        - ((hover_pos - sound_slider_rect.left_top()).y
+                            / sound_slider_rect.height())
+                        .clamp(0., 1.);
+                    self.options
+                        .audio_volume
+                        .set(sound_frac * self.options.max_audio_volume);
+                }
+            }
+        }
+    }
+
+
+    /// The current frame as it is displayed, with the LUT and picture adjustments applied.
+    pub fn displayed_frame(&self) -> Option<Arc<ColorImage>> {
+        self.displayed_frame.lock().unwrap().clone()
+    }
+
+    /// The current frame at the resolution of the source, independent of the size it is displayed at.
+    pub fn snapshot(&self) -> Option<RgbaImage> {
+        self.snapshot_with(&SnapshotOptions::default())
+    }
+
+    /// Same as [`FFMpegPlayer::snapshot`], with optional burned-in subtitles.
+    pub fn snapshot_with(&self, options: &SnapshotOptions) -> Option<RgbaImage> {
+        let frame = self.current_frame.lock().unwrap().clone()?;
+        let mut image = snapshot::color_image_to_rgba(&frame);
+        if let Some(text) = options.burned_in_subtitle.as_ref() {
+            snapshot::burn_in_text(&self.ctx_ref, &mut image, text, options);
+        }
+        Some(image)
     }
 
+    /// Save the current frame to `path`. The format (png, jpeg or webp) is chosen by the extension.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.save_snapshot_with(path, &SnapshotOptions::default())
+    }
+
+    /// Same as [`FFMpegPlayer::save_snapshot`], with optional burned-in subtitles.
+    pub fn save_snapshot_with(&self, path: impl AsRef<Path>, options: &SnapshotOptions) -> Result<()> {
+        let image = self
+            .snapshot_with(options)
+            .ok_or(anyhow::anyhow!("no frame has been decoded yet"))?;
+        snapshot::save_rgba_image(&image, path.as_ref())
+    }
+
+    /// A snapshot filename derived from the input file and the current position, e.g. `movie_00-01-23.456.png`.
+    pub fn default_snapshot_filename(&self, extension: &str) -> String {
+        snapshot::default_snapshot_filename(&self.input_path, self.elapsed_ms(), extension)
+    }
+
+    /// The indices of the audio streams in the input, which aren't played yet but can be exported.
+    pub fn audio_stream_indices(&self) -> &[usize] {
+        &self.audio_stream_indices
+    }
 
     #[cfg(feature = "from_bytes")]
     /// Create a new [`Player`] from input bytes.
-    pub fn from_bytes(ctx: &egui::Context, input_bytes: &[u8]) -> Result<Self> {
+    pub fn from_bytes(ctx: &egui::Context, input_bytes: &[u8]) -> Result<Self, PlayerError> {
         let mut file = tempfile::Builder::new().tempfile()?;
         file.write_all(input_bytes)?;
         let path = file.path().to_string_lossy().to_string();
@@ -1130,64 +2272,187 @@ impl FFMpegPlayer {
         };
     }
 
-    fn try_set_texture_handle(&mut self) -> Result<TextureHandle> {
-        match self.video_streamer.lock().unwrap().recieve_next_packet_until_frame() {
-            Ok(first_frame) => {
-                let texture_handle = self.ctx_ref.load_texture(
-                    "vidstream",
-                    first_frame,
-                    self.options.texture_options,
-                );
-                let texture_handle_clone = texture_handle.clone();
-                self.texture_handle = texture_handle;
-                Ok(texture_handle_clone)
-            }
-            Err(e) => Err(e),
+    fn set_first_frame(&mut self, first_frame: ColorImage) {
+        let lut = self.options.lut.lock().unwrap().clone();
+        let first_frame = Arc::new(first_frame);
+        let adjusted = self.picture_processor.lock().unwrap().apply(self.options.picture.get(), &lut, &first_frame);
+        *self.current_frame.lock().unwrap() = Some(first_frame);
+        *self.displayed_frame.lock().unwrap() = Some(adjusted.clone());
+        self.texture_handle = self.ctx_ref.load_texture(
+            "vidstream",
+            ImageData::Color(adjusted),
+            self.options.texture_options,
+        );
+    }
+
+    /// Show the current frame with the current [`PlayerOptions::picture`] and [`PlayerOptions::lut`], if it
+    /// was shown with others. Playing video picks them up with the next frame, this keeps the picture live
+    /// while paused.
+    fn update_picture(&mut self) {
+        let picture = self.options.picture.get();
+        let lut = self.options.lut.lock().unwrap().clone();
+        if self.video_streamer.is_none() || self.picture_processor.lock().unwrap().is_current(picture, &lut) {
+            return;
+        }
+        let Some(frame) = self.current_frame.lock().unwrap().clone() else {
+            return;
+        };
+        let adjusted = self.picture_processor.lock().unwrap().apply(picture, &lut, &frame);
+        *self.displayed_frame.lock().unwrap() = Some(adjusted.clone());
+        self.texture_handle.set(ImageData::Color(adjusted), self.options.texture_options);
+    }
+
+    /// The settings to remember for this file: where playback is and the picture adjustments.
+    pub fn file_settings(&self) -> FileSettings {
+        // files played to the end start over next time
+        let near_end = self.elapsed_ms() > self.duration_ms - RESUME_END_MARGIN_MS;
+        FileSettings {
+            resume_position_ms: if near_end { 0 } else { self.elapsed_ms() },
+            picture: self.options.picture.get(),
+        }
+    }
+
+    /// Remember [`FFMpegPlayer::file_settings`] for the next time this file is opened.
+    pub fn remember_file_settings(&self) -> Result<()> {
+        self.file_settings().save(&self.input_path)
+    }
+
+    /// Apply the settings remembered for this file, if there are any: restore the picture adjustments and
+    /// seek to where playback stopped.
+    pub fn restore_file_settings(&mut self) {
+        let Some(settings) = FileSettings::load(&self.input_path) else {
+            return;
+        };
+        self.options.picture.set(settings.picture);
+        if settings.resume_position_ms > 0 && self.duration_ms > 0 {
+            self.seek(settings.resume_position_ms as f32 / self.duration_ms as f32);
         }
     }
 
     /// Create a new [`Player`].
-    pub fn new(ctx: &egui::Context, input_path: &String) -> Result<Self> {
-        let input_context = input(&input_path)?;
-        let video_stream = input_context
+    pub fn new(ctx: &egui::Context, input_path: &String) -> Result<Self, PlayerError> {
+        Self::new_with_limits(ctx, input_path, OpenLimits::default())
+    }
+
+    /// Create a new [`Player`], giving up if opening the input, probing it or decoding its first frame
+    /// takes longer than `limits` allow.
+    pub fn new_with_limits(ctx: &egui::Context, input_path: &String, limits: OpenLimits) -> Result<Self, PlayerError> {
+        // open: report missing or unreadable local files before ffmpeg turns them into a generic error
+        if !input_path.contains("://") {
+            std::fs::File::open(input_path)?;
+        }
+        // blocking reads are aborted by the interrupt callback once the deadline of the current phase passes
+        let deadline = Arc::new(Mutex::new(Some(std::time::Instant::now() + limits.open_timeout)));
+        let interrupt_deadline = deadline.clone();
+        let input_context = ffmpeg::format::input_with_interrupt(&input_path, move || {
+            interrupt_deadline
+                .lock()
+                .unwrap()
+                .is_some_and(|deadline| std::time::Instant::now() > deadline)
+        })
+        .map_err(PlayerError::OpenFailed)?;
+
+        // probe
+        let video_stream_index = best_video_stream(&input_context).map(|stream| stream.index());
+        let has_audio = input_context.streams().best(Type::Audio).is_some();
+        if video_stream_index.is_none() && !has_audio {
+            return Err(PlayerError::NoPlayableStream);
+        }
+        let framerate = video_stream_index
+            .and_then(|index| input_context.stream(index))
+            .map(|stream| {
+                (stream.avg_frame_rate().numerator() as f64) / stream.avg_frame_rate().denominator() as f64
+            })
+            .unwrap_or(0.);
+        let media_info = MediaInfo::from_input(&input_context, input_path);
+        let audio_stream_indices = input_context
             .streams()
-            .best(Type::Video)
-            .ok_or(ffmpeg::Error::StreamNotFound)?;
-        let video_stream_index = StreamIndex(video_stream.index());
+            .filter(|s| s.parameters().medium() == Type::Audio)
+            .map(|s| s.index())
+            .collect();
+        let container_duration_ms =
+            timestamp_to_millisec(input_context.duration(), ffmpeg_next::rescale::TIME_BASE).max(0);
 
         let video_elapsed_ms = Shared::new(0);
         let audio_elapsed_ms = Shared::new(0);
         let player_state = Shared::new(PlayerState::Stopped);
 
-        let video_context =
-            ffmpeg::codec::context::Context::from_parameters(video_stream.parameters())?;
-        let video_decoder = video_context.decoder().video()?;
-        let framerate = (video_stream.avg_frame_rate().numerator() as f64)
-            / video_stream.avg_frame_rate().denominator() as f64;
-
-        let (width, height) = (video_decoder.width(), video_decoder.height());
-        let size = Vec2::new(width as f32, height as f32);
-        let duration_ms = timestamp_to_millisec(input_context.duration(), AV_TIME_BASE_RATIONAL); // in sec
-        // let duration_ms = 16;
-        let stream_decoder = VideoStreamer {
-            apply_video_frame_fn: None,
-            duration_ms,
-            video_decoder,
-            video_stream_index,
-            _audio_elapsed_ms: audio_elapsed_ms.clone(),
-            video_elapsed_ms: video_elapsed_ms.clone(),
-            input_context,
-            player_state: player_state.clone(),
+        let mut video_streamer = match video_stream_index {
+            Some(_) => Some(VideoStreamer::new(
+                input_context,
+                player_state.clone(),
+                video_elapsed_ms.clone(),
+                audio_elapsed_ms.clone(),
+            )?),
+            None => None,
+        };
+        // audio-only files show their cover art, or a visualization on a black frame
+        let cover_art = match video_streamer {
+            Some(_) => None,
+            None => decode_cover_art(input_path),
+        };
+        let (size, duration_ms) = match (video_streamer.as_ref(), cover_art.as_ref()) {
+            (Some(vs), _) => (
+                Vec2::new(vs.video_decoder.width() as f32, vs.video_decoder.height() as f32),
+                vs.duration_ms,
+            ),
+            (None, Some(cover_art)) => (
+                Vec2::new(cover_art.size[0] as f32, cover_art.size[1] as f32),
+                container_duration_ms,
+            ),
+            (None, None) => (Vec2::new(640., 360.), container_duration_ms),
         };
         let options = PlayerOptions::default();
+        let video_filter_error = Arc::new(Mutex::new(None));
+        let (scan_type, frames_per_frame, dynamic_range, source_frame) = match video_streamer.as_mut() {
+            Some(vs) => {
+                vs.deinterlace = options.deinterlace.clone();
+                vs.filter = ConfigurableFilter::new(options.video_filter.clone(), video_filter_error.clone());
+                vs.tone_map = options.tone_map.clone();
+                (
+                    vs.scan_type.clone(),
+                    vs.frames_per_frame.clone(),
+                    vs.dynamic_range.clone(),
+                    vs.source_frame.clone(),
+                )
+            }
+            None => (
+                Shared::new(ScanType::Unknown),
+                Shared::new(1),
+                Shared::new(DynamicRange::Unknown),
+                Arc::new(Mutex::new(None)),
+            ),
+        };
         let texture_handle =
             ctx.load_texture("vidstream", ColorImage::example(), options.texture_options);
         let (message_sender, message_reciever) = std::sync::mpsc::channel();
-        let mut streamer = Self {
+        let mut player = Self {
             input_path: input_path.clone(),
-            // audio_streamer: None,
+            current_frame: Arc::new(Mutex::new(None)),
+            audio_stream_indices,
+            audio_tap: None,
+            has_cover_art: cover_art.is_some(),
+            loudness: None,
+            loudness_reciever: None,
+            loudness_error: None,
+            normalization_gain: Shared::new(1.),
+            osd_message: None,
+            audio_output: None,
+            scan_type,
+            frames_per_frame,
+            timer_frames_per_frame: 1,
+            dynamic_range,
+            video_filter_error,
+            audio_filter_error: Arc::new(Mutex::new(None)),
+            picture_processor: Arc::new(Mutex::new(PictureProcessor::default())),
+            displayed_frame: Arc::new(Mutex::new(None)),
+            source_frame,
+            media_info,
+            visualizer: Visualizer::default(),
+            viewport: Viewport::default(),
+            audio_streamer: None,
             // subtitle_streamer: None,
-            video_streamer: Arc::new(Mutex::new(stream_decoder)),
+            video_streamer: video_streamer.map(|vs| Arc::new(Mutex::new(vs))),
             // subtitle_stream_info: StreamInfo::new(),
             // audio_stream_info: StreamInfo::new(),
             framerate,
@@ -1216,15 +2481,118 @@ impl FFMpegPlayer {
             #[cfg(feature = "from_bytes")]
             temp_file: None,
         };
-        
-         
-        loop {
-            if let Ok(_texture_handle) = streamer.try_set_texture_handle() {
-                break;
+
+        // first frame
+        let first_frame = match player.video_streamer.as_ref() {
+            Some(video_streamer) => {
+                *deadline.lock().unwrap() = Some(std::time::Instant::now() + limits.first_frame_timeout);
+                let first_frame = video_streamer
+                    .lock()
+                    .unwrap()
+                    .recieve_first_frame(limits.first_frame_max_packets, limits.first_frame_timeout);
+                *deadline.lock().unwrap() = None;
+                first_frame?
             }
-        }
+            None => cover_art.unwrap_or_else(|| {
+                ColorImage::filled([size.x as usize, size.y as usize], Color32::BLACK)
+            }),
+        };
+        player.set_first_frame(first_frame);
+
+        Ok(player)
+    }
+
+    /// Initializes the audio stream (if there is one), required for making a [`FFMpegPlayer`] output audio.
+    /// [`FFMpegPlayer::stop`] **must** be called before calling this function.
+    pub fn add_audio(&mut self, audio_device: &mut AudioDevice) -> Result<(), PlayerError> {
+        let input_context = input(&self.input_path).map_err(PlayerError::OpenFailed)?;
+        let Some(audio_stream) = input_context.streams().best(Type::Audio) else {
+            return Ok(());
+        };
+        let audio_stream_index = audio_stream.index();
+        let audio_context = ffmpeg::codec::context::Context::from_parameters(audio_stream.parameters())
+            .map_err(PlayerError::DecoderOpenFailed)?;
+        let audio_decoder = audio_context.decoder().audio().map_err(PlayerError::DecoderOpenFailed)?;
+
+        let spec = audio_device.0.spec();
+        let is_primary = self.video_streamer.is_none();
+        // room for the device's buffer and a decoded frame, and for the largest delay when there is video to
+        // delay against. Audio-only files fill the whole buffer, which pausing and seeking have to play out.
+        let delay_frames = if is_primary { 0 } else { (MAX_AUDIO_DELAY_MS * spec.freq as i64 / 1000) as usize };
+        let buffer_frames = spec.samples as usize * 4 + delay_frames + 8192;
+        let (audio_sample_producer, sample_consumer) =
+            HeapRb::<f32>::new(buffer_frames * spec.channels as usize).split();
+        // surround passes through if the device has the channels for it, otherwise it is downmixed
+        let source_layout = source_channel_layout(&audio_decoder);
+        let downmix = self.options.downmix.get();
+        let resampler = audio_resampler(
+            audio_decoder.format(),
+            source_layout,
+            audio_decoder.rate(),
+            spec.channels as u16,
+            spec.freq as u32,
+            downmix,
+        )
+        .map_err(PlayerError::DecoderOpenFailed)?;
+        let output_layout = ffmpeg::ChannelLayout::default(spec.channels as i32);
+        self.audio_output = Some(if needs_downmix(source_layout, spec.channels as u16) {
+            format!(
+                "{} → {} (downmixed)",
+                describe_channel_layout(source_layout),
+                describe_channel_layout(output_layout)
+            )
+        } else {
+            describe_channel_layout(output_layout)
+        });
+
+        audio_device.0.lock().sample_streams.push(AudioSampleStream {
+            sample_consumer,
+            audio_volume: self.options.audio_volume.clone(),
+        });
+        audio_device.0.resume();
+
+        self.stop();
+        let decoded_elapsed_ms = Shared::new(0);
+        let primary_elapsed_ms = if is_primary {
+            decoded_elapsed_ms.clone()
+        } else {
+            self.video_elapsed_ms.clone()
+        };
+        let audio_tap = AudioTap::new(spec.channels as usize, spec.freq as u32);
+        self.audio_tap = Some(audio_tap.clone());
+        self.audio_streamer = Some(Arc::new(Mutex::new(AudioStreamer {
+            audio_decoder,
+            audio_stream_index: StreamIndex(audio_stream_index),
+            resampler,
+            audio_sample_producer,
+            input_context,
+            player_state: self.player_state.clone(),
+            duration_ms: self.duration_ms,
+            audio_elapsed_ms: decoded_elapsed_ms,
+            primary_elapsed_ms,
+            played_elapsed_ms: self.audio_elapsed_ms.clone(),
+            is_primary,
+            audio_tap,
+            normalization_gain: self.normalization_gain.clone(),
+            limiter: Limiter::new(spec.channels as usize, spec.freq as u32),
+            dsp: self.options.dsp.clone(),
+            audio_delay_ms: self.options.audio_delay_ms.clone(),
+            output_latency_ms: audio_device.latency_ms(),
+            applied_delay_frames: 0,
+            source_layout,
+            downmix: self.options.downmix.clone(),
+            applied_downmix: downmix,
+            filter: ConfigurableFilter::new(self.options.audio_filter.clone(), self.audio_filter_error.clone()),
+            filter_pts: 0,
+            pending_samples: Vec::new(),
+        })));
+        Ok(())
+    }
 
-        Ok(streamer)
+    /// Enables using [`FFMpegPlayer::add_audio`] with the builder pattern.
+    pub fn with_audio(mut self, audio_device: &mut AudioDevice) -> Result<Self, PlayerError> {
+        self.add_audio(audio_device)?;
+        Ok(self)
     }
 
 
@@ -1239,7 +2607,7 @@ impl FFMpegPlayer {
 
 use ffmpeg_next::frame::Video;
 
-fn video_frame_to_image(frame: Video) -> ColorImage {
+pub(crate) fn video_frame_to_image(frame: Video) -> ColorImage {
     let size = [frame.width() as usize, frame.height() as usize];
     let data = frame.data(0);
     let stride = frame.stride(0);
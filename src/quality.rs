@@ -0,0 +1,519 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::format::Pixel;
+use ffmpeg::frame::Video;
+use egui::ColorImage;
+use anyhow::Result;
+use serde::Serialize;
+use crate::player::{is_ffmpeg_eof_error, scale_frame, timestamp_to_millisec, VideoStreamer};
+
+/// The PSNR of identical planes, which is infinite, when it has to be finite (e.g. to graph it), in dB.
+pub const IDENTICAL_PSNR_DB: f64 = 100.;
+
+/// How far apart frames may be to be compared before the reference's frame interval is known, in milliseconds.
+const DEFAULT_ALIGN_TOLERANCE_MS: i64 = 20;
+
+/// The side of the windows SSIM compares, and how far apart they start, in pixels.
+const SSIM_WINDOW: usize = 8;
+const SSIM_STEP: usize = 4;
+
+/// One 8 bit plane of a picture, e.g. the luma of a frame.
+#[derive(Clone, Copy)]
+pub struct Plane<'a> {
+    pub data: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    /// Bytes from the start of one row to the start of the next.
+    pub stride: usize,
+}
+
+impl<'a> Plane<'a> {
+    fn row(&self, y: usize) -> &'a [u8] {
+        &self.data[y * self.stride..y * self.stride + self.width]
+    }
+}
+
+/// How much two pictures differ: the PSNR and SSIM of their luma.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameQuality {
+    /// In dB, infinite for identical pictures.
+    pub psnr: f64,
+    /// From 0 to 1, 1 for identical pictures.
+    pub ssim: f64,
+}
+
+impl FrameQuality {
+    /// Compare the luma of `distorted` to that of `reference`, which must have the same size.
+    pub fn of_images(reference: &ColorImage, distorted: &ColorImage) -> Self {
+        let [width, height] = reference.size;
+        let reference = luma(reference);
+        let distorted = luma(distorted);
+        let reference = Plane {
+            data: &reference,
+            width,
+            height,
+            stride: width,
+        };
+        let distorted = Plane {
+            data: &distorted,
+            width,
+            height,
+            stride: width,
+        };
+        Self {
+            psnr: psnr(reference, distorted),
+            ssim: ssim(reference, distorted),
+        }
+    }
+}
+
+/// The mean squared error between two planes of the same size.
+pub fn mean_squared_error(reference: Plane, distorted: Plane) -> f64 {
+    let mut sum = 0u64;
+    for y in 0..reference.height {
+        for (a, b) in reference.row(y).iter().zip(distorted.row(y)) {
+            let difference = *a as i32 - *b as i32;
+            sum += (difference * difference) as u64;
+        }
+    }
+    sum as f64 / (reference.width * reference.height).max(1) as f64
+}
+
+/// The peak signal to noise ratio of a mean squared error of 8 bit values, in dB.
+pub fn psnr_of_mse(mse: f64) -> f64 {
+    if mse == 0. {
+        f64::INFINITY
+    } else {
+        10. * (255. * 255. / mse).log10()
+    }
+}
+
+/// The peak signal to noise ratio between two planes of the same size, in dB.
+pub fn psnr(reference: Plane, distorted: Plane) -> f64 {
+    psnr_of_mse(mean_squared_error(reference, distorted))
+}
+
+/// The structural similarity between two planes of the same size, averaged over overlapping 8×8 windows.
+pub fn ssim(reference: Plane, distorted: Plane) -> f64 {
+    const C1: f64 = (0.01 * 255.) * (0.01 * 255.);
+    const C2: f64 = (0.03 * 255.) * (0.03 * 255.);
+    if reference.width < SSIM_WINDOW || reference.height < SSIM_WINDOW {
+        return if mean_squared_error(reference, distorted) == 0. { 1. } else { 0. };
+    }
+    let count = (SSIM_WINDOW * SSIM_WINDOW) as f64;
+    let mut total = 0.;
+    let mut windows = 0;
+    for top in (0..=reference.height - SSIM_WINDOW).step_by(SSIM_STEP) {
+        for left in (0..=reference.width - SSIM_WINDOW).step_by(SSIM_STEP) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0u64, 0u64, 0u64, 0u64, 0u64);
+            for y in top..top + SSIM_WINDOW {
+                let a = &reference.row(y)[left..left + SSIM_WINDOW];
+                let b = &distorted.row(y)[left..left + SSIM_WINDOW];
+                for (a, b) in a.iter().zip(b) {
+                    let (a, b) = (*a as u64, *b as u64);
+                    sum_a += a;
+                    sum_b += b;
+                    sum_aa += a * a;
+                    sum_bb += b * b;
+                    sum_ab += a * b;
+                }
+            }
+            let mean_a = sum_a as f64 / count;
+            let mean_b = sum_b as f64 / count;
+            let variance_a = sum_aa as f64 / count - mean_a * mean_a;
+            let variance_b = sum_bb as f64 / count - mean_b * mean_b;
+            let covariance = sum_ab as f64 / count - mean_a * mean_b;
+            total += ((2. * mean_a * mean_b + C1) * (2. * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+/// The BT.709 luma of every pixel of `image`.
+fn luma(image: &ColorImage) -> Vec<u8> {
+    image
+        .pixels
+        .iter()
+        .map(|pixel| {
+            let [r, g, b, _] = pixel.to_array();
+            (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32).round() as u8
+        })
+        .collect()
+}
+
+/// The quality of one frame of a distorted video against the reference frame shown at the same time.
+#[derive(Clone, Debug, Serialize)]
+pub struct FrameMetrics {
+    /// The index of the reference frame.
+    pub frame: usize,
+    /// When the frame is shown, relative to the first frame of the reference, in milliseconds.
+    pub pts_ms: i64,
+    /// The PSNR of each plane, in dB (infinite, or null in JSON, for identical planes).
+    pub psnr_y: f64,
+    pub psnr_u: f64,
+    pub psnr_v: f64,
+    /// The PSNR of all planes together, weighted by their sizes.
+    pub psnr: f64,
+    /// The SSIM of the luma.
+    pub ssim: f64,
+    #[serde(skip)]
+    mse: [f64; 4],
+}
+
+/// The quality over all compared frames.
+#[derive(Clone, Debug, Serialize)]
+pub struct AggregateMetrics {
+    /// The PSNR of the mean squared error over all frames, of each plane and of all planes together, in dB.
+    pub psnr_y: f64,
+    pub psnr_u: f64,
+    pub psnr_v: f64,
+    pub psnr: f64,
+    pub min_psnr: f64,
+    pub ssim: f64,
+    pub min_ssim: f64,
+}
+
+/// The result of [`compare_files`].
+#[derive(Clone, Debug, Serialize)]
+pub struct QualityReport {
+    pub reference: String,
+    pub distorted: String,
+    /// The size everything was compared at, the reference's.
+    pub width: u32,
+    pub height: u32,
+    /// Reference frames without a distorted frame at the same time.
+    pub unmatched_reference_frames: usize,
+    /// Distorted frames without a reference frame at the same time.
+    pub unmatched_distorted_frames: usize,
+    /// `None` if no frames could be compared.
+    pub aggregate: Option<AggregateMetrics>,
+    pub frames: Vec<FrameMetrics>,
+}
+
+impl QualityReport {
+    /// The per-frame metrics as CSV, with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("frame,pts_ms,psnr_y,psnr_u,psnr_v,psnr,ssim\n");
+        for frame in &self.frames {
+            csv.push_str(&format!(
+                "{},{},{:.4},{:.4},{:.4},{:.4},{:.6}\n",
+                frame.frame, frame.pts_ms, frame.psnr_y, frame.psnr_u, frame.psnr_v, frame.psnr, frame.ssim
+            ));
+        }
+        csv
+    }
+
+    /// The whole report as JSON.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// A one line summary of the aggregate metrics.
+    pub fn summary(&self) -> String {
+        match self.aggregate.as_ref() {
+            Some(a) => format!(
+                "{} frames  PSNR y:{:.2} u:{:.2} v:{:.2} average:{:.2} min:{:.2}  SSIM {:.6} min:{:.6}",
+                self.frames.len(),
+                a.psnr_y,
+                a.psnr_u,
+                a.psnr_v,
+                a.psnr,
+                a.min_psnr,
+                a.ssim,
+                a.min_ssim
+            ),
+            None => String::from("no frames compared"),
+        }
+    }
+}
+
+/// The frames of the video stream of a file, decoded like the player does and converted to 8 bit 4:2:0.
+struct FrameSource {
+    streamer: VideoStreamer,
+    time_base: ffmpeg::Rational,
+    first_ms: Option<i64>,
+}
+
+impl FrameSource {
+    fn open(path: &str) -> Result<Self> {
+        let streamer = VideoStreamer::open(path)?;
+        let time_base = streamer.time_base();
+        Ok(Self {
+            streamer,
+            time_base,
+            first_ms: None,
+        })
+    }
+
+    /// The next frame and when it is shown relative to the first, `None` at the end of the stream.
+    /// Frames without a timestamp can't be aligned and are skipped.
+    fn next(&mut self) -> Result<Option<(i64, Video)>> {
+        loop {
+            let frame = match self.streamer.recieve_next_decoded_frame() {
+                Ok(frame) => frame,
+                Err(e) if is_ffmpeg_eof_error(&e) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let Some(timestamp) = frame.timestamp() else {
+                continue;
+            };
+            let ms = timestamp_to_millisec(timestamp, self.time_base);
+            let first_ms = *self.first_ms.get_or_insert(ms);
+            return Ok(Some((ms - first_ms, frame)));
+        }
+    }
+}
+
+/// Decode `reference_path` and `distorted_path` in lock-step, pair their frames by presentation time and
+/// compute the PSNR of each plane and the SSIM of the luma of each pair. Distorted frames are scaled to the
+/// reference's size, and both are compared as 8 bit 4:2:0.
+pub fn compare_files(reference_path: &str, distorted_path: &str) -> Result<QualityReport> {
+    let mut reference = FrameSource::open(reference_path)?;
+    let mut distorted = FrameSource::open(distorted_path)?;
+    let width = reference.streamer.video_decoder().width();
+    let height = reference.streamer.video_decoder().height();
+
+    let mut frames = Vec::new();
+    let (unmatched_reference_frames, unmatched_distorted_frames) = align_frames(
+        || reference.next(),
+        || distorted.next(),
+        |reference_index, reference_ms, reference_frame, distorted_frame| {
+            let reference_planes = scale_frame(reference_frame, Pixel::YUV420P, width, height)?;
+            let distorted_planes = scale_frame(distorted_frame, Pixel::YUV420P, width, height)?;
+            frames.push(frame_metrics(reference_index, reference_ms, &reference_planes, &distorted_planes));
+            Ok(())
+        },
+    )?;
+
+    Ok(QualityReport {
+        reference: reference_path.to_string(),
+        distorted: distorted_path.to_string(),
+        width,
+        height,
+        unmatched_reference_frames,
+        unmatched_distorted_frames,
+        aggregate: aggregate(&frames),
+        frames,
+    })
+}
+
+/// Pair the frames of two streams by timestamp, passing each pair to `on_match` with the index and time of
+/// the reference frame. Frames within half a reference frame interval of each other count as the same time.
+/// Returns how many reference and distorted frames had no counterpart.
+fn align_frames<R, D>(
+    mut next_reference: impl FnMut() -> Result<Option<(i64, R)>>,
+    mut next_distorted: impl FnMut() -> Result<Option<(i64, D)>>,
+    mut on_match: impl FnMut(usize, i64, &R, &D) -> Result<()>,
+) -> Result<(usize, usize)> {
+    let mut unmatched_reference_frames = 0;
+    let mut unmatched_distorted_frames = 0;
+    let mut tolerance_ms = DEFAULT_ALIGN_TOLERANCE_MS;
+    let mut previous_reference_ms = None;
+    let mut reference_index = 0;
+    let mut reference = next_reference()?;
+    let mut distorted = next_distorted()?;
+    while let Some((reference_ms, reference_frame)) = reference.as_ref() {
+        let reference_ms = *reference_ms;
+        if let Some(previous_ms) = previous_reference_ms {
+            if reference_ms > previous_ms {
+                tolerance_ms = ((reference_ms - previous_ms) / 2).max(1);
+            }
+        }
+        match distorted.as_ref() {
+            Some((distorted_ms, _)) if *distorted_ms < reference_ms - tolerance_ms => {
+                unmatched_distorted_frames += 1;
+                distorted = next_distorted()?;
+                continue;
+            }
+            Some((distorted_ms, distorted_frame)) if *distorted_ms <= reference_ms + tolerance_ms => {
+                on_match(reference_index, reference_ms, reference_frame, distorted_frame)?;
+                distorted = next_distorted()?;
+            }
+            _ => unmatched_reference_frames += 1,
+        }
+        previous_reference_ms = Some(reference_ms);
+        reference_index += 1;
+        reference = next_reference()?;
+    }
+    while distorted.is_some() {
+        unmatched_distorted_frames += 1;
+        distorted = next_distorted()?;
+    }
+    Ok((unmatched_reference_frames, unmatched_distorted_frames))
+}
+
+/// One plane of a decoded 8 bit frame.
+fn plane(frame: &Video, index: usize) -> Plane<'_> {
+    Plane {
+        data: frame.data(index),
+        width: frame.plane_width(index) as usize,
+        height: frame.plane_height(index) as usize,
+        stride: frame.stride(index),
+    }
+}
+
+/// The weights of the planes of a 4:2:0 frame, by their number of samples.
+fn plane_weights(width: u32, height: u32) -> [f64; 3] {
+    let luma = width as f64 * height as f64;
+    let chroma = width.div_ceil(2) as f64 * height.div_ceil(2) as f64;
+    let total = luma + 2. * chroma;
+    [luma / total, chroma / total, chroma / total]
+}
+
+fn frame_metrics(frame: usize, pts_ms: i64, reference: &Video, distorted: &Video) -> FrameMetrics {
+    let mut mse = [0.; 4];
+    for (index, mse) in mse.iter_mut().take(3).enumerate() {
+        *mse = mean_squared_error(plane(reference, index), plane(distorted, index));
+    }
+    let weights = plane_weights(reference.width(), reference.height());
+    mse[3] = (0..3).map(|index| mse[index] * weights[index]).sum();
+    FrameMetrics {
+        frame,
+        pts_ms,
+        psnr_y: psnr_of_mse(mse[0]),
+        psnr_u: psnr_of_mse(mse[1]),
+        psnr_v: psnr_of_mse(mse[2]),
+        psnr: psnr_of_mse(mse[3]),
+        ssim: ssim(plane(reference, 0), plane(distorted, 0)),
+        mse,
+    }
+}
+
+fn aggregate(frames: &[FrameMetrics]) -> Option<AggregateMetrics> {
+    if frames.is_empty() {
+        return None;
+    }
+    let count = frames.len() as f64;
+    let mean_mse = |index: usize| frames.iter().map(|f| f.mse[index]).sum::<f64>() / count;
+    Some(AggregateMetrics {
+        psnr_y: psnr_of_mse(mean_mse(0)),
+        psnr_u: psnr_of_mse(mean_mse(1)),
+        psnr_v: psnr_of_mse(mean_mse(2)),
+        psnr: psnr_of_mse(mean_mse(3)),
+        min_psnr: frames.iter().map(|f| f.psnr).fold(f64::INFINITY, f64::min),
+        ssim: frames.iter().map(|f| f.ssim).sum::<f64>() / count,
+        min_ssim: frames.iter().map(|f| f.ssim).fold(f64::INFINITY, f64::min),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `width`x`height` plane with padding at the end of every row, which must be ignored.
+    fn plane_data(width: usize, height: usize, value: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        let stride = width + 3;
+        (0..height)
+            .flat_map(|y| (0..stride).map(move |x| (x, y)))
+            .map(|(x, y)| if x < width { value(x, y) } else { 255 })
+            .collect()
+    }
+
+    fn as_plane(data: &[u8], width: usize, height: usize) -> Plane<'_> {
+        Plane {
+            data,
+            width,
+            height,
+            stride: width + 3,
+        }
+    }
+
+    fn texture(x: usize, y: usize) -> u8 {
+        (x * 13 + y * 29 + (x * y) % 7 * 11) as u8 / 2 + 40
+    }
+
+    #[test]
+    fn psnr_of_mse_extremes() {
+        assert_eq!(psnr_of_mse(0.), f64::INFINITY);
+        assert_eq!(psnr_of_mse(255. * 255.), 0.);
+        assert!((psnr_of_mse(1.) - 48.1308).abs() < 1e-4);
+    }
+
+    #[test]
+    fn identical_planes() {
+        let data = plane_data(32, 24, texture);
+        let plane = as_plane(&data, 32, 24);
+        assert_eq!(mean_squared_error(plane, plane), 0.);
+        assert_eq!(psnr(plane, plane), f64::INFINITY);
+        assert!((ssim(plane, plane) - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn known_offset() {
+        let reference = plane_data(32, 24, texture);
+        let distorted = plane_data(32, 24, |x, y| texture(x, y) + 4);
+        let (reference, distorted) = (as_plane(&reference, 32, 24), as_plane(&distorted, 32, 24));
+        assert_eq!(mean_squared_error(reference, distorted), 16.);
+        let expected_psnr = 10. * (255f64 * 255. / 16.).log10();
+        assert!((psnr(reference, distorted) - expected_psnr).abs() < 1e-9);
+        // a brightness shift keeps the structure, so SSIM barely drops
+        let ssim = ssim(reference, distorted);
+        assert!(ssim < 1. && ssim > 0.99, "{ssim}");
+    }
+
+    #[test]
+    fn ssim_of_unrelated_structure_is_low() {
+        let reference = plane_data(32, 32, |x, y| if (x / 2 + y / 2) % 2 == 0 { 30 } else { 220 });
+        let distorted = plane_data(32, 32, |x, y| if (x / 2 + y / 2) % 2 == 0 { 220 } else { 30 });
+        let ssim = ssim(as_plane(&reference, 32, 32), as_plane(&distorted, 32, 32));
+        assert!(ssim < 0., "{ssim}");
+    }
+
+    #[test]
+    fn ssim_of_planes_smaller_than_a_window() {
+        let reference = plane_data(4, 4, texture);
+        let distorted = plane_data(4, 4, |x, y| texture(x, y) + 1);
+        let reference = as_plane(&reference, 4, 4);
+        assert_eq!(ssim(reference, reference), 1.);
+        assert_eq!(ssim(reference, as_plane(&distorted, 4, 4)), 0.);
+    }
+
+    #[test]
+    fn plane_weights_follow_sample_counts() {
+        assert_eq!(plane_weights(4, 4), [16. / 24., 4. / 24., 4. / 24.]);
+        // odd sizes round the chroma planes up
+        assert_eq!(plane_weights(3, 3), [9. / 17., 4. / 17., 4. / 17.]);
+    }
+
+    /// Align two lists of timestamps: the matched pairs and the unmatched counts.
+    fn align(reference: &[i64], distorted: &[i64]) -> (Vec<(i64, i64)>, usize, usize) {
+        let mut reference = reference.iter().map(|ms| (*ms, *ms));
+        let mut distorted = distorted.iter().map(|ms| (*ms, *ms));
+        let mut pairs = Vec::new();
+        let (unmatched_reference, unmatched_distorted) = align_frames(
+            || Ok(reference.next()),
+            || Ok(distorted.next()),
+            |_, _, reference_ms, distorted_ms| {
+                pairs.push((*reference_ms, *distorted_ms));
+                Ok(())
+            },
+        )
+        .unwrap();
+        (pairs, unmatched_reference, unmatched_distorted)
+    }
+
+    #[test]
+    fn aligns_identical_timestamps() {
+        let timestamps = [0, 40, 80, 120];
+        let (pairs, unmatched_reference, unmatched_distorted) = align(&timestamps, &timestamps);
+        assert_eq!(pairs, timestamps.map(|ms| (ms, ms)));
+        assert_eq!((unmatched_reference, unmatched_distorted), (0, 0));
+    }
+
+    #[test]
+    fn aligns_within_half_a_frame() {
+        let (pairs, unmatched_reference, unmatched_distorted) = align(&[0, 40, 80, 120], &[10, 50, 90, 130]);
+        assert_eq!(pairs, vec![(0, 10), (40, 50), (80, 90), (120, 130)]);
+        assert_eq!((unmatched_reference, unmatched_distorted), (0, 0));
+    }
+
+    #[test]
+    fn counts_dropped_and_extra_frames() {
+        // the distorted file dropped the frame at 40 and has a second more at either end
+        let (pairs, unmatched_reference, unmatched_distorted) =
+            align(&[0, 40, 80, 120], &[-1000, 0, 80, 120, 1120]);
+        assert_eq!(pairs, vec![(0, 0), (80, 80), (120, 120)]);
+        assert_eq!((unmatched_reference, unmatched_distorted), (1, 2));
+    }
+}
@@ -0,0 +1,331 @@
+use egui::{Color32, ColorImage, Pos2, Rect, Stroke, TextureHandle, TextureOptions, Ui, Vec2};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The shortest time between two scope updates, to keep them from competing with playback.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+/// Scopes sample at most about this many pixels of a frame.
+const MAX_SAMPLES: usize = 512 * 288;
+
+/// A measurement of the displayed frame.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ScopeKind {
+    /// How many pixels have each luma and R, G and B value.
+    Histogram,
+    /// The luma of every column of the frame, from black at the bottom to white at the top.
+    Waveform,
+    /// Waveforms of R, G and B side by side.
+    Parade,
+    /// The hue and saturation of every pixel, as the angle and distance from the center.
+    Vectorscope,
+}
+
+impl ScopeKind {
+    pub const ALL: [ScopeKind; 4] = [
+        ScopeKind::Histogram,
+        ScopeKind::Waveform,
+        ScopeKind::Parade,
+        ScopeKind::Vectorscope,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScopeKind::Histogram => "histogram",
+            ScopeKind::Waveform => "waveform",
+            ScopeKind::Parade => "RGB parade",
+            ScopeKind::Vectorscope => "vectorscope",
+        }
+    }
+
+    /// Draw the scope of `frame`.
+    pub fn compute(&self, frame: &ColorImage) -> ColorImage {
+        match self {
+            ScopeKind::Histogram => histogram(frame),
+            ScopeKind::Waveform => waveform(frame, &[(Channel::Luma, Color32::from_rgb(120, 255, 120))]),
+            ScopeKind::Parade => waveform(
+                frame,
+                &[
+                    (Channel::Red, Color32::from_rgb(255, 80, 80)),
+                    (Channel::Green, Color32::from_rgb(80, 255, 80)),
+                    (Channel::Blue, Color32::from_rgb(100, 140, 255)),
+                ],
+            ),
+            ScopeKind::Vectorscope => vectorscope(frame),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Channel {
+    Luma,
+    Red,
+    Green,
+    Blue,
+}
+
+impl Channel {
+    fn of(&self, pixel: Color32) -> u8 {
+        match self {
+            Channel::Luma => luma(pixel),
+            Channel::Red => pixel.r(),
+            Channel::Green => pixel.g(),
+            Channel::Blue => pixel.b(),
+        }
+    }
+}
+
+/// BT.709 luma of a gamma encoded pixel.
+fn luma(pixel: Color32) -> u8 {
+    (0.2126 * pixel.r() as f32 + 0.7152 * pixel.g() as f32 + 0.0722 * pixel.b() as f32).round() as u8
+}
+
+/// BT.709 chroma of a gamma encoded pixel, each in -0.5..=0.5.
+fn chroma(pixel: Color32) -> (f32, f32) {
+    let [r, g, b] = [pixel.r(), pixel.g(), pixel.b()].map(|c| c as f32 / 255.);
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    ((b - y) / 1.8556, (r - y) / 1.5748)
+}
+
+/// Every how many pixels, in both directions, a frame is sampled.
+fn sample_step(frame: &ColorImage) -> usize {
+    let pixels = frame.size[0] * frame.size[1];
+    ((pixels as f32 / MAX_SAMPLES as f32).sqrt().ceil() as usize).max(1)
+}
+
+/// The sampled pixels of `frame`, with their column.
+fn samples(frame: &ColorImage) -> impl Iterator<Item = (usize, Color32)> + '_ {
+    let step = sample_step(frame);
+    let [width, height] = frame.size;
+    (0..height)
+        .step_by(step)
+        .flat_map(move |y| (0..width).step_by(step).map(move |x| (x, frame.pixels[y * width + x])))
+}
+
+fn blank(width: usize, height: usize) -> ColorImage {
+    ColorImage {
+        size: [width, height],
+        source_size: Vec2::new(width as f32, height as f32),
+        pixels: vec![Color32::from_black_alpha(230); width * height],
+    }
+}
+
+/// Scale a count so that sparse traces stay visible next to dense ones.
+fn intensity(count: u32, max: u32) -> f32 {
+    if max == 0 {
+        return 0.;
+    }
+    ((count as f32).sqrt() / (max as f32).sqrt()).min(1.)
+}
+
+fn histogram(frame: &ColorImage) -> ColorImage {
+    const HEIGHT: usize = 128;
+    let channels = [Channel::Red, Channel::Green, Channel::Blue, Channel::Luma];
+    let mut bins = [[0u32; 256]; 4];
+    for (_, pixel) in samples(frame) {
+        for (channel_bins, channel) in bins.iter_mut().zip(channels) {
+            channel_bins[channel.of(pixel) as usize] += 1;
+        }
+    }
+    // clipped blacks and whites would flatten everything else
+    let max = bins.iter().flat_map(|b| b[1..255].iter()).copied().max().unwrap_or(0).max(1);
+    let mut image = blank(256, HEIGHT);
+    for x in 0..256 {
+        let height_of = |count: u32| ((count as f32 / max as f32).min(1.) * HEIGHT as f32) as usize;
+        let [red, green, blue, luma] = bins.map(|b| height_of(b[x]));
+        for y in 0..HEIGHT {
+            let level = HEIGHT - y;
+            let on = |h: usize| if h >= level { 200 } else { 0 };
+            let gray = if luma >= level { 70 } else { 0 };
+            image.pixels[y * 256 + x] = Color32::from_rgb(
+                (on(red) + gray).min(255) as u8,
+                (on(green) + gray).min(255) as u8,
+                (on(blue) + gray).min(255) as u8,
+            );
+        }
+    }
+    image
+}
+
+/// One waveform per channel, side by side.
+fn waveform(frame: &ColorImage, channels: &[(Channel, Color32)]) -> ColorImage {
+    const HEIGHT: usize = 256;
+    let columns = (frame.size[0] / sample_step(frame)).clamp(1, 512 / channels.len());
+    let width = columns * channels.len();
+    let mut counts = vec![0u32; width * HEIGHT];
+    for (x, pixel) in samples(frame) {
+        let column = x * columns / frame.size[0].max(1);
+        for (index, (channel, _)) in channels.iter().enumerate() {
+            let row = 255 - channel.of(pixel) as usize;
+            counts[row * width + index * columns + column] += 1;
+        }
+    }
+    let max = counts.iter().copied().max().unwrap_or(0);
+    let mut image = blank(width, HEIGHT);
+    for (index, count) in counts.iter().enumerate() {
+        let (_, color) = channels[(index % width) / columns];
+        let i = intensity(*count, max);
+        image.pixels[index] = Color32::from_rgb(
+            (color.r() as f32 * i) as u8,
+            (color.g() as f32 * i) as u8,
+            (color.b() as f32 * i) as u8,
+        );
+    }
+    image
+}
+
+fn vectorscope(frame: &ColorImage) -> ColorImage {
+    const SIZE: usize = 256;
+    let mut counts = vec![0u32; SIZE * SIZE];
+    let mut colors = vec![[0u32; 3]; SIZE * SIZE];
+    for (_, pixel) in samples(frame) {
+        let (cb, cr) = chroma(pixel);
+        let x = ((cb + 0.5) * (SIZE - 1) as f32).round() as usize;
+        let y = ((0.5 - cr) * (SIZE - 1) as f32).round() as usize;
+        let index = y.min(SIZE - 1) * SIZE + x.min(SIZE - 1);
+        counts[index] += 1;
+        for (sum, c) in colors[index].iter_mut().zip([pixel.r(), pixel.g(), pixel.b()]) {
+            *sum += c as u32;
+        }
+    }
+    let max = counts.iter().copied().max().unwrap_or(0);
+    let mut image = blank(SIZE, SIZE);
+    for (index, count) in counts.iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        // traces take the average color of the pixels that land on them, brightened to stay visible
+        let i = 0.35 + 0.65 * intensity(*count, max);
+        let [r, g, b] = colors[index].map(|sum| sum as f32 / *count as f32);
+        let brightest = r.max(g).max(b).max(1.);
+        image.pixels[index] = Color32::from_rgb(
+            (r / brightest * 255. * i) as u8,
+            (g / brightest * 255. * i) as u8,
+            (b / brightest * 255. * i) as u8,
+        );
+    }
+    image
+}
+
+/// Computes a scope of the displayed frame on a worker thread and shows it.
+pub struct Scopes {
+    pub kind: ScopeKind,
+    texture: Option<TextureHandle>,
+    request_sender: Sender<(ScopeKind, Arc<ColorImage>)>,
+    result_reciever: Receiver<(ScopeKind, ColorImage)>,
+    pending: bool,
+    last_request: Option<Instant>,
+    // what the shown scope was computed from
+    computed_for: Option<(ScopeKind, Arc<ColorImage>)>,
+}
+
+impl Default for Scopes {
+    fn default() -> Self {
+        let (request_sender, request_reciever) = std::sync::mpsc::channel::<(ScopeKind, Arc<ColorImage>)>();
+        let (result_sender, result_reciever) = std::sync::mpsc::channel();
+        // exits once the scopes, and with them the sender, are dropped
+        std::thread::spawn(move || {
+            while let Ok(mut request) = request_reciever.recv() {
+                // only the newest frame matters
+                while let Ok(newer) = request_reciever.try_recv() {
+                    request = newer;
+                }
+                let (kind, frame) = request;
+                if result_sender.send((kind, kind.compute(&frame))).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            kind: ScopeKind::Histogram,
+            texture: None,
+            request_sender,
+            result_reciever,
+            pending: false,
+            last_request: None,
+            computed_for: None,
+        }
+    }
+}
+
+impl Scopes {
+    /// Pick up a finished scope, and start on `frame` if it changed and the last update was long enough ago.
+    pub fn update(&mut self, ctx: &egui::Context, frame: Option<Arc<ColorImage>>) {
+        if let Ok((kind, image)) = self.result_reciever.try_recv() {
+            self.pending = false;
+            if kind == self.kind {
+                match self.texture.as_mut() {
+                    Some(texture) => texture.set(image, TextureOptions::LINEAR),
+                    None => self.texture = Some(ctx.load_texture("scope", image, TextureOptions::LINEAR)),
+                }
+            }
+        }
+        let Some(frame) = frame else {
+            return;
+        };
+        let is_current = self
+            .computed_for
+            .as_ref()
+            .is_some_and(|(kind, computed)| *kind == self.kind && Arc::ptr_eq(computed, &frame));
+        let throttled = self.last_request.is_some_and(|last| last.elapsed() < UPDATE_INTERVAL);
+        if self.pending || is_current || throttled {
+            return;
+        }
+        if self.request_sender.send((self.kind, frame.clone())).is_ok() {
+            self.pending = true;
+            self.last_request = Some(Instant::now());
+            self.computed_for = Some((self.kind, frame));
+        }
+    }
+
+    /// Draw the latest scope, `width` wide.
+    pub fn show(&self, ui: &mut Ui, width: f32) {
+        let Some(texture) = self.texture.as_ref() else {
+            ui.label("no frame yet");
+            return;
+        };
+        let size = texture.size_vec2();
+        let (response, painter) = ui.allocate_painter(Vec2::new(width, width * size.y / size.x), egui::Sense::hover());
+        let rect = response.rect;
+        painter.image(
+            texture.id(),
+            rect,
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.)),
+            Color32::WHITE,
+        );
+        let graticule = Stroke::new(1., Color32::from_white_alpha(40));
+        match self.kind {
+            ScopeKind::Vectorscope => {
+                painter.circle_stroke(rect.center(), rect.width() / 2., graticule);
+                painter.line_segment([rect.center_top(), rect.center_bottom()], graticule);
+                painter.line_segment([rect.left_center(), rect.right_center()], graticule);
+                // where 75% color bars land
+                for target in [
+                    Color32::from_rgb(191, 0, 0),
+                    Color32::from_rgb(191, 191, 0),
+                    Color32::from_rgb(0, 191, 0),
+                    Color32::from_rgb(0, 191, 191),
+                    Color32::from_rgb(0, 0, 191),
+                    Color32::from_rgb(191, 0, 191),
+                ] {
+                    let (cb, cr) = chroma(target);
+                    let center = rect.center() + Vec2::new(cb, -cr) * rect.width();
+                    painter.rect_stroke(
+                        Rect::from_center_size(center, Vec2::splat(8.)),
+                        0.,
+                        Stroke::new(1., target),
+                        egui::StrokeKind::Middle,
+                    );
+                }
+            }
+            ScopeKind::Waveform | ScopeKind::Parade => {
+                // 0, 25, 50, 75 and 100%
+                for step in 0..=4 {
+                    let y = rect.bottom() - rect.height() * step as f32 / 4.;
+                    painter.line_segment([Pos2::new(rect.left(), y), Pos2::new(rect.right(), y)], graticule);
+                }
+            }
+            ScopeKind::Histogram => (),
+        }
+    }
+}
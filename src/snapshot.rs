@@ -0,0 +1,128 @@
+use egui::{Color32, ColorImage, FontId, Pos2};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use std::path::Path;
+use anyhow::Result;
+
+/// Configures how a snapshot of the current frame is produced.
+pub struct SnapshotOptions {
+    /// Text burned into the bottom of the frame, usually the subtitle that is currently shown.
+    pub burned_in_subtitle: Option<String>,
+    /// The size of the burned-in subtitle, in pixels of the source frame.
+    pub subtitle_font_size: f32,
+    /// The color of the burned-in subtitle.
+    pub subtitle_color: Color32,
+    /// The distance between the bottom of the frame and the subtitle, in pixels of the source frame.
+    pub subtitle_margin: f32,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self {
+            burned_in_subtitle: None,
+            subtitle_font_size: 30.,
+            subtitle_color: Color32::WHITE,
+            subtitle_margin: 40.,
+        }
+    }
+}
+
+/// Convert a decoded frame into an [`RgbaImage`] of the same resolution.
+pub fn color_image_to_rgba(image: &ColorImage) -> RgbaImage {
+    let [width, height] = image.size;
+    let mut rgba = RgbaImage::new(width as u32, height as u32);
+    for (pixel, color) in rgba.pixels_mut().zip(image.pixels.iter()) {
+        *pixel = Rgba(color.to_srgba_unmultiplied());
+    }
+    rgba
+}
+
+/// Draw `text` centered at the bottom of `image`, using the glyphs egui has already rasterized
+/// into its font atlas.
+pub fn burn_in_text(ctx: &egui::Context, image: &mut RgbaImage, text: &str, options: &SnapshotOptions) {
+    if text.is_empty() {
+        return;
+    }
+    let pixels_per_point = ctx.pixels_per_point();
+    // egui lays text out in points, so scale the font to end up with the requested pixel size
+    let font_id = FontId::proportional(options.subtitle_font_size / pixels_per_point);
+    let galley = ctx.fonts_mut(|f| f.layout_no_wrap(text.to_string(), font_id, options.subtitle_color));
+    let atlas = ctx.fonts(|f| f.image());
+
+    let galley_size = galley.size() * pixels_per_point;
+    let origin = Pos2::new(
+        (image.width() as f32 - galley_size.x) / 2.,
+        image.height() as f32 - options.subtitle_margin - galley_size.y,
+    );
+    let [r, g, b, _] = options.subtitle_color.to_srgba_unmultiplied();
+    let outline = Rgba([0, 0, 0, 255]);
+    let fill = Rgba([r, g, b, 255]);
+
+    for row in galley.rows.iter() {
+        for glyph in row.glyphs.iter() {
+            let uv_rect = glyph.uv_rect;
+            if uv_rect.is_nothing() {
+                continue;
+            }
+            let glyph_pos = (row.pos + glyph.pos.to_vec2() + uv_rect.offset).to_vec2() * pixels_per_point;
+            let left_top = origin + glyph_pos;
+            for ty in uv_rect.min[1]..uv_rect.max[1] {
+                for tx in uv_rect.min[0]..uv_rect.max[0] {
+                    let coverage = atlas[(tx as usize, ty as usize)].a();
+                    if coverage == 0 {
+                        continue;
+                    }
+                    let x = (left_top.x + (tx - uv_rect.min[0]) as f32).round() as i64;
+                    let y = (left_top.y + (ty - uv_rect.min[1]) as f32).round() as i64;
+                    // a one pixel outline keeps the text readable on bright frames
+                    for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                        blend_pixel(image, x + dx, y + dy, outline, coverage);
+                    }
+                    blend_pixel(image, x, y, fill, coverage);
+                }
+            }
+        }
+    }
+}
+
+fn blend_pixel(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>, coverage: u8) {
+    if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+        return;
+    }
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    let alpha = coverage as u32;
+    for c in 0..3 {
+        pixel.0[c] = ((color.0[c] as u32 * alpha + pixel.0[c] as u32 * (255 - alpha)) / 255) as u8;
+    }
+}
+
+/// Write `image` to `path`, choosing the format from the file extension.
+pub fn save_rgba_image(image: &RgbaImage, path: &Path) -> Result<()> {
+    let format = ImageFormat::from_path(path)?;
+    match format {
+        // jpeg has no alpha channel
+        ImageFormat::Jpeg => DynamicImage::ImageRgba8(image.clone())
+            .to_rgb8()
+            .save_with_format(path, format)?,
+        ImageFormat::Png | ImageFormat::WebP => image.save_with_format(path, format)?,
+        _ => anyhow::bail!("unsupported snapshot format: {:?}", format),
+    }
+    Ok(())
+}
+
+/// A filename for a snapshot of `input_path` taken at `elapsed_ms`, e.g. `movie_00-01-23.456.png`.
+pub fn default_snapshot_filename(input_path: &str, elapsed_ms: i64, extension: &str) -> String {
+    let stem = Path::new(input_path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| String::from("snapshot"));
+    let elapsed_ms = elapsed_ms.max(0);
+    format!(
+        "{}_{:02}-{:02}-{:02}.{:03}.{}",
+        stem,
+        elapsed_ms / 3_600_000,
+        (elapsed_ms / 60_000) % 60,
+        (elapsed_ms / 1000) % 60,
+        elapsed_ms % 1000,
+        extension
+    )
+}
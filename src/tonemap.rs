@@ -0,0 +1,349 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::color::{Primaries, Range, Space, TransferCharacteristic};
+use ffmpeg::format::Pixel;
+use ffmpeg::frame::{side_data, Video};
+use ffmpeg::software::scaling::{context::Context as ScaleContext, flag::Flags};
+use egui::{Color32, ColorImage, Vec2};
+use bytemuck::NoUninit;
+use anyhow::Result;
+
+/// The luminance SDR white is shown at, in nits (BT.2408).
+const REFERENCE_WHITE_NITS: f32 = 203.;
+/// The peak assumed for HDR10 video without metadata, and the nominal peak of HLG, in nits.
+const DEFAULT_PEAK_NITS: f32 = 1000.;
+
+/// How HDR video is compressed into the range of an SDR display.
+#[derive(PartialEq, Clone, Copy, Debug, NoUninit)]
+#[repr(u8)]
+pub enum ToneMapOperator {
+    /// Convert HDR video like SDR video, which looks dim and washed out.
+    Off,
+    /// Rolls highlights off smoothly, keeping midtones close to the original.
+    Reinhard,
+    /// The filmic curve of Uncharted 2, with more contrast in the shadows.
+    Hable,
+    /// The EETF of ITU-R BT.2390, which leaves everything up to SDR white untouched.
+    Bt2390,
+}
+
+impl ToneMapOperator {
+    pub const ALL: [ToneMapOperator; 4] = [
+        ToneMapOperator::Off,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::Hable,
+        ToneMapOperator::Bt2390,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapOperator::Off => "off",
+            ToneMapOperator::Reinhard => "Reinhard",
+            ToneMapOperator::Hable => "Hable",
+            ToneMapOperator::Bt2390 => "BT.2390",
+        }
+    }
+
+    /// Map a luminance relative to SDR white onto 0..=1, for content peaking at `peak` (also relative).
+    fn map(&self, x: f32, peak: f32) -> f32 {
+        if peak <= 1. {
+            return x.min(1.);
+        }
+        match self {
+            ToneMapOperator::Off => x.min(1.),
+            ToneMapOperator::Reinhard => x * (1. + x / (peak * peak)) / (1. + x),
+            ToneMapOperator::Hable => {
+                let hable = |x: f32| {
+                    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+                    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+                };
+                hable(x) / hable(peak)
+            }
+            ToneMapOperator::Bt2390 => {
+                let source_peak = pq_encode(peak * REFERENCE_WHITE_NITS);
+                let e1 = pq_encode(x * REFERENCE_WHITE_NITS) / source_peak;
+                let max_luminance = pq_encode(REFERENCE_WHITE_NITS) / source_peak;
+                let knee = 1.5 * max_luminance - 0.5;
+                let e2 = if e1 < knee {
+                    e1
+                } else {
+                    // hermite spline from the knee to the target peak
+                    let t = (e1 - knee) / (1. - knee);
+                    let (t2, t3) = (t * t, t * t * t);
+                    (2. * t3 - 3. * t2 + 1.) * knee + (t3 - 2. * t2 + t) * (1. - knee) + (-2. * t3 + 3. * t2) * max_luminance
+                };
+                pq_decode(e2 * source_peak) / REFERENCE_WHITE_NITS
+            }
+        }
+    }
+}
+
+/// The transfer function of a frame, which says whether it is HDR.
+#[derive(PartialEq, Clone, Copy, Debug, NoUninit)]
+#[repr(u8)]
+pub enum DynamicRange {
+    /// No frame has been decoded yet.
+    Unknown,
+    Sdr,
+    /// SMPTE ST 2084, used by HDR10 and Dolby Vision.
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma.
+    Hlg,
+}
+
+impl DynamicRange {
+    pub fn of(frame: &Video) -> Self {
+        match frame.color_transfer_characteristic() {
+            TransferCharacteristic::SMPTE2084 => DynamicRange::Pq,
+            TransferCharacteristic::ARIB_STD_B67 => DynamicRange::Hlg,
+            _ => DynamicRange::Sdr,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, DynamicRange::Pq | DynamicRange::Hlg)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DynamicRange::Unknown => "-",
+            DynamicRange::Sdr => "SDR",
+            DynamicRange::Pq => "HDR (PQ)",
+            DynamicRange::Hlg => "HDR (HLG)",
+        }
+    }
+}
+
+/// Nits to a PQ signal in 0..=1.
+fn pq_encode(nits: f32) -> f32 {
+    let (m1, m2, c1, c2, c3) = (0.1593017578125, 78.84375, 0.8359375, 18.8515625, 18.6875);
+    let y = (nits / 10000.).clamp(0., 1.).powf(m1);
+    ((c1 + c2 * y) / (1. + c3 * y)).powf(m2)
+}
+
+/// A PQ signal in 0..=1 to nits.
+fn pq_decode(signal: f32) -> f32 {
+    let (m1, m2, c1, c2, c3) = (0.1593017578125, 78.84375, 0.8359375, 18.8515625, 18.6875);
+    let e = signal.clamp(0., 1.).powf(1. / m2);
+    10000. * ((e - c1).max(0.) / (c2 - c3 * e)).powf(1. / m1)
+}
+
+/// An HLG signal in 0..=1 to scene light in 0..=1.
+fn hlg_decode(signal: f32) -> f32 {
+    let (a, b, c) = (0.17883277, 0.28466892, 0.55991073);
+    if signal <= 0.5 {
+        signal * signal / 3.
+    } else {
+        (((signal - c) / a).exp() + b) / 12.
+    }
+}
+
+/// The brightest the video gets according to its HDR10 metadata, in nits: the mastering display's peak,
+/// or else the content light level.
+fn metadata_peak_nits(frame: &Video) -> Option<f32> {
+    let mastering_peak = frame.side_data(side_data::Type::MasteringDisplayMetadata).and_then(|side_data| {
+        let metadata = unsafe { &*(side_data.data().as_ptr() as *const ffmpeg::ffi::AVMasteringDisplayMetadata) };
+        let max = metadata.max_luminance;
+        (metadata.has_luminance != 0 && max.num > 0 && max.den > 0).then(|| max.num as f32 / max.den as f32)
+    });
+    let content_peak = frame.side_data(side_data::Type::ContentLightLevel).and_then(|side_data| {
+        let metadata = unsafe { &*(side_data.data().as_ptr() as *const ffmpeg::ffi::AVContentLightMetadata) };
+        (metadata.MaxCLL > 0).then_some(metadata.MaxCLL as f32)
+    });
+    mastering_peak.or(content_peak)
+}
+
+/// Converts HDR frames to SDR images: decodes the transfer function, maps BT.2020 colors into BT.709 and
+/// compresses the highlights with a [`ToneMapOperator`].
+#[derive(Default)]
+pub(crate) struct ToneMapper {
+    designed_for: Option<DynamicRange>,
+    // 16 bit signal to linear light, per channel: relative to SDR white for PQ, scene light for HLG
+    linearize: Vec<f32>,
+    // linear light in 0..=1 to 8 bit BT.709, in 4096 steps
+    encode: Vec<u8>,
+    // the metadata only comes with some frames, so it is kept until the next
+    peak_nits: Option<f32>,
+    // converts frames to 16 bit RGB, with the format, size, matrix and range it was made for
+    scaler: Option<(ScaleContext, RgbConversion)>,
+}
+
+/// What a frame needs to be converted to RGB: its format, size, YUV matrix and whether it is full range.
+type RgbConversion = (Pixel, u32, u32, i32, bool);
+
+const ENCODE_STEPS: usize = 4096;
+
+/// BT.2020 to BT.709 primaries, in linear light.
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.6605, -0.5876, -0.0728],
+    [-0.1246, 1.1329, -0.0083],
+    [-0.0182, -0.1006, 1.1187],
+];
+
+impl ToneMapper {
+    /// The peak of the video, in nits.
+    fn peak_nits(&self) -> f32 {
+        self.peak_nits.unwrap_or(DEFAULT_PEAK_NITS)
+    }
+
+    fn design(&mut self, dynamic_range: DynamicRange) {
+        if self.designed_for == Some(dynamic_range) {
+            return;
+        }
+        self.linearize = (0..=u16::MAX)
+            .map(|code| {
+                let signal = code as f32 / u16::MAX as f32;
+                match dynamic_range {
+                    DynamicRange::Hlg => hlg_decode(signal),
+                    _ => pq_decode(signal) / REFERENCE_WHITE_NITS,
+                }
+            })
+            .collect();
+        self.encode = (0..ENCODE_STEPS)
+            .map(|step| {
+                let linear = step as f32 / (ENCODE_STEPS - 1) as f32;
+                // the inverse of the BT.1886 display gamma
+                (linear.powf(1. / 2.4) * 255. + 0.5) as u8
+            })
+            .collect();
+        self.designed_for = Some(dynamic_range);
+    }
+
+    /// Convert an HDR `frame` to an 8 bit SDR image.
+    pub(crate) fn convert(
+        &mut self,
+        frame: &Video,
+        dynamic_range: DynamicRange,
+        operator: ToneMapOperator,
+    ) -> Result<ColorImage> {
+        self.design(dynamic_range);
+        if let Some(peak_nits) = metadata_peak_nits(frame) {
+            self.peak_nits = Some(peak_nits);
+        }
+        let peak = match dynamic_range {
+            DynamicRange::Hlg => DEFAULT_PEAK_NITS,
+            _ => self.peak_nits(),
+        } / REFERENCE_WHITE_NITS;
+        let wide_gamut = frame.color_primaries() == Primaries::BT2020;
+        let rgb = self.to_rgb48(frame)?;
+
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        let mut pixels = vec![Color32::BLACK; width * height];
+        let (linearize, encode) = (&self.linearize, &self.encode);
+        let data = rgb.data(0);
+        let stride = rgb.stride(0);
+        let map_rows = |first_row: usize, rows: &mut [Color32]| {
+            for (row_index, row) in rows.chunks_exact_mut(width).enumerate() {
+                let line = &data[(first_row + row_index) * stride..][..width * 6];
+                for (pixel, source) in row.iter_mut().zip(line.chunks_exact(6)) {
+                    let code = |i: usize| u16::from_le_bytes([source[i], source[i + 1]]) as usize;
+                    let mut rgb = [linearize[code(0)], linearize[code(2)], linearize[code(4)]];
+                    if dynamic_range == DynamicRange::Hlg {
+                        // the HLG OOTF, with the system gamma of a 1000 nit display
+                        let scene_luma = 0.2627 * rgb[0] + 0.6780 * rgb[1] + 0.0593 * rgb[2];
+                        let gain = scene_luma.max(1e-6).powf(0.2) * DEFAULT_PEAK_NITS / REFERENCE_WHITE_NITS;
+                        rgb = rgb.map(|c| c * gain);
+                    }
+                    if wide_gamut {
+                        let m = &BT2020_TO_BT709;
+                        rgb = [0, 1, 2].map(|row| m[row][0] * rgb[0] + m[row][1] * rgb[1] + m[row][2] * rgb[2]);
+                    }
+                    let luma = (0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]).max(0.);
+                    let mapped_luma = operator.map(luma, peak);
+                    let scale = if luma > 0. { mapped_luma / luma } else { 0. };
+                    rgb = rgb.map(|c| c * scale);
+                    // colors outside of BT.709 or above white are desaturated towards their luma until they fit
+                    let (min, max) = (rgb[0].min(rgb[1]).min(rgb[2]), rgb[0].max(rgb[1]).max(rgb[2]));
+                    let mut desaturate = 1f32;
+                    if min < 0. {
+                        desaturate = desaturate.min(mapped_luma / (mapped_luma - min));
+                    }
+                    if max > 1. && mapped_luma < 1. {
+                        desaturate = desaturate.min((1. - mapped_luma) / (max - mapped_luma));
+                    }
+                    rgb = rgb.map(|c| mapped_luma + (c - mapped_luma) * desaturate);
+                    let [r, g, b] =
+                        rgb.map(|c| encode[(c.clamp(0., 1.) * (ENCODE_STEPS - 1) as f32 + 0.5) as usize]);
+                    *pixel = Color32::from_rgb(r, g, b);
+                }
+            }
+        };
+        // like the 3D LUT, this is too slow for playback on one core
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let rows_per_thread = height.div_ceil(threads).max(1);
+        let map_rows = &map_rows;
+        std::thread::scope(|scope| {
+            for (index, rows) in pixels.chunks_mut(rows_per_thread * width.max(1)).enumerate() {
+                scope.spawn(move || map_rows(index * rows_per_thread, rows));
+            }
+        });
+        Ok(ColorImage {
+            size: [width, height],
+            source_size: Vec2::new(width as f32, height as f32),
+            pixels,
+        })
+    }
+
+    /// Convert `frame` to 16 bit RGB, with the YUV matrix and range the frame says it uses.
+    fn to_rgb48(&mut self, frame: &Video) -> Result<Video> {
+        let colorspace = match frame.color_space() {
+            Space::BT2020NCL | Space::BT2020CL => ffmpeg::ffi::SWS_CS_BT2020,
+            _ => ffmpeg::ffi::SWS_CS_ITU709,
+        } as i32;
+        let full_range = frame.color_range() == Range::JPEG;
+        let conversion = (frame.format(), frame.width(), frame.height(), colorspace, full_range);
+        if self.scaler.as_ref().is_none_or(|(_, made_for)| *made_for != conversion) {
+            let mut scaler = ScaleContext::get(
+                frame.format(),
+                frame.width(),
+                frame.height(),
+                Pixel::RGB48LE,
+                frame.width(),
+                frame.height(),
+                Flags::BILINEAR,
+            )?;
+            unsafe {
+                let coefficients = ffmpeg::ffi::sws_getCoefficients(colorspace);
+                ffmpeg::ffi::sws_setColorspaceDetails(
+                    scaler.as_mut_ptr(),
+                    coefficients,
+                    full_range as i32,
+                    coefficients,
+                    1,
+                    0,
+                    1 << 16,
+                    1 << 16,
+                );
+            }
+            self.scaler = Some((scaler, conversion));
+        }
+        let (scaler, _) = self.scaler.as_mut().unwrap();
+        let mut rgb = Video::empty();
+        scaler.run(frame, &mut rgb)?;
+        Ok(rgb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pq_round_trip() {
+        for nits in [0., 0.1, 1., 100., REFERENCE_WHITE_NITS, 1000., 4000., 10000.] {
+            let decoded = pq_decode(pq_encode(nits));
+            assert!((decoded - nits).abs() <= nits * 1e-3 + 1e-3, "{nits} -> {decoded}");
+        }
+        // the reference points of ST 2084
+        assert!((pq_encode(10000.) - 1.).abs() < 1e-6);
+        assert!((pq_encode(100.) - 0.508).abs() < 1e-3);
+    }
+
+    #[test]
+    fn peak_maps_to_white() {
+        let peak = DEFAULT_PEAK_NITS / REFERENCE_WHITE_NITS;
+        for operator in ToneMapOperator::ALL {
+            let mapped = operator.map(peak, peak);
+            assert!((mapped - 1.).abs() < 1e-3, "{} maps the peak to {mapped}", operator.name());
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use egui::{ColorImage, Pos2, Rect, Vec2};
+use std::time::{Duration, Instant};
+
+/// How the frame is scaled into the space it is drawn in, before zooming.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FitMode {
+    /// As large as possible while showing the whole frame.
+    Fit,
+    /// As small as possible while covering the whole space, cutting off the edges of the frame.
+    Fill,
+    /// One pixel of the frame per physical pixel of the screen.
+    Original,
+    /// Exactly the space, ignoring the aspect ratio.
+    Stretch,
+}
+
+impl FitMode {
+    pub const ALL: [FitMode; 4] = [FitMode::Fit, FitMode::Fill, FitMode::Original, FitMode::Stretch];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FitMode::Fit => "fit",
+            FitMode::Fill => "fill",
+            FitMode::Original => "1:1",
+            FitMode::Stretch => "stretch",
+        }
+    }
+}
+
+/// The shape the frame is shown with.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AspectRatio {
+    /// The shape of the (cropped) frame.
+    Source,
+    FourThree,
+    SixteenNine,
+    /// Cinemascope.
+    Scope,
+}
+
+impl AspectRatio {
+    pub const ALL: [AspectRatio; 4] = [
+        AspectRatio::Source,
+        AspectRatio::FourThree,
+        AspectRatio::SixteenNine,
+        AspectRatio::Scope,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AspectRatio::Source => "source",
+            AspectRatio::FourThree => "4:3",
+            AspectRatio::SixteenNine => "16:9",
+            AspectRatio::Scope => "2.35:1",
+        }
+    }
+
+    /// Width over height, `None` for the source's.
+    fn ratio(&self) -> Option<f32> {
+        match self {
+            AspectRatio::Source => None,
+            AspectRatio::FourThree => Some(4. / 3.),
+            AspectRatio::SixteenNine => Some(16. / 9.),
+            AspectRatio::Scope => Some(2.35),
+        }
+    }
+}
+
+/// Rows and columns darker than this (out of 255) count as black bars, like cropdetect's default limit.
+const CROP_LIMIT: u8 = 24;
+/// How often the black bars are looked for while [`Viewport::auto_crop`] is on.
+const CROP_DETECT_INTERVAL: Duration = Duration::from_millis(500);
+/// The smallest zoom and the largest, relative to the fitted size.
+const ZOOM_RANGE: std::ops::RangeInclusive<f32> = 0.1..=50.;
+
+/// Where the frame is drawn and which part of it is visible: fit mode, aspect ratio, cropping, zoom and pan.
+#[derive(Clone)]
+pub struct Viewport {
+    pub fit: FitMode,
+    pub aspect: AspectRatio,
+    /// Cut off black bars found in the frame.
+    pub auto_crop: bool,
+    zoom: f32,
+    // the offset of the center of the frame from the center of the space, in points
+    pan: Vec2,
+    // the part of the frame without black bars, in texture coordinates
+    detected_crop: Option<Rect>,
+    last_crop_detection: Option<Instant>,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            fit: FitMode::Fit,
+            aspect: AspectRatio::Source,
+            auto_crop: false,
+            zoom: 1.,
+            pan: Vec2::ZERO,
+            detected_crop: None,
+            last_crop_detection: None,
+        }
+    }
+}
+
+/// Where a frame ends up on screen.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewportLayout {
+    /// Where the whole (cropped) frame would be drawn; can extend past the space when zoomed in or filling.
+    pub content: Rect,
+    /// The part of `content` that is inside the space.
+    pub visible: Rect,
+    /// The part of the texture drawn in `visible`.
+    pub uv: Rect,
+    // the part of the texture drawn in `content`
+    crop: Rect,
+}
+
+impl ViewportLayout {
+    /// The texture coordinates at `pos`, if the frame is drawn there.
+    pub fn uv_at(&self, pos: Pos2) -> Option<Pos2> {
+        if !self.visible.contains(pos) || self.content.area() <= 0. {
+            return None;
+        }
+        Some(self.texture_coordinates(pos))
+    }
+
+    /// The same layout, only showing what is inside `rect`.
+    pub fn clipped(&self, rect: Rect) -> Self {
+        let visible = self.visible.intersect(rect);
+        let uv = if visible.is_positive() {
+            Rect::from_min_max(self.texture_coordinates(visible.min), self.texture_coordinates(visible.max))
+        } else {
+            Rect::NOTHING
+        };
+        Self { visible, uv, ..*self }
+    }
+
+    fn texture_coordinates(&self, pos: Pos2) -> Pos2 {
+        let t = (pos - self.content.min) / self.content.size();
+        self.crop.min + t * self.crop.size()
+    }
+}
+
+impl Viewport {
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Back to the whole frame, centered.
+    pub fn reset_zoom(&mut self) {
+        self.zoom = 1.;
+        self.pan = Vec2::ZERO;
+    }
+
+    /// The part of the texture that is shown at all, after cropping.
+    pub fn crop(&self) -> Rect {
+        match (self.auto_crop, self.detected_crop) {
+            (true, Some(crop)) => crop,
+            _ => Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.)),
+        }
+    }
+
+    /// Lay out a texture of `texture_size` pixels in `space`.
+    pub fn layout(&self, space: Rect, texture_size: Vec2, pixels_per_point: f32) -> ViewportLayout {
+        let crop = self.crop();
+        let cropped = texture_size * crop.size();
+        let height = cropped.y.max(1.);
+        let frame_size = match self.aspect.ratio() {
+            Some(ratio) => Vec2::new(height * ratio, height),
+            None => Vec2::new(cropped.x.max(1.), height),
+        };
+        let fitted = match self.fit {
+            FitMode::Fit => frame_size * (space.width() / frame_size.x).min(space.height() / frame_size.y),
+            FitMode::Fill => frame_size * (space.width() / frame_size.x).max(space.height() / frame_size.y),
+            FitMode::Original => frame_size / pixels_per_point,
+            FitMode::Stretch => space.size(),
+        };
+        let content = Rect::from_center_size(space.center() + self.pan, fitted * self.zoom);
+        let whole = ViewportLayout {
+            content,
+            visible: content,
+            uv: crop,
+            crop,
+        };
+        whole.clipped(space)
+    }
+
+    /// Zoom by `factor`, keeping the point of the frame under `anchor` where it is. `space` is where the
+    /// frame is laid out.
+    pub fn zoom_at(&mut self, space: Rect, anchor: Pos2, factor: f32) {
+        let zoom = (self.zoom * factor).clamp(*ZOOM_RANGE.start(), *ZOOM_RANGE.end());
+        let factor = zoom / self.zoom;
+        let center = space.center() + self.pan;
+        let new_center = anchor + (center - anchor) * factor;
+        self.pan = new_center - space.center();
+        self.zoom = zoom;
+    }
+
+    /// Move the frame by `delta` points.
+    pub fn pan_by(&mut self, delta: Vec2) {
+        self.pan += delta;
+    }
+
+    /// Look for black bars in `frame` if it is time to, widening the crop to include anything that isn't.
+    /// Only ever widening keeps dark scenes and fades from cropping into the picture.
+    pub(crate) fn detect_crop(&mut self, frame: &ColorImage) {
+        if !self.auto_crop {
+            self.detected_crop = None;
+            self.last_crop_detection = None;
+            return;
+        }
+        if self.last_crop_detection.is_some_and(|last| last.elapsed() < CROP_DETECT_INTERVAL) {
+            return;
+        }
+        self.last_crop_detection = Some(Instant::now());
+        let Some(found) = find_picture(frame) else {
+            return;
+        };
+        self.detected_crop = Some(match self.detected_crop {
+            Some(crop) => crop.union(found),
+            None => found,
+        });
+    }
+}
+
+/// The part of `frame` inside its black bars, in texture coordinates. `None` if the frame is all black.
+fn find_picture(frame: &ColorImage) -> Option<Rect> {
+    let [width, height] = frame.size;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let brightest = |pixels: &mut dyn Iterator<Item = usize>| {
+        pixels
+            .map(|index| {
+                let [r, g, b, _] = frame.pixels[index].to_array();
+                r.max(g).max(b)
+            })
+            .max()
+            .unwrap_or(0)
+    };
+    let is_bar_row = |y: usize| brightest(&mut (0..width).map(|x| y * width + x)) <= CROP_LIMIT;
+    let is_bar_column = |x: usize| brightest(&mut (0..height).map(|y| y * width + x)) <= CROP_LIMIT;
+    let top = (0..height).find(|y| !is_bar_row(*y))?;
+    let bottom = (0..height).rev().find(|y| !is_bar_row(*y))? + 1;
+    let left = (0..width).find(|x| !is_bar_column(*x))?;
+    let right = (0..width).rev().find(|x| !is_bar_column(*x))? + 1;
+    // round inwards to even pixels, like cropdetect
+    let (top, left) = (top.next_multiple_of(2), left.next_multiple_of(2));
+    if top >= bottom || left >= right {
+        return None;
+    }
+    Some(Rect::from_min_max(
+        Pos2::new(left as f32 / width as f32, top as f32 / height as f32),
+        Pos2::new(right as f32 / width as f32, bottom as f32 / height as f32),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::Color32;
+
+    /// A `width`x`height` black frame with a gray picture in `columns` and `rows`.
+    fn letterboxed(
+        width: usize,
+        height: usize,
+        columns: std::ops::Range<usize>,
+        rows: std::ops::Range<usize>,
+    ) -> ColorImage {
+        let mut frame = ColorImage::from_rgb([width, height], &vec![0; width * height * 3]);
+        for y in rows {
+            for x in columns.clone() {
+                frame.pixels[y * width + x] = Color32::from_gray(128);
+            }
+        }
+        frame
+    }
+
+    fn uv_rect(left: f32, top: f32, right: f32, bottom: f32) -> Rect {
+        Rect::from_min_max(Pos2::new(left, top), Pos2::new(right, bottom))
+    }
+
+    #[test]
+    fn finds_letterbox_and_pillarbox() {
+        let frame = letterboxed(40, 20, 4..36, 2..18);
+        assert_eq!(find_picture(&frame), Some(uv_rect(0.1, 0.1, 0.9, 0.9)));
+    }
+
+    #[test]
+    fn whole_frame_without_bars() {
+        let frame = letterboxed(8, 4, 0..8, 0..4);
+        assert_eq!(find_picture(&frame), Some(uv_rect(0., 0., 1., 1.)));
+    }
+
+    #[test]
+    fn rounds_top_left_inwards_to_even() {
+        let frame = letterboxed(40, 20, 3..36, 1..18);
+        assert_eq!(find_picture(&frame), Some(uv_rect(0.1, 0.1, 0.9, 0.9)));
+    }
+
+    #[test]
+    fn dark_noise_counts_as_bars() {
+        let mut frame = letterboxed(40, 20, 4..36, 2..18);
+        frame.pixels[0] = Color32::from_gray(CROP_LIMIT);
+        assert_eq!(find_picture(&frame), Some(uv_rect(0.1, 0.1, 0.9, 0.9)));
+        // anything brighter is picture
+        frame.pixels[0] = Color32::from_gray(CROP_LIMIT + 1);
+        assert_eq!(find_picture(&frame), Some(uv_rect(0., 0., 0.9, 0.9)));
+    }
+
+    #[test]
+    fn black_or_empty_frames_have_no_picture() {
+        assert_eq!(find_picture(&letterboxed(8, 4, 0..0, 0..0)), None);
+        assert_eq!(find_picture(&ColorImage::from_rgb([0, 0], &[])), None);
+    }
+}
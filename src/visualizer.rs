@@ -0,0 +1,297 @@
+use egui::{pos2, Color32, CornerRadius, Painter, Pos2, Rect, Stroke};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// How many frames of audio an [`AudioTap`] keeps around.
+const TAP_CAPACITY_FRAMES: usize = 8192;
+/// The number of samples analyzed per spectrum. Must be a power of two.
+const FFT_SIZE: usize = 2048;
+/// The number of bars the spectrum is drawn with.
+const SPECTRUM_BANDS: usize = 64;
+/// The quietest level the spectrum shows, in dBFS.
+const SPECTRUM_FLOOR_DB: f32 = -90.;
+/// The lowest frequency shown on a logarithmic scale.
+const LOG_SCALE_MIN_HZ: f32 = 20.;
+/// How fast held peaks fall, in spectrum heights per second.
+const PEAK_FALL_PER_SEC: f32 = 0.35;
+/// The number of frames shown by the oscilloscope.
+const WAVEFORM_FRAMES: usize = 1024;
+
+/// The most recently decoded audio samples, shared between the audio streamer and visualizations.
+#[derive(Clone)]
+pub struct AudioTap {
+    // interleaved samples
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    // the newest frames that are still waiting in the device's buffer, and aren't shown yet
+    unplayed_frames: Arc<AtomicUsize>,
+    channels: usize,
+    sample_rate: u32,
+}
+
+impl AudioTap {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(TAP_CAPACITY_FRAMES * channels))),
+            unplayed_frames: Arc::new(AtomicUsize::new(0)),
+            channels: channels.max(1),
+            sample_rate,
+        }
+    }
+
+    /// Append interleaved samples, dropping the oldest ones.
+    pub(crate) fn push(&self, interleaved: &[f32]) {
+        let mut samples = self.samples.lock().unwrap();
+        samples.extend(interleaved);
+        let capacity = (TAP_CAPACITY_FRAMES + self.unplayed_frames.load(Ordering::Relaxed)) * self.channels;
+        if samples.len() > capacity {
+            let excess = samples.len() - capacity;
+            samples.drain(..excess);
+        }
+    }
+
+    /// Forget all samples, e.g. after seeking.
+    pub(crate) fn clear(&self) {
+        self.samples.lock().unwrap().clear();
+        self.unplayed_frames.store(0, Ordering::Relaxed);
+    }
+
+    /// How many of the newest frames haven't been played yet. They are left out until they are.
+    pub(crate) fn set_unplayed_frames(&self, frames: usize) {
+        self.unplayed_frames.store(frames, Ordering::Relaxed);
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The last `frames` frames played, mixed down to mono. Shorter if fewer have been decoded.
+    pub fn latest_mono(&self, frames: usize) -> Vec<f32> {
+        let samples = self.samples.lock().unwrap();
+        let played = (samples.len() / self.channels).saturating_sub(self.unplayed_frames.load(Ordering::Relaxed));
+        let skip = played.saturating_sub(frames) * self.channels;
+        samples
+            .iter()
+            .take(played * self.channels)
+            .skip(skip)
+            .copied()
+            .collect::<Vec<_>>()
+            .chunks_exact(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / self.channels as f32)
+            .collect()
+    }
+}
+
+/// What a [`Visualizer`] draws.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VisualizerMode {
+    /// The level of each frequency band.
+    Spectrum,
+    /// The waveform itself, like an oscilloscope.
+    Waveform,
+}
+
+impl VisualizerMode {
+    pub const ALL: [VisualizerMode; 2] = [VisualizerMode::Spectrum, VisualizerMode::Waveform];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VisualizerMode::Spectrum => "spectrum",
+            VisualizerMode::Waveform => "waveform",
+        }
+    }
+}
+
+/// How frequencies are spread over the width of the spectrum.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrequencyScale {
+    Linear,
+    /// Every octave gets the same width, which is closer to how pitch is heard.
+    Log,
+}
+
+/// Configures a [`Visualizer`].
+#[derive(Clone, Copy, Debug)]
+pub struct VisualizerOptions {
+    pub mode: VisualizerMode,
+    pub frequency_scale: FrequencyScale,
+    /// Mark the highest recent level of each spectrum band, slowly falling back down.
+    pub peak_hold: bool,
+    /// Draw over the bottom of the video as well, not only for audio-only files.
+    pub overlay: bool,
+}
+
+impl Default for VisualizerOptions {
+    fn default() -> Self {
+        Self {
+            mode: VisualizerMode::Spectrum,
+            frequency_scale: FrequencyScale::Log,
+            peak_hold: true,
+            overlay: false,
+        }
+    }
+}
+
+/// Draws the audio of an [`AudioTap`] as a spectrum or waveform.
+#[derive(Default)]
+pub struct Visualizer {
+    pub options: VisualizerOptions,
+    // held level of each spectrum band, as a fraction of the spectrum's height
+    peaks: Vec<f32>,
+    last_drawn: Option<Instant>,
+}
+
+impl Visualizer {
+    pub fn new(options: VisualizerOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Draw the most recent audio of `tap` filling `rect`, on top of `background`.
+    pub fn draw(&mut self, painter: &Painter, rect: Rect, tap: &AudioTap, background: Color32) {
+        painter.rect_filled(rect, CornerRadius::ZERO, background);
+        match self.options.mode {
+            VisualizerMode::Spectrum => self.draw_spectrum(painter, rect, tap),
+            VisualizerMode::Waveform => draw_waveform(painter, rect, tap),
+        }
+    }
+
+    fn draw_spectrum(&mut self, painter: &Painter, rect: Rect, tap: &AudioTap) {
+        let levels = band_levels(&tap.latest_mono(FFT_SIZE), tap.sample_rate(), self.options.frequency_scale);
+
+        let now = Instant::now();
+        let elapsed_secs = self
+            .last_drawn
+            .map(|last_drawn| now.duration_since(last_drawn).as_secs_f32())
+            .unwrap_or(0.);
+        self.last_drawn = Some(now);
+        self.peaks.resize(SPECTRUM_BANDS, 0.);
+        for (peak, level) in self.peaks.iter_mut().zip(levels.iter()) {
+            *peak = level.max(*peak - PEAK_FALL_PER_SEC * elapsed_secs);
+        }
+
+        let bar_width = rect.width() / SPECTRUM_BANDS as f32;
+        for (i, level) in levels.iter().enumerate() {
+            let x = rect.left() + bar_width * i as f32;
+            let bar = Rect::from_min_max(
+                pos2(x + bar_width * 0.1, rect.bottom() - level * rect.height()),
+                pos2(x + bar_width * 0.9, rect.bottom()),
+            );
+            painter.rect_filled(bar, CornerRadius::ZERO, Color32::from_rgb(90, 170, 255));
+            if self.options.peak_hold {
+                let y = rect.bottom() - self.peaks[i] * rect.height();
+                painter.line_segment(
+                    [pos2(bar.left(), y), pos2(bar.right(), y)],
+                    Stroke::new(2., Color32::WHITE),
+                );
+            }
+        }
+    }
+}
+
+fn draw_waveform(painter: &Painter, rect: Rect, tap: &AudioTap) {
+    let samples = tap.latest_mono(WAVEFORM_FRAMES * 2);
+    painter.line_segment(
+        [pos2(rect.left(), rect.center().y), pos2(rect.right(), rect.center().y)],
+        Stroke::new(1., Color32::from_gray(60)),
+    );
+    if samples.len() < WAVEFORM_FRAMES * 2 {
+        return;
+    }
+    // start at a rising zero crossing so periodic signals stand still
+    let start = (1..WAVEFORM_FRAMES)
+        .find(|&i| samples[i - 1] <= 0. && samples[i] > 0.)
+        .unwrap_or(0);
+    let window = &samples[start..start + WAVEFORM_FRAMES];
+    let points: Vec<Pos2> = window
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            pos2(
+                rect.left() + rect.width() * i as f32 / (WAVEFORM_FRAMES - 1) as f32,
+                rect.center().y - sample.clamp(-1., 1.) * rect.height() * 0.45,
+            )
+        })
+        .collect();
+    painter.line(points, Stroke::new(1.5, Color32::from_rgb(90, 170, 255)));
+}
+
+/// The level of each spectrum band of `samples`, as a fraction of full scale on a dB scale.
+fn band_levels(samples: &[f32], sample_rate: u32, scale: FrequencyScale) -> Vec<f32> {
+    let mut re = vec![0.; FFT_SIZE];
+    let mut im = vec![0.; FFT_SIZE];
+    // hann window, the samples end up at the end so the newest audio is always analyzed
+    let offset = FFT_SIZE - samples.len().min(FFT_SIZE);
+    for (i, sample) in samples.iter().take(FFT_SIZE).enumerate() {
+        let n = offset + i;
+        let window = 0.5 - 0.5 * (2. * PI * n as f32 / (FFT_SIZE - 1) as f32).cos();
+        re[n] = sample * window;
+    }
+    fft(&mut re, &mut im);
+    // a full scale sine peaks at FFT_SIZE / 4 after the hann window
+    let magnitudes: Vec<f32> = (0..FFT_SIZE / 2)
+        .map(|bin| (re[bin] * re[bin] + im[bin] * im[bin]).sqrt() / (FFT_SIZE as f32 / 4.))
+        .collect();
+
+    let nyquist = sample_rate as f32 / 2.;
+    let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+    let band_edge_hz = |band: usize| {
+        let frac = band as f32 / SPECTRUM_BANDS as f32;
+        match scale {
+            FrequencyScale::Linear => nyquist * frac,
+            FrequencyScale::Log => LOG_SCALE_MIN_HZ * (nyquist / LOG_SCALE_MIN_HZ).powf(frac),
+        }
+    };
+    (0..SPECTRUM_BANDS)
+        .map(|band| {
+            let first_bin = ((band_edge_hz(band) / bin_hz) as usize).clamp(1, magnitudes.len() - 1);
+            let last_bin = ((band_edge_hz(band + 1) / bin_hz) as usize).clamp(first_bin + 1, magnitudes.len());
+            let magnitude = magnitudes[first_bin..last_bin].iter().copied().fold(0., f32::max);
+            let db = 20. * magnitude.max(1e-9).log10();
+            ((db - SPECTRUM_FLOOR_DB) / -SPECTRUM_FLOOR_DB).clamp(0., 1.)
+        })
+        .collect()
+}
+
+/// In-place iterative radix-2 FFT. The length of `re` and `im` must be the same power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2. * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}